@@ -3,12 +3,13 @@ use arc_swap::ArcSwap;
 use deadqueue::unlimited::Queue;
 use futures::{future, try_join, StreamExt, TryStreamExt};
 use im::HashMap;
+use log::error;
 use once_cell::sync::Lazy;
 use stack_string::{format_sstr, StackString};
-use std::{collections::VecDeque, sync::Arc};
+use std::{collections::HashSet, sync::Arc};
 use telegram_bot::{
-    types::Update, Api, CanReplySendMessage, CanSendMessage, ChatId, ChatRef, MessageKind,
-    ToChatRef, UpdateKind, UserId,
+    types::Update, Api, CanReplySendMessage, CanSendDocument, CanSendMessage, CanSendPhoto, ChatId,
+    ChatRef, InputFileUpload, MessageKind, ToChatRef, UpdateKind, UserId,
 };
 use time::{Duration, OffsetDateTime};
 use tokio::{
@@ -17,7 +18,15 @@ use tokio::{
 };
 
 use calendar_app_lib::{
-    calendar_sync::CalendarSync, config::Config, models::AuthorizedUsers, pgpool::PgPool,
+    attachments::AttachmentStore,
+    calendar::Event,
+    calendar_sync::CalendarSync,
+    config::Config,
+    models::{AuthorizedUsers, EventAttachment},
+    notification_sim::{agenda_time, REMINDER_MINUTES},
+    pgpool::PgPool,
+    shutdown::Shutdown,
+    tickets::{ticket_content, TicketContent},
 };
 
 use crate::failure_count::FailureCount;
@@ -27,12 +36,21 @@ type UserIds = ArcSwap<HashMap<UserId, Option<ChatId>>>;
 static TELEGRAM_USERIDS: Lazy<UserIds> = Lazy::new(|| ArcSwap::new(Arc::new(HashMap::new())));
 static FAILURE_COUNT: Lazy<FailureCount> = Lazy::new(|| FailureCount::new(5));
 
+enum BotMessage {
+    Text(StackString),
+    Ticket {
+        caption: StackString,
+        content: TicketContent,
+    },
+}
+
 #[derive(Clone)]
 pub struct TelegramBot {
     api: Arc<Api>,
     pool: PgPool,
     cal_sync: Arc<CalendarSync>,
-    queue: Arc<Queue<(ChatId, StackString)>>,
+    attachments: AttachmentStore,
+    queue: Arc<Queue<(ChatId, BotMessage)>>,
 }
 
 impl TelegramBot {
@@ -41,15 +59,21 @@ impl TelegramBot {
             api: Arc::new(Api::new(bot_token)),
             pool: pool.clone(),
             cal_sync: Arc::new(CalendarSync::new(config.clone(), pool.clone()).await),
+            attachments: AttachmentStore::new(config).await,
             queue: Arc::new(Queue::new()),
         }
     }
 
-    pub async fn run(&self) -> Result<(), Error> {
+    /// Run until `shutdown` is triggered, if any loop fails the others are
+    /// stopped and shutdown is triggered so the binary exits
+    pub async fn run(&self, shutdown: &Shutdown) -> Result<(), Error> {
         let fill_task = self.fill_telegram_user_ids();
         let notification_task = self.notification_handler();
         let bot_task = self.telegram_worker();
-        try_join!(fill_task, notification_task, bot_task).map(|_| ())
+        let tasks = async { try_join!(fill_task, notification_task, bot_task).map(|_| ()) };
+        let result = shutdown.run_until(tasks).await.unwrap_or(Ok(()));
+        shutdown.trigger();
+        result
     }
 
     pub async fn telegram_worker(&self) -> Result<(), Error> {
@@ -75,7 +99,21 @@ impl TelegramBot {
                     self.process_update(update).await
                 },
                 (chat, msg) = self.queue.pop() => {
-                    self.api.spawn(chat.text(msg.as_str()));
+                    match msg {
+                        BotMessage::Text(msg) => self.api.spawn(chat.text(msg.as_str())),
+                        BotMessage::Ticket { caption, content } => {
+                            let upload = InputFileUpload::with_data(
+                                content.data,
+                                content.filename.as_str(),
+                            );
+                            if content.is_image {
+                                self.api.spawn(chat.photo(upload).caption(caption.as_str()));
+                            } else {
+                                self.api
+                                    .spawn(chat.document(upload).caption(caption.as_str()));
+                            }
+                        }
+                    }
                     Ok(())
                 },
                 else => break,
@@ -131,56 +169,92 @@ impl TelegramBot {
         Ok(())
     }
 
+    /// Sends every chat the daily agenda at `AGENDA_HOUR` and a reminder
+    /// (with any tickets) `REMINDER_MINUTES` before each event.  Reminders use
+    /// the agenda as it is now, so events added or moved since the daily
+    /// agenda went out are reminded about too.
     pub async fn notification_handler(&self) -> Result<(), Error> {
         let now = OffsetDateTime::now_utc();
-        let mut events: VecDeque<_> = self.cal_sync.list_agenda(0, 1).await?.into();
-        let mut agenda_datetime = now.date().with_hms(12, 0, 0)?.assume_utc();
+        let mut agenda_datetime = agenda_time(now.date());
+        let mut reminded: HashSet<(StackString, StackString)> = HashSet::new();
         loop {
             FAILURE_COUNT.check()?;
             let now = OffsetDateTime::now_utc();
-            for chat_id in TELEGRAM_USERIDS.load().values().flatten() {
-                if now > agenda_datetime {
-                    agenda_datetime += Duration::days(1);
-                    events = self.cal_sync.list_agenda(0, 1).await?.into();
-                    for event in &events {
-                        self.send_message(
-                            *chat_id,
-                            &event
-                                .get_summary(
-                                    &self.cal_sync.config.domain,
-                                    &self.pool,
-                                    &self.cal_sync.config,
-                                )
-                                .await,
-                        )?;
+            let events = self.cal_sync.list_agenda(0, 1).await?;
+            let chat_ids: Vec<ChatId> = TELEGRAM_USERIDS
+                .load()
+                .values()
+                .flatten()
+                .copied()
+                .collect();
+            if now > agenda_datetime {
+                agenda_datetime += Duration::days(1);
+                for event in &events {
+                    let summary = event
+                        .get_summary(
+                            &self.cal_sync.config.domain,
+                            &self.pool,
+                            &self.cal_sync.config,
+                        )
+                        .await;
+                    for chat_id in &chat_ids {
+                        self.send_message(*chat_id, &summary)?;
                     }
-                } else {
-                    while let Some(event) = events.front() {
-                        let start_time: OffsetDateTime = event.start_time.into();
-                        if now > start_time - Duration::minutes(5) {
-                            self.send_message(
-                                *chat_id,
-                                &event
-                                    .get_summary(
-                                        &self.cal_sync.config.domain,
-                                        &self.pool,
-                                        &self.cal_sync.config,
-                                    )
-                                    .await,
-                            )?;
-                            events.pop_front();
-                        } else {
-                            break;
-                        }
+                }
+            }
+            for event in &events {
+                let start_time: OffsetDateTime = event.start_time.into();
+                let key = (event.gcal_id.clone(), event.event_id.clone());
+                if now <= start_time - Duration::minutes(REMINDER_MINUTES)
+                    || reminded.contains(&key)
+                {
+                    continue;
+                }
+                let summary = event
+                    .get_summary(
+                        &self.cal_sync.config.domain,
+                        &self.pool,
+                        &self.cal_sync.config,
+                    )
+                    .await;
+                for chat_id in &chat_ids {
+                    self.send_message(*chat_id, &summary)?;
+                    // A broken attachment shouldn't stop the reminder loop
+                    if let Err(e) = self.send_tickets(*chat_id, event).await {
+                        error!(
+                            "failed to send tickets for {} {}: {e}",
+                            event.gcal_id, event.event_id
+                        );
                     }
                 }
+                reminded.insert(key);
             }
+            // Finished events drop out of the agenda, no need to remember them
+            reminded.retain(|(gcal_id, event_id)| {
+                events
+                    .iter()
+                    .any(|e| &e.gcal_id == gcal_id && &e.event_id == event_id)
+            });
             sleep(std::time::Duration::from_secs(60)).await;
         }
     }
 
     pub fn send_message(&self, chat: ChatId, msg: &str) -> Result<(), Error> {
-        self.queue.push((chat, msg.into()));
+        self.queue.push((chat, BotMessage::Text(msg.into())));
+        Ok(())
+    }
+
+    /// Send any ticket attachments of `event` so they're at hand at the venue
+    async fn send_tickets(&self, chat: ChatId, event: &Event) -> Result<(), Error> {
+        let attachments =
+            EventAttachment::get_by_gcal_id_event_id(&event.gcal_id, &event.event_id, &self.pool)
+                .await?;
+        for attachment in attachments.iter().filter(|a| a.is_ticket) {
+            let content = ticket_content(attachment, &self.attachments).await?;
+            let caption = format_sstr!("{} ticket: {}", event.name, attachment.filename);
+            self.queue
+                .push((chat, BotMessage::Ticket { caption, content }));
+        }
         Ok(())
     }
 
@@ -0,0 +1,161 @@
+use parking_lot::{const_rwlock, RwLock};
+use rweb::{Filter, Rejection};
+use stack_string::{format_sstr, StackString};
+use time::OffsetDateTime;
+
+use calendar_app_lib::{
+    models::{hash_token, AccessToken},
+    pgpool::PgPool,
+};
+
+use crate::{errors::ServiceError as Error, logged_user::LoggedUser};
+
+static API_KEYS: RwLock<Vec<AccessToken>> = const_rwlock(Vec::new());
+
+/// # Errors
+/// Return error if `get_api_keys` fails
+pub async fn fill_api_keys(pool: &PgPool) -> Result<(), Error> {
+    let api_keys = AccessToken::get_api_keys(pool).await?;
+    *API_KEYS.write() = api_keys;
+    Ok(())
+}
+
+fn find_api_key(keys: &[AccessToken], key: &str, now: OffsetDateTime) -> Option<AccessToken> {
+    let key_hash = hash_token(key);
+    keys.iter()
+        .find(|k| k.accepts_hash(&key_hash, now))
+        .cloned()
+}
+
+/// Caller of the json api, either a logged in user or an api key sent as
+/// `Authorization: Bearer <key>`.
+#[derive(Debug, Clone)]
+pub enum ApiCaller {
+    User(LoggedUser),
+    ApiKey(AccessToken),
+}
+
+impl ApiCaller {
+    #[must_use]
+    pub fn filter() -> impl Filter<Extract = (Self,), Error = Rejection> + Copy {
+        let api_key = rweb::header::<StackString>("authorization").and_then(
+            |header: StackString| async move {
+                header
+                    .strip_prefix("Bearer ")
+                    .and_then(|key| {
+                        find_api_key(&API_KEYS.read(), key.trim(), OffsetDateTime::now_utc())
+                    })
+                    .map(Self::ApiKey)
+                    .ok_or_else(|| rweb::reject::custom(Error::Unauthorized))
+            },
+        );
+        LoggedUser::filter().map(Self::User).or(api_key).unify()
+    }
+
+    #[must_use]
+    pub fn allows_calendar(&self, gcal_id: &str) -> bool {
+        match self {
+            Self::User(_) => true,
+            Self::ApiKey(key) => key.allows_calendar(gcal_id),
+        }
+    }
+
+    /// Calendar a scoped api key is limited to, for filtering in the query
+    /// itself (so pagination and totals only count visible rows)
+    #[must_use]
+    pub fn calendar_scope(&self) -> Option<&StackString> {
+        match self {
+            Self::User(_) => None,
+            Self::ApiKey(key) => key.gcal_id.as_ref(),
+        }
+    }
+
+    /// # Errors
+    /// Returns error if the caller is a read-only api key or any of
+    /// `gcal_ids` is out of scope
+    pub fn check_write<'a>(
+        &self,
+        mut gcal_ids: impl Iterator<Item = &'a str>,
+    ) -> Result<(), Error> {
+        if let Self::ApiKey(key) = self {
+            if key.read_only {
+                return Err(Error::BadRequest("Read-only api key".into()));
+            }
+            if let Some(gcal_id) = gcal_ids.find(|g| !key.allows_calendar(g)) {
+                return Err(Error::BadRequest(format_sstr!(
+                    "Api key not valid for calendar {gcal_id}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Deployment-wide actions (e.g. syncing) need a logged in user or an
+    /// api key that is neither read-only nor scoped to one calendar
+    /// # Errors
+    /// Returns error if the caller is a read-only or scoped api key
+    pub fn check_admin(&self) -> Result<(), Error> {
+        if let Self::ApiKey(key) = self {
+            if key.read_only {
+                return Err(Error::BadRequest("Read-only api key".into()));
+            }
+            if key.gcal_id.is_some() {
+                return Err(Error::BadRequest(
+                    "Api key is scoped to a single calendar".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use calendar_app_lib::models::{hash_token, AccessToken};
+
+    use crate::api_key::{find_api_key, ApiCaller};
+
+    #[test]
+    fn test_api_key_scope_and_rotation() {
+        let now = OffsetDateTime::now_utc();
+        let (mut key, current) =
+            AccessToken::new_api_key("user@test", Some("cal_a".into()), true, None);
+        assert_eq!(key.token_hash, hash_token(&current));
+
+        let keys = vec![key.clone()];
+        assert!(find_api_key(&keys, &current, now).is_some());
+        assert!(find_api_key(&keys, "not a key", now).is_none());
+
+        let caller = ApiCaller::ApiKey(key.clone());
+        assert!(caller.allows_calendar("cal_a"));
+        assert!(!caller.allows_calendar("cal_b"));
+        assert!(caller.check_write(vec!["cal_a"].into_iter()).is_err());
+
+        key.read_only = false;
+        let caller = ApiCaller::ApiKey(key.clone());
+        assert!(caller.check_write(vec!["cal_a"].into_iter()).is_ok());
+        assert!(caller
+            .check_write(vec!["cal_a", "cal_b"].into_iter())
+            .is_err());
+        assert!(caller.check_admin().is_err());
+
+        let mut admin = key.clone();
+        admin.gcal_id = None;
+        assert!(ApiCaller::ApiKey(admin.clone()).check_admin().is_ok());
+        admin.read_only = true;
+        assert!(ApiCaller::ApiKey(admin).check_admin().is_err());
+
+        key.previous_token_hash = Some(key.token_hash.clone());
+        key.previous_expires_at = Some((now + Duration::hours(1)).into());
+        key.token_hash = hash_token("rotated");
+        assert!(key.accepts("rotated", now));
+        assert!(key.accepts(&current, now));
+        assert!(!key.accepts(&current, now + Duration::hours(2)));
+
+        key.expires_at = Some((now + Duration::days(1)).into());
+        assert!(key.accepts("rotated", now));
+        assert!(!key.accepts("rotated", now + Duration::days(2)));
+    }
+}
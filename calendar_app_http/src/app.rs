@@ -1,4 +1,5 @@
 use anyhow::Error;
+use futures::try_join;
 use rweb::{
     filters::BoxedFilter,
     http::header::CONTENT_TYPE,
@@ -9,25 +10,49 @@ use stack_string::{format_sstr, StackString};
 use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::{sync::RwLock, time::interval};
 
-use calendar_app_lib::{calendar_sync::CalendarSync, config::Config, pgpool::PgPool};
+use calendar_app_lib::{
+    attachments::AttachmentStore,
+    calendar_sync::CalendarSync,
+    config::Config,
+    pgpool::PgPool,
+    shutdown::{Shutdown, TaskTracker},
+    signed_url::UrlSigner,
+};
 
 use crate::{
+    api_key::fill_api_keys,
     errors::error_response,
-    logged_user::{fill_from_db, get_secrets},
+    logged_user::{fill_from_db, get_secrets, SECRET_KEY},
+    rate_limit::RateLimiter,
     routes::{
-        agenda, build_calendar_event, calendar_cache, calendar_cache_update, calendar_index,
-        calendar_list, calendar_list_update, create_calendar_event, delete_event, edit_calendar,
-        event_detail, link_shortener, list_calendars, list_events, sync_calendars,
-        sync_calendars_full, user,
+        agenda, agenda_json, attachment_download, build_calendar_event, calendar_cache,
+        calendar_cache_event, calendar_cache_update, calendar_feed, calendar_index, calendar_list,
+        calendar_list_update, calendar_stats, create_access_token, create_calendar_event,
+        create_inbound_source, delete_access_token, delete_attachment, delete_event,
+        delete_inbound_source, edit_calendar, event_detail, inbound_event,
+        integration_create_event, integration_me, integration_new_event,
+        integration_new_event_sample, integration_status, link_shortener, list_access_tokens,
+        list_attachments, list_calendars, list_events, list_inbound_sources, rotate_access_token,
+        scrape_health, share_link, shared_calendar, sync_calendars, sync_calendars_full,
+        upload_attachment, user,
     },
+    token_usage::TokenUsage,
 };
 
+/// How long background tasks get to stop once the server has shut down
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
 pub type UrlCache = RwLock<HashMap<StackString, StackString>>;
 
 #[derive(Clone)]
 pub struct AppState {
     pub cal_sync: CalendarSync,
     pub shortened_urls: Arc<UrlCache>,
+    pub attachments: AttachmentStore,
+    pub signer: UrlSigner,
+    pub public_limiter: Arc<RateLimiter>,
+    pub lookup_limiter: Arc<RateLimiter>,
+    pub token_usage: Arc<TokenUsage>,
 }
 
 /// # Errors
@@ -35,17 +60,25 @@ pub struct AppState {
 pub async fn start_app() -> Result<(), Error> {
     let config = Config::init_config()?;
     get_secrets(&config.secret_path, &config.jwt_secret_path).await?;
-    run_app(&config).await
+    let shutdown = Shutdown::new();
+    try_join!(
+        run_app(&config, shutdown.clone()),
+        shutdown.trigger_on_signal()
+    )?;
+    Ok(())
 }
 
 fn get_calendar_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
     let calendar_index_path = calendar_index().boxed();
     let agenda_path = agenda(app.clone()).boxed();
+    let agenda_json_path = agenda_json(app.clone()).boxed();
     let sync_calendars_path = sync_calendars(app.clone()).boxed();
 
     let sync_calendars_full_path = sync_calendars_full(app.clone()).boxed();
     let delete_event_path = delete_event(app.clone()).boxed();
     let list_calendars_path = list_calendars(app.clone()).boxed();
+    let scrape_health_path = scrape_health(app.clone()).boxed();
+    let calendar_stats_path = calendar_stats(app.clone()).boxed();
     let list_events_path = list_events(app.clone()).boxed();
     let event_detail_path = event_detail(app.clone()).boxed();
 
@@ -55,7 +88,11 @@ fn get_calendar_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
 
     let calendar_cache_get = calendar_cache(app.clone()).boxed();
     let calendar_cache_post = calendar_cache_update(app.clone()).boxed();
-    let calendar_cache_path = calendar_cache_get.or(calendar_cache_post).boxed();
+    let calendar_cache_event_path = calendar_cache_event(app.clone()).boxed();
+    let calendar_cache_path = calendar_cache_get
+        .or(calendar_cache_post)
+        .or(calendar_cache_event_path)
+        .boxed();
 
     let user_path = user().boxed();
 
@@ -69,12 +106,57 @@ fn get_calendar_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
 
     let edit_calendar_path = edit_calendar(app.clone()).boxed();
 
+    let list_attachments_path = list_attachments(app.clone()).boxed();
+    let upload_attachment_path = upload_attachment(app.clone()).boxed();
+    let delete_attachment_path = delete_attachment(app.clone()).boxed();
+    let attachments_path = list_attachments_path
+        .or(upload_attachment_path)
+        .or(delete_attachment_path)
+        .boxed();
+
+    let shared_calendar_path = shared_calendar(app.clone()).boxed();
+    let share_link_path = share_link(app.clone()).boxed();
+    let list_access_tokens_path = list_access_tokens(app.clone()).boxed();
+    let create_access_token_path = create_access_token(app.clone()).boxed();
+    let delete_access_token_path = delete_access_token(app.clone()).boxed();
+    let rotate_access_token_path = rotate_access_token(app.clone()).boxed();
+    let access_tokens_path = list_access_tokens_path
+        .or(create_access_token_path)
+        .or(delete_access_token_path)
+        .or(rotate_access_token_path)
+        .boxed();
+
+    let inbound_event_path = inbound_event(app.clone()).boxed();
+    let list_inbound_sources_path = list_inbound_sources(app.clone()).boxed();
+    let create_inbound_source_path = create_inbound_source(app.clone()).boxed();
+    let delete_inbound_source_path = delete_inbound_source(app.clone()).boxed();
+    let inbound_path = inbound_event_path
+        .or(list_inbound_sources_path)
+        .or(create_inbound_source_path)
+        .or(delete_inbound_source_path)
+        .boxed();
+
+    let integration_status_path = integration_status().boxed();
+    let integration_me_path = integration_me().boxed();
+    let integration_new_event_path = integration_new_event(app.clone()).boxed();
+    let integration_new_event_sample_path = integration_new_event_sample().boxed();
+    let integration_create_event_path = integration_create_event(app.clone()).boxed();
+    let integrations_path = integration_status_path
+        .or(integration_me_path)
+        .or(integration_new_event_sample_path)
+        .or(integration_new_event_path)
+        .or(integration_create_event_path)
+        .boxed();
+
     calendar_index_path
         .or(agenda_path)
+        .or(agenda_json_path)
         .or(sync_calendars_path)
         .or(sync_calendars_full_path)
         .or(delete_event_path)
         .or(list_calendars_path)
+        .or(scrape_health_path)
+        .or(calendar_stats_path)
         .or(list_events_path)
         .or(event_detail_path)
         .or(calendar_list_path)
@@ -83,26 +165,51 @@ fn get_calendar_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
         .or(link_path)
         .or(create_calendar_event_path)
         .or(edit_calendar_path)
+        .or(attachments_path)
+        .or(shared_calendar_path)
+        .or(share_link_path)
+        .or(access_tokens_path)
+        .or(inbound_path)
+        .or(integrations_path)
         .boxed()
 }
 
-async fn run_app(config: &Config) -> Result<(), Error> {
-    async fn update_db(pool: PgPool) {
+/// Serve until `shutdown` is triggered, then give background tasks
+/// `SHUTDOWN_GRACE` to stop
+async fn run_app(config: &Config, shutdown: Shutdown) -> Result<(), Error> {
+    async fn update_db(pool: PgPool) -> Result<(), Error> {
         let mut i = interval(Duration::from_secs(60));
         loop {
             fill_from_db(&pool).await.unwrap_or(());
+            fill_api_keys(&pool).await.unwrap_or(());
             i.tick().await;
         }
     }
     let pool = PgPool::new(&config.database_url)?;
     let cal_sync = CalendarSync::new(config.clone(), pool).await;
     let shortened_urls = Arc::new(RwLock::new(HashMap::new()));
-
-    tokio::task::spawn(update_db(cal_sync.pool.clone()));
+    let attachments = AttachmentStore::new(config).await;
+    let signer = UrlSigner::new(&SECRET_KEY.get());
+    let public_limiter = Arc::new(RateLimiter::new(config.public_rate_limit));
+    let lookup_limiter = Arc::new(RateLimiter::new(config.public_lookup_rate_limit));
+    let token_usage = Arc::new(TokenUsage::new());
+
+    let tasks = TaskTracker::new(shutdown);
+    tasks.spawn("update_db", update_db(cal_sync.pool.clone()));
+    tasks.spawn("token_usage", {
+        let token_usage = token_usage.clone();
+        let pool = cal_sync.pool.clone();
+        async move { token_usage.flush_loop(&pool).await }
+    });
 
     let app = AppState {
         cal_sync,
         shortened_urls,
+        attachments,
+        signer,
+        public_limiter,
+        lookup_limiter,
+        token_usage,
     };
 
     let (spec, calendar_path) = openapi::spec()
@@ -114,6 +221,9 @@ async fn run_app(config: &Config) -> Result<(), Error> {
         })
         .build(|| get_calendar_path(&app));
 
+    let attachment_download_path = attachment_download(app.clone());
+    let calendar_feed_path = calendar_feed(app.clone());
+
     let spec = Arc::new(spec);
     let spec_json_path = rweb::path!("calendar" / "openapi" / "json")
         .and(rweb::path::end())
@@ -133,9 +243,19 @@ async fn run_app(config: &Config) -> Result<(), Error> {
     let routes = calendar_path
         .or(spec_json_path)
         .or(spec_yaml_path)
+        .or(attachment_download_path)
+        .or(calendar_feed_path)
         .recover(error_response);
     let addr: SocketAddr = format_sstr!("{}:{}", config.host, config.port).parse()?;
-    rweb::serve(routes).bind(addr).await;
+    let stopped = {
+        let shutdown = tasks.shutdown().clone();
+        async move { shutdown.cancelled().await }
+    };
+    let (_, server) = rweb::serve(routes).try_bind_with_graceful_shutdown(addr, stopped)?;
+    server.await;
+    tasks.shutdown_and_wait(SHUTDOWN_GRACE).await;
+    // Write out usage counted since the last flush
+    app.token_usage.flush(&app.cal_sync.pool).await?;
     Ok(())
 }
 
@@ -149,7 +269,7 @@ mod tests {
     use auth_server_http::app::run_test_app;
     use auth_server_lib::get_random_string;
 
-    use calendar_app_lib::config::Config;
+    use calendar_app_lib::{config::Config, shutdown::Shutdown};
 
     use crate::{
         app::run_app,
@@ -182,7 +302,11 @@ mod tests {
         set_var("PORT", test_port.to_string());
         let config = Config::init_config()?;
 
-        tokio::task::spawn(async move { run_app(&config).await.unwrap() });
+        let shutdown = Shutdown::new();
+        let server = tokio::task::spawn({
+            let shutdown = shutdown.clone();
+            async move { run_app(&config, shutdown).await.unwrap() }
+        });
         tokio::time::sleep(std::time::Duration::from_secs(10)).await;
 
         let client = reqwest::Client::builder().cookie_store(true).build()?;
@@ -213,6 +337,9 @@ mod tests {
         assert!(result.len() > 0);
         assert!(result.contains("Calendar"));
 
+        shutdown.trigger();
+        server.await?;
+
         remove_var("TESTENV");
         Ok(())
     }
@@ -4,17 +4,18 @@ use dioxus::prelude::{
 };
 use itertools::Itertools;
 use stack_string::{format_sstr, StackString};
-use std::collections::HashMap;
-use time::macros::format_description;
+use std::collections::{BTreeMap, HashMap};
+use time::{macros::format_description, OffsetDateTime};
 use url::Url;
 
 use calendar_app_lib::{
     calendar::{Calendar, Event},
     config::Config,
     get_default_or_local_time,
+    models::ScrapeRun,
 };
 
-use crate::errors::ServiceError as Error;
+use crate::{errors::ServiceError as Error, routes::EventAttachmentInfo};
 
 /// # Errors
 /// Returns error if formatting fails
@@ -43,6 +44,18 @@ fn IndexElement() -> Element {
                     value: "Agenda",
                     "onclick": "displayAgenda();",
                 },
+                input {
+                    "type": "button",
+                    name: "display_week",
+                    value: "Week",
+                    "onclick": "displayAgenda(true);",
+                },
+                input {
+                    "type": "button",
+                    name: "share_agenda",
+                    value: "Share Agenda",
+                    "onclick": "shareLink('/calendar/agenda');",
+                },
                 input {
                     "type": "button",
                     name: "sync",
@@ -55,6 +68,12 @@ fn IndexElement() -> Element {
                     value: "List Calendars",
                     "onclick": "listCalendars();",
                 },
+                input {
+                    "type": "button",
+                    name: "scrape_health",
+                    value: "Scraper Health",
+                    "onclick": "scrapeHealth();",
+                },
                 button {
                     name: "garminconnectoutput",
                     id: "garminconnectoutput",
@@ -83,14 +102,20 @@ fn IndexElement() -> Element {
 pub fn agenda_body(
     calendar_map: HashMap<StackString, Calendar>,
     events: Vec<Event>,
+    now: OffsetDateTime,
+    week: bool,
     config: Config,
+    can_edit: bool,
 ) -> Result<String, Error> {
     let mut app = VirtualDom::new_with_props(
         AgendaElement,
         AgendaElementProps {
             calendar_map,
             events,
+            now,
+            week,
             config,
+            can_edit,
         },
     );
     app.rebuild_in_place();
@@ -102,69 +127,100 @@ pub fn agenda_body(
     Ok(buffer)
 }
 
+/// Events are split around a "now" row, `scripts.js` moves it as time
+/// passes and reloads the table every `agenda_refresh_seconds`.  Without
+/// `can_edit` (a signed link) there are no buttons, only plain text.
 #[component]
 fn AgendaElement(
     calendar_map: HashMap<StackString, Calendar>,
     events: Vec<Event>,
+    now: OffsetDateTime,
+    week: bool,
     config: Config,
+    can_edit: bool,
 ) -> Element {
+    let event_row = |(idx, event): (usize, &Event)| {
+        let cal = calendar_map.get(&event.gcal_id)?;
+        let calendar_name = cal.gcal_name.as_ref().unwrap_or(&cal.name);
+        let delete = if can_edit && cal.edit && cal.is_writable() {
+            let event_id = &event.event_id;
+            let gcal_id = &event.gcal_id;
+            Some(rsx! {
+                input {
+                    "type": "button",
+                    name: "delete_event",
+                    value: "Delete",
+                    "onclick": "deleteEventAgenda('{gcal_id}', '{event_id}')",
+                }
+            })
+        } else {
+            None
+        };
+        let start_time = get_default_or_local_time(event.start_time.into(), &config);
+        let start_timestamp = event.start_time.unix_timestamp();
+        let cal_name = &cal.name;
+        let gcal_id = &event.gcal_id;
+        let event_id = &event.event_id;
+        let event_name = &event.name;
+        Some(rsx! {
+            tr {
+                key: "event-key-{idx}",
+                "text-style": "center",
+                "data-start": "{start_timestamp}",
+                td {
+                    if can_edit {
+                        input {
+                            "type": "button",
+                            name: "list_events",
+                            value: "{calendar_name}",
+                            "onclick": "listEvents('{cal_name}')",
+                        }
+                    } else {
+                        "{calendar_name}"
+                    }
+                },
+                td {
+                    if can_edit {
+                        input {
+                            "type": "button",
+                            name: "event_detail",
+                            value: "{event_name}",
+                            "onclick": "eventDetail('{gcal_id}', '{event_id}')",
+                        }
+                    } else {
+                        "{event_name}"
+                    }
+                },
+                td {"{start_time}"},
+                td { {delete} },
+            }
+        })
+    };
+    let refresh = config.agenda_refresh_seconds;
+    let now_time = get_default_or_local_time(now, &config);
     rsx! {
         table {
             "border": "1",
             class: "dataframe",
+            id: "agenda_table",
+            "data-refresh": "{refresh}",
+            "data-week": "{week}",
             thead {
                 th {"Calendar"},
                 th {"Event"},
                 th {"Start Time"},
             },
             tbody {
-                {events.iter().enumerate().filter_map(|(idx, event)| {
-                    let cal = calendar_map.get(&event.gcal_id)?;
-                    let calendar_name = cal.gcal_name.as_ref().unwrap_or(&cal.name);
-                    let delete = if cal.edit {
-                        let event_id = &event.event_id;
-                        let gcal_id = &event.gcal_id;
-                        Some(rsx! {
-                            input {
-                                "type": "button",
-                                name: "delete_event",
-                                value: "Delete",
-                                "onclick": "deleteEventAgenda('{gcal_id}', '{event_id}')",
-                            }
-                        })
-                    } else {
-                        None
-                    };
-                    let start_time = get_default_or_local_time(event.start_time.into(), &config);
-                    let cal_name = &cal.name;
-                    let gcal_id = &event.gcal_id;
-                    let event_id = &event.event_id;
-                    let event_name = &event.name;
-                    Some(rsx! {
-                        tr {
-                            key: "event-key-{idx}",
-                            "text-style": "center",
-                            td {
-                                input {
-                                    "type": "button",
-                                    name: "list_events",
-                                    value: "{calendar_name}",
-                                    "onclick": "listEvents('{cal_name}')",
-                                },
-                            },
-                            td {
-                                input {
-                                    "type": "button",
-                                    name: "event_detail",
-                                    value: "{event_name}",
-                                    "onclick": "eventDetail('{gcal_id}', '{event_id}')",
-                                }
-                            },
-                            td {"{start_time}"},
-                            td { {delete} },
-                        }
-                    })
-                })}
+                {events.iter().enumerate().filter(|(_, e)| *e.start_time <= now).filter_map(event_row)},
+                tr {
+                    id: "now_line",
+                    class: "now-line",
+                    td {
+                        colspan: "4",
+                        "now {now_time}",
+                    },
+                },
+                {events.iter().enumerate().filter(|(_, e)| *e.start_time > now).filter_map(event_row)}
             }
         }
     }
@@ -208,7 +264,7 @@ fn ListCalendarsElement(calendars: Vec<Calendar>) -> Element {
             tbody {
                 {calendars.iter().enumerate().map(|(idx, calendar)| {
                     let gcal_id = &calendar.gcal_id;
-                    let create_event = if calendar.edit {
+                    let create_event = if calendar.edit && calendar.is_writable() {
                         Some(rsx! {
                             input {
                                 "type": "button",
@@ -270,6 +326,7 @@ pub fn list_events_body(
     calendar: Calendar,
     events: Vec<Event>,
     config: Config,
+    can_edit: bool,
 ) -> Result<String, Error> {
     let mut app = VirtualDom::new_with_props(
         ListEventsElement,
@@ -277,6 +334,7 @@ pub fn list_events_body(
             calendar,
             events,
             config,
+            can_edit,
         },
     );
     app.rebuild_in_place();
@@ -289,7 +347,12 @@ pub fn list_events_body(
 }
 
 #[component]
-fn ListEventsElement(calendar: Calendar, events: Vec<Event>, config: Config) -> Element {
+fn ListEventsElement(
+    calendar: Calendar,
+    events: Vec<Event>,
+    config: Config,
+    can_edit: bool,
+) -> Element {
     let gcal_id = &calendar.gcal_id;
     rsx! {
         table {
@@ -300,18 +363,20 @@ fn ListEventsElement(calendar: Calendar, events: Vec<Event>, config: Config) ->
                 th {"Start Time"},
                 th {"End Time"},
                 th {
-                    input {
-                        "type": "button",
-                        name: "create_event",
-                        value: "Create Event",
-                        "onclick": "buildEvent('{gcal_id}')",
+                    if can_edit && calendar.edit && calendar.is_writable() {
+                        input {
+                            "type": "button",
+                            name: "create_event",
+                            value: "Create Event",
+                            "onclick": "buildEvent('{gcal_id}')",
+                        }
                     }
                 }
             },
             tbody {
                 {events.iter().enumerate().map(|(idx, event)| {
 
-                    let delete = if calendar.edit {
+                    let delete = if can_edit && calendar.edit && calendar.is_writable() {
                         let gcal_id = &event.gcal_id;
                         let event_id = &event.event_id;
                         let calendar_name = &calendar.name;
@@ -337,11 +402,22 @@ fn ListEventsElement(calendar: Calendar, events: Vec<Event>, config: Config) ->
                             key: "event-key-{idx}",
                             "text-style": "center",
                             td {
-                                input {
-                                    "type": "button",
-                                    name: "{name}",
-                                    value: "{name}",
-                                    "onclick": "eventDetail('{gcal_id}', '{event_id}')",
+                                if can_edit {
+                                    input {
+                                        "type": "button",
+                                        name: "{name}",
+                                        value: "{name}",
+                                        "onclick": "eventDetail('{gcal_id}', '{event_id}')",
+                                    }
+                                } else {
+                                    "{name}"
+                                },
+                                if !event.editable {
+                                    span {
+                                        class: "read-only",
+                                        title: "Changes to this event are rejected by Google Calendar",
+                                        " (read-only)",
+                                    }
                                 }
                             },
                             td {"{start_time}"},
@@ -357,10 +433,231 @@ fn ListEventsElement(calendar: Calendar, events: Vec<Event>, config: Config) ->
 
 /// # Errors
 /// Returns error if formatting fails
-pub fn event_detail_body(event: Event, config: Config) -> Result<String, Error> {
+pub fn shared_events_body(
+    calendar_name: StackString,
+    events: Vec<Event>,
+    attachments: HashMap<StackString, Vec<EventAttachmentInfo>>,
+    config: Config,
+) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(
+        SharedEventsElement,
+        SharedEventsElementProps {
+            calendar_name,
+            events,
+            attachments,
+            config,
+        },
+    );
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer
+        .render_to(&mut buffer, &app)
+        .map_err(Into::<Error>::into)?;
+    Ok(buffer)
+}
+
+/// Read-only event list for shared pages and embeds, no scripts are loaded
+/// so there are no links back into the authenticated app.
+#[component]
+fn SharedEventsElement(
+    calendar_name: StackString,
+    events: Vec<Event>,
+    attachments: HashMap<StackString, Vec<EventAttachmentInfo>>,
+    config: Config,
+) -> Element {
+    rsx! {
+        head {
+            title {"{calendar_name}"},
+            style {dangerous_inner_html: include_str!("../../templates/style.css")},
+        },
+        body {
+            h3 {"{calendar_name}"},
+            table {
+                "border": "1",
+                class: "dataframe",
+                thead {
+                    th {"Event"},
+                    th {"Start Time"},
+                    th {"End Time"},
+                    th {"Location"},
+                    th {"Attachments"},
+                },
+                tbody {
+                    {events.iter().enumerate().map(|(idx, event)| {
+                        let start_time = get_default_or_local_time(event.start_time.into(), &config);
+                        let end_time = get_default_or_local_time(event.end_time.into(), &config);
+                        let name = &event.name;
+                        let location = event.location.as_ref().map_or("", |l| l.name.as_str());
+                        let event_attachments = attachments.get(&event.event_id);
+                        let name_element = event.url.as_ref().map_or_else(
+                            || rsx! {"{name}"},
+                            |url| rsx! {
+                                a {
+                                    href: "{url}",
+                                    "{name}",
+                                }
+                            },
+                        );
+                        rsx! {
+                            tr {
+                                key: "event-key-{idx}",
+                                "text-style": "center",
+                                td { {name_element} },
+                                td {"{start_time}"},
+                                td {"{end_time}"},
+                                td {"{location}"},
+                                td {
+                                    {event_attachments.into_iter().flatten().map(|attachment| {
+                                        let attachment_id = &attachment.attachment_id;
+                                        let url = &attachment.url;
+                                        let filename = &attachment.filename;
+                                        rsx! {
+                                            a {
+                                                key: "attachment-{attachment_id}",
+                                                href: "{url}",
+                                                "{filename}",
+                                            },
+                                            br {},
+                                        }
+                                    })}
+                                },
+                            }
+                        }
+                    })}
+                }
+            }
+        }
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn scrape_health_body(runs: Vec<ScrapeRun>, config: Config) -> Result<String, Error> {
+    let mut sources: BTreeMap<StackString, Vec<ScrapeRun>> = BTreeMap::new();
+    for run in runs {
+        sources.entry(run.source.clone()).or_default().push(run);
+    }
+    let mut app = VirtualDom::new_with_props(
+        ScrapeHealthElement,
+        ScrapeHealthElementProps { sources, config },
+    );
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer
+        .render_to(&mut buffer, &app)
+        .map_err(Into::<Error>::into)?;
+    Ok(buffer)
+}
+
+const CHART_WIDTH: usize = 600;
+const CHART_HEIGHT: usize = 120;
+
+/// Line chart of events found (black) and parse failures (red) per run,
+/// oldest run on the left
+fn scrape_chart_svg(runs: &[ScrapeRun]) -> StackString {
+    let max = runs
+        .iter()
+        .map(|run| run.events_found.max(run.parse_failures))
+        .max()
+        .unwrap_or(0)
+        .max(1) as usize;
+    // In f64 so the last run lands on the right edge when the width isn't a
+    // multiple of the number of runs
+    let step = CHART_WIDTH as f64 / (runs.len().max(2) - 1) as f64;
+    let points = |value: fn(&ScrapeRun) -> i32| {
+        runs.iter()
+            .enumerate()
+            .map(|(idx, run)| {
+                let y = CHART_HEIGHT - CHART_HEIGHT * value(run).max(0) as usize / max;
+                format_sstr!("{:.1},{y}", idx as f64 * step)
+            })
+            .join(" ")
+    };
+    format_sstr!(
+        r#"<svg width="{CHART_WIDTH}" height="{CHART_HEIGHT}" style="border:1px solid #ccc">
+        <text x="4" y="14" font-size="12">{max}</text>
+        <polyline fill="none" stroke="black" points="{found}"/>
+        <polyline fill="none" stroke="red" points="{failures}"/>
+        </svg>"#,
+        found = points(|run| run.events_found),
+        failures = points(|run| run.parse_failures),
+    )
+}
+
+#[component]
+fn ScrapeHealthElement(sources: BTreeMap<StackString, Vec<ScrapeRun>>, config: Config) -> Element {
+    rsx! {
+        {sources.iter().map(|(source, runs)| {
+            let chart = scrape_chart_svg(runs);
+            rsx! {
+                div {
+                    key: "scrape-source-{source}",
+                    h3 {"{source}"},
+                    div {dangerous_inner_html: "{chart}"},
+                    table {
+                        "border": "1",
+                        class: "dataframe",
+                        thead {
+                            th {"Started"},
+                            th {"Found"},
+                            th {"New"},
+                            th {"Updated"},
+                            th {"Parse Failures"},
+                            th {"Status"},
+                        },
+                        tbody {
+                            {runs.iter().rev().take(10).map(|run| {
+                                let run_id = run.run_id;
+                                let started_at = get_default_or_local_time(run.started_at.into(), &config);
+                                let found = run.events_found;
+                                let new = run.events_new;
+                                let updated = run.events_updated;
+                                let parse_failures = run.parse_failures;
+                                let status = run
+                                    .error
+                                    .as_ref()
+                                    .map(|e| format_sstr!("error: {e}"))
+                                    .or_else(|| run.alert.as_ref().map(|a| format_sstr!("ALERT: {a}")))
+                                    .unwrap_or_else(|| "ok".into());
+                                rsx! {
+                                    tr {
+                                        key: "scrape-run-{run_id}",
+                                        "text-style": "center",
+                                        td {"{started_at}"},
+                                        td {"{found}"},
+                                        td {"{new}"},
+                                        td {"{updated}"},
+                                        td {"{parse_failures}"},
+                                        td {"{status}"},
+                                    }
+                                }
+                            })}
+                        }
+                    }
+                }
+            }
+        })}
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn event_detail_body(
+    event: Event,
+    config: Config,
+    attachments: Vec<EventAttachmentInfo>,
+    editable: bool,
+) -> Result<String, Error> {
     let mut app = VirtualDom::new_with_props(
         EventDetailElement,
-        EventDetailElementProps { event, config },
+        EventDetailElementProps {
+            event,
+            config,
+            attachments,
+            editable,
+        },
     );
     app.rebuild_in_place();
     let mut renderer = dioxus_ssr::Renderer::default();
@@ -372,7 +669,12 @@ pub fn event_detail_body(event: Event, config: Config) -> Result<String, Error>
 }
 
 #[component]
-fn EventDetailElement(event: Event, config: Config) -> Element {
+fn EventDetailElement(
+    event: Event,
+    config: Config,
+    attachments: Vec<EventAttachmentInfo>,
+    editable: bool,
+) -> Element {
     let name = &event.name;
     let description = event.description.as_ref().map(|description| {
         let description = description
@@ -405,6 +707,8 @@ fn EventDetailElement(event: Event, config: Config) -> Element {
     });
     let start_time = get_default_or_local_time(event.start_time.into(), &config);
     let end_time = get_default_or_local_time(event.end_time.into(), &config);
+    let gcal_id = &event.gcal_id;
+    let event_id = &event.event_id;
     rsx! {
         table {
             "border": "1",
@@ -417,6 +721,13 @@ fn EventDetailElement(event: Event, config: Config) -> Element {
                     td {"Name"},
                     td {"{name}"},
                 },
+                if !event.editable {
+                    tr {
+                        "text-style": "center",
+                        td {"Read-only"},
+                        td {"Organized elsewhere, changes are rejected by Google Calendar"},
+                    }
+                },
                 tr {
                     "text-style": "center",
                     td {"Description"},
@@ -464,6 +775,51 @@ fn EventDetailElement(event: Event, config: Config) -> Element {
                     "text-style": "center",
                     td {"End Time"},
                     td {"{end_time}"},
+                },
+                {attachments.iter().map(|attachment| {
+                    let attachment_id = &attachment.attachment_id;
+                    let filename = &attachment.filename;
+                    let url = &attachment.url;
+                    let label = if attachment.is_ticket {"Ticket"} else {"Attachment"};
+                    rsx! {
+                        tr {
+                            key: "attachment-{attachment_id}",
+                            "text-style": "center",
+                            td {"{label}"},
+                            td {
+                                a {
+                                    href: "{url}",
+                                    "{filename}",
+                                },
+                                if editable {
+                                    input {
+                                        "type": "button",
+                                        name: "delete_attachment",
+                                        value: "Delete",
+                                        "onclick": "deleteAttachment('{attachment_id}', '{gcal_id}', '{event_id}')",
+                                    }
+                                }
+                            }
+                        }
+                    }
+                })},
+                if editable {
+                    tr {
+                        "text-style": "center",
+                        td {"Upload"},
+                        td {
+                            input {
+                                "type": "file",
+                                id: "attachment_file",
+                            },
+                            input {
+                                "type": "button",
+                                name: "upload_attachment",
+                                value: "Upload",
+                                "onclick": "uploadAttachment('{gcal_id}', '{event_id}')",
+                            }
+                        }
+                    }
                 }
             }
         }
@@ -30,6 +30,8 @@ pub enum ServiceError {
     BadRequest(StackString),
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Too Many Requests")]
+    TooManyRequests,
     #[error("Anyhow error {0}")]
     AnyhowError(#[from] AnyhowError),
     #[error("io Error {0}")]
@@ -82,6 +84,10 @@ pub async fn error_response(err: Rejection) -> Result<Box<dyn Reply>, Infallible
             ServiceError::Unauthorized => {
                 return Ok(Box::new(login_html()));
             }
+            ServiceError::TooManyRequests => {
+                code = StatusCode::TOO_MANY_REQUESTS;
+                message = "Too Many Requests";
+            }
             _ => {
                 error!("Other error: {:?}", service_err);
                 code = StatusCode::INTERNAL_SERVER_ERROR;
@@ -124,6 +130,7 @@ impl ResponseEntity for ServiceError {
             (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
             (StatusCode::BAD_REQUEST, "Bad Request"),
             (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
+            (StatusCode::TOO_MANY_REQUESTS, "Too Many Requests"),
         ];
 
         for (code, msg) in &error_responses {
@@ -156,6 +163,10 @@ mod test {
         let err = ServiceError::InternalServerError.into();
         let resp = error_response(err).await?.into_response();
         assert_eq!(resp.status().as_u16(), 500);
+
+        let err = ServiceError::TooManyRequests.into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 429);
         Ok(())
     }
 }
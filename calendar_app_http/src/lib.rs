@@ -7,22 +7,32 @@
 #![allow(clippy::implicit_hasher)]
 #![allow(clippy::ignored_unit_patterns)]
 
+pub mod api_key;
 pub mod app;
 pub mod elements;
 pub mod errors;
 pub mod logged_user;
+pub mod rate_limit;
 pub mod routes;
+pub mod signed_access;
+pub mod token_usage;
 
 use derive_more::{From, Into};
 use rweb::Schema;
 use rweb_helper::{derive_rweb_schema, DateTimeType};
 use serde::{Deserialize, Serialize};
-use stack_string::StackString;
+use stack_string::{format_sstr, StackString};
 use time::OffsetDateTime;
 
 use gcal_lib::date_time_wrapper::DateTimeWrapper;
 
-use calendar_app_lib::models::{CalendarCache, CalendarList};
+use calendar_app_lib::{
+    calendar::AgendaEvent,
+    description::DescriptionMode,
+    inbound::InboundEvent,
+    models::{AccessToken, CalendarCache, CalendarList, InboundSource},
+    stats::CalendarStats,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize, Into, From)]
 pub struct CalendarListWrapper(CalendarList);
@@ -53,6 +63,8 @@ struct _CalendarListWrapper {
     edit: bool,
     #[schema(description = "Display Flag")]
     display: bool,
+    #[schema(description = "GCal Access Role (owner, writer, reader or freeBusyReader)")]
+    access_role: Option<StackString>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Into, From)]
@@ -86,6 +98,12 @@ struct _CalendarCacheWrapper {
     event_location_lon: Option<f64>,
     #[schema(description = "Last Modified")]
     last_modified: DateTimeType,
+    #[schema(description = "Deployment the Edit was Made On")]
+    source_id: Option<StackString>,
+    #[schema(description = "Time the Edit was Made")]
+    source_modified: Option<DateTimeType>,
+    #[schema(description = "Editable Flag (false if gcal would reject changes)")]
+    editable: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -108,6 +126,87 @@ struct _MinModifiedQuery {
     limit: Option<usize>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct CalendarCacheQuery {
+    pub min_modified: Option<DateTimeWrapper>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub description: Option<DescriptionMode>,
+}
+
+derive_rweb_schema!(CalendarCacheQuery, _CalendarCacheQuery);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+struct _CalendarCacheQuery {
+    #[schema(description = "Min Modified Date")]
+    min_modified: Option<DateTimeType>,
+    #[schema(description = "Offset")]
+    offset: Option<usize>,
+    #[schema(description = "Limit")]
+    limit: Option<usize>,
+    #[schema(description = "Event Descriptions: summary, full (default) or none")]
+    description: Option<StackString>,
+}
+
+/// Entry of the `/calendar/calendar_cache` listing, the description may have
+/// been shortened according to the requested `DescriptionMode`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CalendarCacheSummary {
+    #[serde(flatten)]
+    pub event: CalendarCache,
+    pub description_truncated: bool,
+}
+
+impl CalendarCacheSummary {
+    #[must_use]
+    pub fn new(mut event: CalendarCache, mode: DescriptionMode, max_length: usize) -> Self {
+        let description_truncated = mode.apply(&mut event.event_description, max_length);
+        Self {
+            event,
+            description_truncated,
+        }
+    }
+}
+
+derive_rweb_schema!(CalendarCacheSummary, _CalendarCacheSummary);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "CalendarCacheSummary")]
+struct _CalendarCacheSummary {
+    #[schema(description = "Gcal Calendar ID")]
+    gcal_id: StackString,
+    #[schema(description = "Calendar Event ID")]
+    event_id: StackString,
+    #[schema(description = "Event Start Time")]
+    event_start_time: DateTimeType,
+    #[schema(description = "Event End Time")]
+    event_end_time: DateTimeType,
+    #[schema(description = "Event URL")]
+    event_url: Option<StackString>,
+    #[schema(description = "Event Name")]
+    event_name: StackString,
+    #[schema(description = "Event Description")]
+    event_description: Option<StackString>,
+    #[schema(description = "Event Location Name")]
+    event_location_name: Option<StackString>,
+    #[schema(description = "Event Location Latitude")]
+    event_location_lat: Option<f64>,
+    #[schema(description = "Event Location Longitude")]
+    event_location_lon: Option<f64>,
+    #[schema(description = "Last Modified")]
+    last_modified: DateTimeType,
+    #[schema(description = "Deployment the Edit was Made On")]
+    source_id: Option<StackString>,
+    #[schema(description = "Time the Edit was Made")]
+    source_modified: Option<DateTimeType>,
+    #[schema(description = "Editable Flag (false if gcal would reject changes)")]
+    editable: bool,
+    #[schema(description = "Description was Shortened or Omitted")]
+    description_truncated: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CalendarCacheRequest {
     pub gcal_id: StackString,
@@ -121,6 +220,9 @@ pub struct CalendarCacheRequest {
     pub event_location_lat: Option<f64>,
     pub event_location_lon: Option<f64>,
     pub last_modified: DateTimeWrapper,
+    pub source_id: Option<StackString>,
+    pub source_modified: Option<DateTimeWrapper>,
+    pub editable: Option<bool>,
 }
 
 impl From<CalendarCacheRequest> for CalendarCache {
@@ -140,6 +242,9 @@ impl From<CalendarCacheRequest> for CalendarCache {
             event_location_lat: item.event_location_lat,
             event_location_lon: item.event_location_lon,
             last_modified: last_modified.into(),
+            source_id: item.source_id,
+            source_modified: item.source_modified,
+            editable: item.editable.unwrap_or(true),
         }
     }
 }
@@ -172,6 +277,12 @@ struct _CalendarCacheRequest {
     event_location_lon: Option<f64>,
     #[schema(description = "Last Modified")]
     last_modified: DateTimeType,
+    #[schema(description = "Deployment the Edit was Made On")]
+    source_id: Option<StackString>,
+    #[schema(description = "Time the Edit was Made")]
+    source_modified: Option<DateTimeType>,
+    #[schema(description = "Editable Flag (default true)")]
+    editable: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -210,14 +321,320 @@ struct _CreateCalendarEventRequest {
     event_location_name: Option<StackString>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, Into, From)]
+pub struct AccessTokenWrapper(AccessToken);
+
+derive_rweb_schema!(AccessTokenWrapper, _AccessTokenWrapper);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "AccessToken")]
+struct _AccessTokenWrapper {
+    #[schema(description = "Hash of the Feed Token or Api Key")]
+    token_hash: StackString,
+    #[schema(description = "Owner Email")]
+    email: StackString,
+    #[schema(description = "GCal Calendar ID (all calendars if unset)")]
+    gcal_id: Option<StackString>,
+    #[schema(description = "Description")]
+    description: Option<StackString>,
+    #[schema(description = "Requests Served")]
+    request_count: i64,
+    #[schema(description = "Requests Rejected by Rate Limit")]
+    rejected_count: i64,
+    #[schema(description = "Last Used")]
+    last_used_at: Option<DateTimeType>,
+    #[schema(description = "Created At")]
+    created_at: DateTimeType,
+    #[schema(description = "Api Key Flag")]
+    is_api_key: bool,
+    #[schema(description = "Read Only Flag")]
+    read_only: bool,
+    #[schema(description = "Expires At")]
+    expires_at: Option<DateTimeType>,
+    #[schema(description = "Hash of the Previous Token (valid during rotation grace period)")]
+    previous_token_hash: Option<StackString>,
+    #[schema(description = "Previous Token Expires At")]
+    previous_expires_at: Option<DateTimeType>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Into, From)]
+pub struct InboundEventWrapper(InboundEvent);
+
+derive_rweb_schema!(InboundEventWrapper, _InboundEventWrapper);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "InboundEvent")]
+struct _InboundEventWrapper {
+    #[schema(description = "Producer Event ID (re-posting updates the event)")]
+    external_id: Option<StackString>,
+    #[schema(description = "Event Name")]
+    name: StackString,
+    #[schema(description = "Event Start Time")]
+    start_time: DateTimeType,
+    #[schema(description = "Event End Time")]
+    end_time: Option<DateTimeType>,
+    #[schema(description = "Event Description")]
+    description: Option<StackString>,
+    #[schema(description = "Event URL")]
+    url: Option<StackString>,
+    #[schema(description = "Event Location Name")]
+    location: Option<StackString>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Into, From)]
+pub struct InboundSourceWrapper(InboundSource);
+
+derive_rweb_schema!(InboundSourceWrapper, _InboundSourceWrapper);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "InboundSource")]
+struct _InboundSourceWrapper {
+    #[schema(description = "Source Token")]
+    source_token: StackString,
+    #[schema(description = "Source Name")]
+    source_name: StackString,
+    #[schema(description = "Owner Email")]
+    email: StackString,
+    #[schema(description = "GCal Calendar ID")]
+    gcal_id: StackString,
+    #[schema(description = "Required Payload Fields")]
+    required_fields: Vec<StackString>,
+    #[schema(description = "Maximum Event Duration in Minutes")]
+    max_duration_minutes: Option<i32>,
+    #[schema(description = "Rate Limit per Minute")]
+    rate_limit_per_minute: i32,
+    #[schema(description = "Events Accepted")]
+    request_count: i64,
+    #[schema(description = "Requests Rejected by Validation")]
+    rejected_count: i64,
+    #[schema(description = "Last Used")]
+    last_used_at: Option<DateTimeType>,
+    #[schema(description = "Created At")]
+    created_at: DateTimeType,
+}
+
+/// IFTTT requires every trigger item to carry `meta`, Zapier deduplicates
+/// polled items on the top level `id`.
+#[derive(Clone, Debug, Serialize, Deserialize, Schema)]
+#[schema(component = "IntegrationMeta")]
+pub struct IntegrationMeta {
+    #[schema(description = "Unique Item ID")]
+    pub id: StackString,
+    #[schema(description = "Unix Timestamp")]
+    pub timestamp: i64,
+}
+
+/// Event as exposed to Zapier / IFTTT triggers and actions
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IntegrationEvent {
+    pub id: StackString,
+    pub gcal_id: StackString,
+    pub event_id: StackString,
+    pub name: StackString,
+    pub start_time: DateTimeWrapper,
+    pub end_time: DateTimeWrapper,
+    pub description: Option<StackString>,
+    pub location: Option<StackString>,
+    pub url: Option<StackString>,
+    pub description_truncated: bool,
+    pub meta: IntegrationMeta,
+}
+
+impl IntegrationEvent {
+    pub fn apply_description(&mut self, mode: DescriptionMode, max_length: usize) {
+        self.description_truncated = mode.apply(&mut self.description, max_length);
+    }
+}
+
+impl From<CalendarCache> for IntegrationEvent {
+    fn from(item: CalendarCache) -> Self {
+        let id = format_sstr!("{}/{}", item.gcal_id, item.event_id);
+        Self {
+            meta: IntegrationMeta {
+                id: id.clone(),
+                timestamp: item.last_modified.unix_timestamp(),
+            },
+            id,
+            gcal_id: item.gcal_id,
+            event_id: item.event_id,
+            name: item.event_name,
+            start_time: item.event_start_time,
+            end_time: item.event_end_time,
+            description: item.event_description,
+            location: item.event_location_name,
+            url: item.event_url,
+            description_truncated: false,
+        }
+    }
+}
+
+derive_rweb_schema!(IntegrationEvent, _IntegrationEvent);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "IntegrationEvent")]
+struct _IntegrationEvent {
+    #[schema(description = "Unique Item ID")]
+    id: StackString,
+    #[schema(description = "GCal Calendar ID")]
+    gcal_id: StackString,
+    #[schema(description = "Event ID")]
+    event_id: StackString,
+    #[schema(description = "Event Name")]
+    name: StackString,
+    #[schema(description = "Event Start Time")]
+    start_time: DateTimeType,
+    #[schema(description = "Event End Time")]
+    end_time: DateTimeType,
+    #[schema(description = "Event Description")]
+    description: Option<StackString>,
+    #[schema(description = "Event Location Name")]
+    location: Option<StackString>,
+    #[schema(description = "Event URL")]
+    url: Option<StackString>,
+    #[schema(description = "Description was Shortened or Omitted")]
+    description_truncated: bool,
+    #[schema(description = "Trigger Metadata")]
+    meta: IntegrationMeta,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewEventQuery {
+    pub gcal_id: Option<StackString>,
+    pub limit: Option<usize>,
+    pub description: Option<DescriptionMode>,
+}
+
+derive_rweb_schema!(NewEventQuery, _NewEventQuery);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "NewEventQuery")]
+struct _NewEventQuery {
+    #[schema(description = "GCal Calendar ID (all calendars if unset)")]
+    gcal_id: Option<StackString>,
+    #[schema(description = "Maximum Number of Events (default 50)")]
+    limit: Option<usize>,
+    #[schema(description = "Event Descriptions: summary, full (default) or none")]
+    description: Option<StackString>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IntegrationEventRequest {
+    pub gcal_id: StackString,
+    pub name: StackString,
+    pub start_time: DateTimeWrapper,
+    pub end_time: Option<DateTimeWrapper>,
+    pub description: Option<StackString>,
+    pub location: Option<StackString>,
+    pub url: Option<StackString>,
+}
+
+derive_rweb_schema!(IntegrationEventRequest, _IntegrationEventRequest);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "IntegrationEventRequest")]
+struct _IntegrationEventRequest {
+    #[schema(description = "GCal Calendar ID")]
+    gcal_id: StackString,
+    #[schema(description = "Event Name")]
+    name: StackString,
+    #[schema(description = "Event Start Time")]
+    start_time: DateTimeType,
+    #[schema(description = "Event End Time (defaults to one hour after start)")]
+    end_time: Option<DateTimeType>,
+    #[schema(description = "Event Description")]
+    description: Option<StackString>,
+    #[schema(description = "Event Location Name")]
+    location: Option<StackString>,
+    #[schema(description = "Event URL")]
+    url: Option<StackString>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Into, From)]
+pub struct AgendaEventWrapper(AgendaEvent);
+
+derive_rweb_schema!(AgendaEventWrapper, _AgendaEventWrapper);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "AgendaEvent")]
+struct _AgendaEventWrapper {
+    #[schema(description = "GCal Calendar ID")]
+    gcal_id: StackString,
+    #[schema(description = "Event ID")]
+    event_id: StackString,
+    #[schema(description = "Calendar Name")]
+    calendar_name: StackString,
+    #[schema(description = "Event Name")]
+    name: StackString,
+    #[schema(description = "Event Start Time")]
+    start_time: DateTimeType,
+    #[schema(description = "Event End Time")]
+    end_time: DateTimeType,
+    #[schema(description = "Event Description")]
+    description: Option<StackString>,
+    #[schema(description = "Event Location Name")]
+    location: Option<StackString>,
+    #[schema(description = "Event URL")]
+    url: Option<StackString>,
+    #[schema(description = "Description was Shortened or Omitted")]
+    description_truncated: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AgendaQuery {
+    pub description: Option<DescriptionMode>,
+}
+
+derive_rweb_schema!(AgendaQuery, _AgendaQuery);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+struct _AgendaQuery {
+    #[schema(description = "Event Descriptions: summary, full (default) or none")]
+    description: Option<StackString>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Into, From)]
+pub struct CalendarStatsWrapper(CalendarStats);
+
+derive_rweb_schema!(CalendarStatsWrapper, _CalendarStatsWrapper);
+
+#[allow(dead_code)]
+#[derive(Schema)]
+#[schema(component = "CalendarStats")]
+struct _CalendarStatsWrapper {
+    #[schema(description = "Number of Calendars")]
+    calendars: usize,
+    #[schema(description = "Number of Cached Events")]
+    events: usize,
+    #[schema(description = "Last Successful Sync")]
+    last_sync: Option<DateTimeType>,
+    #[schema(description = "Number of Scheduled Full Syncs")]
+    pending_full_syncs: usize,
+    #[schema(description = "Scraper Errors and Alerts")]
+    scrape_alerts: Vec<StackString>,
+}
+
 #[cfg(test)]
 mod test {
     use rweb_helper::derive_rweb_test;
 
     use crate::{
-        CalendarCacheRequest, CalendarCacheWrapper, CalendarListWrapper,
-        CreateCalendarEventRequest, MinModifiedQuery, _CalendarCacheRequest, _CalendarCacheWrapper,
-        _CalendarListWrapper, _CreateCalendarEventRequest, _MinModifiedQuery,
+        _AccessTokenWrapper, _AgendaEventWrapper, _AgendaQuery, _CalendarCacheQuery,
+        _CalendarCacheRequest, _CalendarCacheSummary, _CalendarCacheWrapper, _CalendarListWrapper,
+        _CalendarStatsWrapper, _CreateCalendarEventRequest, _InboundEventWrapper,
+        _InboundSourceWrapper, _IntegrationEvent, _IntegrationEventRequest, _MinModifiedQuery,
+        _NewEventQuery, AccessTokenWrapper, AgendaEventWrapper, AgendaQuery, CalendarCacheQuery,
+        CalendarCacheRequest, CalendarCacheSummary, CalendarCacheWrapper, CalendarListWrapper,
+        CalendarStatsWrapper, CreateCalendarEventRequest, InboundEventWrapper,
+        InboundSourceWrapper, IntegrationEvent, IntegrationEventRequest, MinModifiedQuery,
+        NewEventQuery,
     };
 
     #[test]
@@ -227,5 +644,16 @@ mod test {
         derive_rweb_test!(MinModifiedQuery, _MinModifiedQuery);
         derive_rweb_test!(CalendarCacheRequest, _CalendarCacheRequest);
         derive_rweb_test!(CreateCalendarEventRequest, _CreateCalendarEventRequest);
+        derive_rweb_test!(AccessTokenWrapper, _AccessTokenWrapper);
+        derive_rweb_test!(InboundEventWrapper, _InboundEventWrapper);
+        derive_rweb_test!(InboundSourceWrapper, _InboundSourceWrapper);
+        derive_rweb_test!(IntegrationEvent, _IntegrationEvent);
+        derive_rweb_test!(IntegrationEventRequest, _IntegrationEventRequest);
+        derive_rweb_test!(AgendaEventWrapper, _AgendaEventWrapper);
+        derive_rweb_test!(CalendarCacheQuery, _CalendarCacheQuery);
+        derive_rweb_test!(CalendarCacheSummary, _CalendarCacheSummary);
+        derive_rweb_test!(NewEventQuery, _NewEventQuery);
+        derive_rweb_test!(AgendaQuery, _AgendaQuery);
+        derive_rweb_test!(CalendarStatsWrapper, _CalendarStatsWrapper);
     }
 }
@@ -0,0 +1,161 @@
+use parking_lot::Mutex;
+use stack_string::StackString;
+use std::{collections::HashMap, time::Instant};
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    updated: Instant,
+}
+
+/// Most buckets kept, beyond this full buckets are dropped and then the
+/// least recently used ones.
+const MAX_BUCKETS: usize = 1024;
+
+/// Token bucket limiter keyed by e.g. feed token, allows bursts of up to
+/// `per_minute` requests which then refill at `per_minute` per minute.
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<StackString, Bucket>>,
+}
+
+impl RateLimiter {
+    #[must_use]
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute: per_minute.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `true` if the request is allowed
+    pub fn check(&self, key: &str) -> bool {
+        self.check_at(key, self.per_minute, Instant::now())
+    }
+
+    /// Same as `check` with a per key limit (e.g. configured per inbound
+    /// source) instead of the default.
+    pub fn check_limit(&self, key: &str, per_minute: u32) -> bool {
+        self.check_at(key, per_minute.max(1), Instant::now())
+    }
+
+    /// Check `key` against the limit it already has, `None` if it has no
+    /// bucket (never checked, or idle long enough to be dropped).
+    pub fn check_existing(&self, key: &str) -> Option<bool> {
+        self.check_existing_at(key, Instant::now())
+    }
+
+    /// Set the limit of `key` for later `check_existing` calls.
+    pub fn set_limit(&self, key: &str, per_minute: u32) {
+        let capacity = f64::from(per_minute.max(1));
+        if let Some(bucket) = self.buckets.lock().get_mut(key) {
+            bucket.capacity = capacity;
+            bucket.tokens = bucket.tokens.min(capacity);
+        }
+    }
+
+    fn check_existing_at(&self, key: &str, now: Instant) -> Option<bool> {
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.get_mut(key)?;
+        Some(bucket.take(bucket.capacity, now))
+    }
+
+    fn check_at(&self, key: &str, per_minute: u32, now: Instant) -> bool {
+        let capacity = f64::from(per_minute);
+        let mut buckets = self.buckets.lock();
+        if !buckets.contains_key(key) && buckets.len() >= MAX_BUCKETS {
+            // Full buckets carry no state, then evict the least recently used
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+            if buckets.len() >= MAX_BUCKETS {
+                let oldest = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    buckets.remove(&oldest);
+                }
+            }
+        }
+        buckets
+            .entry(key.into())
+            .or_insert(Bucket {
+                tokens: capacity,
+                capacity,
+                updated: now,
+            })
+            .take(capacity, now)
+    }
+}
+
+impl Bucket {
+    fn refilled(&self, capacity: f64, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        (self.tokens + elapsed * capacity / 60.0).min(capacity)
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        self.refilled(self.capacity, now) >= self.capacity
+    }
+
+    fn take(&mut self, capacity: f64, now: Instant) -> bool {
+        self.tokens = self.refilled(capacity, now);
+        self.capacity = capacity;
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::format_sstr;
+    use std::time::{Duration, Instant};
+
+    use crate::rate_limit::{RateLimiter, MAX_BUCKETS};
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(3);
+        let now = Instant::now();
+        assert!(limiter.check_at("a", 3, now));
+        assert!(limiter.check_at("a", 3, now));
+        assert!(limiter.check_at("a", 3, now));
+        assert!(!limiter.check_at("a", 3, now));
+        assert!(limiter.check_at("b", 3, now));
+        assert!(!limiter.check_at("a", 3, now + Duration::from_secs(10)));
+        assert!(limiter.check_at("a", 3, now + Duration::from_secs(20)));
+        assert!(!limiter.check_at("a", 3, now + Duration::from_secs(20)));
+
+        assert!(limiter.check_at("c", 1, now));
+        assert!(!limiter.check_at("c", 1, now + Duration::from_secs(30)));
+        assert!(limiter.check_at("c", 1, now + Duration::from_secs(60)));
+
+        assert_eq!(limiter.check_existing_at("d", now), None);
+        assert!(limiter.check_at("d", 2, now));
+        assert_eq!(limiter.check_existing_at("d", now), Some(true));
+        assert_eq!(limiter.check_existing_at("d", now), Some(false));
+        assert_eq!(
+            limiter.check_existing_at("d", now + Duration::from_secs(30)),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_max_buckets() {
+        let limiter = RateLimiter::new(3);
+        let now = Instant::now();
+        for i in 0..MAX_BUCKETS + 10 {
+            let key = format_sstr!("key {i}");
+            assert!(limiter.check_at(&key, 3, now + Duration::from_millis(i as u64)));
+        }
+        assert_eq!(limiter.buckets.lock().len(), MAX_BUCKETS);
+        // The least recently used buckets are the ones dropped
+        assert_eq!(limiter.check_existing_at("key 0", now), None);
+        assert!(limiter.check_existing_at("key 1033", now).is_some());
+    }
+}
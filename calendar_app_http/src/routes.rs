@@ -1,33 +1,60 @@
 use anyhow::format_err;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use futures::{future, stream::FuturesUnordered, TryStreamExt};
-use rweb::{delete, get, post, Json, Query, Rejection, Schema};
+use log::info;
+use rweb::{
+    delete,
+    filters::BoxedFilter,
+    get,
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+        Response,
+    },
+    post, Filter, Json, Query, Rejection, Reply, Schema,
+};
 use rweb_helper::{
     html_response::HtmlResponse as HtmlBase, json_response::JsonResponse as JsonBase, DateType,
     RwebResponse,
 };
 use serde::{Deserialize, Serialize};
 use stack_string::{format_sstr, StackString};
-use std::collections::HashMap;
-use time::OffsetDateTime;
+use std::collections::{HashMap, HashSet};
+use time::{macros::datetime, Duration, OffsetDateTime};
 use time_tz::OffsetDateTimeExt;
+use tokio::task::spawn_blocking;
+use uuid::Uuid;
 
 use calendar_app_lib::{
-    calendar::Event,
+    attachments::AttachmentStore,
+    calendar::{AgendaEvent, Event},
     calendar_sync::CalendarSync,
-    models::{CalendarCache, CalendarList, ShortenedLinks},
+    description::DescriptionMode,
+    ics::events_to_ics,
+    inbound::{InboundEvent, OPTIONAL_FIELDS},
+    models::{
+        hash_token, AccessToken, CalendarCache, CalendarList, EventAttachment, InboundSource,
+        ScrapeRun, ShortenedLinks,
+    },
+    signed_url::UrlSigner,
+    stats::CalendarStats,
+    tickets::detect_ticket,
     timezone::TimeZone,
 };
 
 use crate::{
+    api_key::{fill_api_keys, ApiCaller},
     app::{AppState, UrlCache},
     elements::{
         agenda_body, build_event_body, event_detail_body, index_body, list_calendars_body,
-        list_events_body,
+        list_events_body, scrape_health_body, shared_events_body,
     },
     errors::ServiceError as Error,
     logged_user::LoggedUser,
-    CalendarCacheRequest, CalendarCacheWrapper, CalendarListWrapper, CreateCalendarEventRequest,
-    MinModifiedQuery,
+    signed_access::{is_shareable, PageAccess, PageRequest},
+    AccessTokenWrapper, AgendaEventWrapper, AgendaQuery, CalendarCacheQuery, CalendarCacheRequest,
+    CalendarCacheSummary, CalendarCacheWrapper, CalendarListWrapper, CalendarStatsWrapper,
+    CreateCalendarEventRequest, InboundEventWrapper, InboundSourceWrapper, IntegrationEvent,
+    IntegrationEventRequest, IntegrationMeta, MinModifiedQuery, NewEventQuery,
 };
 
 pub type WarpResult<T> = Result<T, Rejection>;
@@ -50,17 +77,30 @@ pub async fn calendar_index(
 #[response(description = "Agenda", content = "html")]
 struct AgendaResponse(HtmlBase<StackString, Error>);
 
+#[derive(Serialize, Deserialize, Schema)]
+pub struct AgendaPageQuery {
+    #[schema(description = "Show the coming week instead of yesterday through tomorrow")]
+    pub week: Option<bool>,
+}
+
 #[get("/calendar/agenda")]
 #[openapi(description = "Calendar Agenda Page")]
 pub async fn agenda(
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    query: Query<AgendaPageQuery>,
+    #[filter = "PageRequest::filter"] request: PageRequest,
     #[data] data: AppState,
 ) -> WarpResult<AgendaResponse> {
-    let body = get_agenda(data.cal_sync).await?;
+    let access = request.authorize(&data.signer)?;
+    let week = query.into_inner().week.unwrap_or(false);
+    let body = get_agenda(data.cal_sync, week, &access).await?;
     Ok(HtmlBase::new(body).into())
 }
 
-async fn get_agenda(cal_sync: CalendarSync) -> HttpResult<StackString> {
+async fn get_agenda(
+    cal_sync: CalendarSync,
+    week: bool,
+    access: &PageAccess,
+) -> HttpResult<StackString> {
     let calendar_map: HashMap<_, _> = cal_sync
         .list_calendars()
         .await?
@@ -73,12 +113,67 @@ async fn get_agenda(cal_sync: CalendarSync) -> HttpResult<StackString> {
         })
         .try_collect()
         .await?;
-    let mut events = cal_sync.list_agenda(1, 2).await?;
+    let (days_before, days_after) = if week { (0, 7) } else { (1, 2) };
+    let mut events = cal_sync.list_agenda(days_before, days_after).await?;
     events.sort_by_key(|event| event.start_time);
-    let body = agenda_body(calendar_map, events, cal_sync.config.clone())?.into();
+    let body = agenda_body(
+        calendar_map,
+        events,
+        OffsetDateTime::now_utc(),
+        week,
+        cal_sync.config.clone(),
+        access.can_edit(),
+    )?
+    .into();
     Ok(body)
 }
 
+#[derive(RwebResponse)]
+#[response(description = "Agenda Events")]
+struct AgendaJsonResponse(JsonBase<Vec<AgendaEventWrapper>, Error>);
+
+#[get("/calendar/agenda.json")]
+#[openapi(description = "Calendar Agenda")]
+pub async fn agenda_json(
+    query: Query<AgendaQuery>,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
+    #[data] data: AppState,
+) -> WarpResult<AgendaJsonResponse> {
+    let query = query.into_inner();
+    let mode = query.description.unwrap_or_default();
+    let mut events = get_agenda_events(mode, &data.cal_sync).await?;
+    events.retain(|event| caller.allows_calendar(&event.0.gcal_id));
+    Ok(JsonBase::new(events).into())
+}
+
+async fn get_agenda_events(
+    mode: DescriptionMode,
+    cal_sync: &CalendarSync,
+) -> HttpResult<Vec<AgendaEventWrapper>> {
+    let max_length = cal_sync.config.description_summary_length;
+    let calendar_names: HashMap<_, _> = cal_sync
+        .list_calendars()
+        .await?
+        .map_ok(|cal| (cal.gcal_id, cal.name))
+        .try_collect()
+        .await?;
+    let mut events = cal_sync.list_agenda(1, 2).await?;
+    events.sort_by_key(|event| event.start_time);
+    let events = events
+        .into_iter()
+        .map(|event| {
+            let calendar_name = calendar_names
+                .get(&event.gcal_id)
+                .cloned()
+                .unwrap_or_else(|| event.gcal_id.clone());
+            let mut event = AgendaEvent::new(event, calendar_name);
+            event.description_truncated = mode.apply(&mut event.description, max_length);
+            event.into()
+        })
+        .collect();
+    Ok(events)
+}
+
 #[derive(RwebResponse)]
 #[response(description = "Sync Output", content = "html")]
 struct SyncResponse(HtmlBase<String, Error>);
@@ -86,9 +181,10 @@ struct SyncResponse(HtmlBase<String, Error>);
 #[post("/calendar/sync_calendars")]
 #[openapi(description = "Sync Calendars")]
 pub async fn sync_calendars(
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
     #[data] data: AppState,
 ) -> WarpResult<SyncResponse> {
+    caller.check_admin()?;
     let body = sync_calendars_body(&data.cal_sync, false).await?;
     Ok(HtmlBase::new(body).into())
 }
@@ -100,13 +196,34 @@ async fn sync_calendars_body(cal_sync: &CalendarSync, do_full: bool) -> HttpResu
 #[post("/calendar/sync_calendars_full")]
 #[openapi(description = "Fully Sync All Calendars")]
 pub async fn sync_calendars_full(
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
     #[data] data: AppState,
 ) -> WarpResult<SyncResponse> {
+    caller.check_admin()?;
     let body = sync_calendars_body(&data.cal_sync, true).await?;
     Ok(HtmlBase::new(body).into())
 }
 
+#[derive(RwebResponse)]
+#[response(description = "Calendar Stats")]
+struct CalendarStatsResponse(JsonBase<CalendarStatsWrapper, Error>);
+
+#[get("/calendar/stats")]
+#[openapi(description = "Calendar Stats")]
+pub async fn calendar_stats(
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
+    #[data] data: AppState,
+) -> WarpResult<CalendarStatsResponse> {
+    caller.check_admin()?;
+    let stats = get_calendar_stats(&data.cal_sync).await?;
+    Ok(JsonBase::new(stats).into())
+}
+
+async fn get_calendar_stats(cal_sync: &CalendarSync) -> HttpResult<CalendarStatsWrapper> {
+    let stats = CalendarStats::get(&cal_sync.pool).await?;
+    Ok(stats.into())
+}
+
 #[derive(Serialize, Deserialize, Debug, Schema)]
 #[schema(component = "GcalEventID")]
 pub struct GcalEventID {
@@ -132,19 +249,31 @@ pub async fn delete_event(
     #[data] data: AppState,
 ) -> WarpResult<DeleteEventResponse> {
     let payload = payload.into_inner();
-    let body = delete_event_body(payload, &data.cal_sync).await?;
+    let body = delete_event_body(payload, &data.cal_sync, &data.attachments).await?;
     Ok(HtmlBase::new(body).into())
 }
 
 async fn delete_event_body(
     payload: GcalEventID,
     cal_sync: &CalendarSync,
+    attachments: &AttachmentStore,
 ) -> HttpResult<StackString> {
+    check_calendar_writable(&payload.gcal_id, cal_sync).await?;
     let body = if let Some(event) =
         CalendarCache::get_by_gcal_id_event_id(&payload.gcal_id, &payload.event_id, &cal_sync.pool)
             .await?
     {
         let body = format_sstr!("delete {} {}", &payload.gcal_id, &payload.event_id);
+        for attachment in EventAttachment::get_by_gcal_id_event_id(
+            &payload.gcal_id,
+            &payload.event_id,
+            &cal_sync.pool,
+        )
+        .await?
+        {
+            attachments.delete(&attachment.storage_key).await?;
+            attachment.delete(&cal_sync.pool).await?;
+        }
         event.delete(&cal_sync.pool).await?;
         cal_sync
             .gcal
@@ -159,6 +288,17 @@ async fn delete_event_body(
     Ok(body)
 }
 
+/// gcal rejects changes to calendars we only have read access to, so refuse
+/// them up front instead of failing on the next sync
+async fn check_calendar_writable(gcal_id: &str, cal_sync: &CalendarSync) -> HttpResult<()> {
+    match CalendarList::get_by_gcal_id(gcal_id, &cal_sync.pool).await? {
+        Some(calendar) if !calendar.is_writable() => Err(Error::BadRequest(format_sstr!(
+            "Calendar {gcal_id} is read-only"
+        ))),
+        _ => Ok(()),
+    }
+}
+
 #[derive(RwebResponse)]
 #[response(description = "List Calendars", content = "html")]
 struct ListCalendarsResponse(HtmlBase<StackString, Error>);
@@ -190,6 +330,27 @@ async fn get_calendars_list(cal_sync: &CalendarSync) -> HttpResult<StackString>
     Ok(body)
 }
 
+#[derive(RwebResponse)]
+#[response(description = "Scraper Health", content = "html")]
+struct ScrapeHealthResponse(HtmlBase<StackString, Error>);
+
+#[get("/calendar/scrape_health")]
+#[openapi(description = "Scraper Health Over the Last 30 Days")]
+pub async fn scrape_health(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ScrapeHealthResponse> {
+    let body = get_scrape_health(&data.cal_sync).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn get_scrape_health(cal_sync: &CalendarSync) -> HttpResult<StackString> {
+    let min_time = OffsetDateTime::now_utc() - Duration::days(30);
+    let runs = ScrapeRun::get_since(min_time, &cal_sync.pool).await?;
+    let body = scrape_health_body(runs, cal_sync.config.clone())?.into();
+    Ok(body)
+}
+
 #[derive(Serialize, Deserialize, Schema)]
 pub struct ListEventsRequest {
     #[schema(description = "Calendar Name")]
@@ -208,16 +369,18 @@ struct ListEventsResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "List Events")]
 pub async fn list_events(
     query: Query<ListEventsRequest>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "PageRequest::filter"] request: PageRequest,
     #[data] data: AppState,
 ) -> WarpResult<ListEventsResponse> {
+    let access = request.authorize(&data.signer)?;
     let query = query.into_inner();
-    let body = get_events_list(query, &data.cal_sync).await?;
+    let body = get_events_list(query, &access, &data.cal_sync).await?;
     Ok(HtmlBase::new(body).into())
 }
 
 async fn get_events_list(
     query: ListEventsRequest,
+    access: &PageAccess,
     cal_sync: &CalendarSync,
 ) -> HttpResult<StackString> {
     let calendars: Vec<_> = cal_sync.list_calendars().await?.try_collect().await?;
@@ -233,7 +396,8 @@ async fn get_events_list(
         .list_events(&calendar.gcal_id, min_time, max_time)
         .await?;
     events.sort_by_key(|event| event.start_time);
-    let body = list_events_body(calendar, events, cal_sync.config.clone())?.into();
+    let body =
+        list_events_body(calendar, events, cal_sync.config.clone(), access.can_edit())?.into();
     Ok(body)
 }
 
@@ -245,24 +409,34 @@ struct EventDetailResponse(HtmlBase<StackString, Error>);
 #[openapi(description = "Get Calendar Event Detail")]
 pub async fn event_detail(
     payload: Query<GcalEventID>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "PageRequest::filter"] request: PageRequest,
     #[data] data: AppState,
 ) -> WarpResult<EventDetailResponse> {
+    let access = request.authorize(&data.signer)?;
     let payload = payload.into_inner();
-    let body = get_event_detail(payload, &data.cal_sync).await?;
+    let body = get_event_detail(payload, &access, &data).await?;
     Ok(HtmlBase::new(body).into())
 }
 
 async fn get_event_detail(
     payload: GcalEventID,
-    cal_sync: &CalendarSync,
+    access: &PageAccess,
+    data: &AppState,
 ) -> HttpResult<StackString> {
+    let cal_sync = &data.cal_sync;
     let body = if let Some(event) =
         CalendarCache::get_by_gcal_id_event_id(&payload.gcal_id, &payload.event_id, &cal_sync.pool)
             .await?
     {
+        let editable = CalendarList::get_by_gcal_id(&payload.gcal_id, &cal_sync.pool)
+            .await?
+            .map_or(false, |calendar| calendar.edit)
+            && access.can_edit();
+        let link_lifetime = access.link_lifetime(Duration::days(ATTACHMENT_LINK_DAYS));
+        let attachments =
+            get_attachments(&payload.gcal_id, &payload.event_id, link_lifetime, data).await?;
         let event: Event = event.into();
-        event_detail_body(event, cal_sync.config.clone())?.into()
+        event_detail_body(event, cal_sync.config.clone(), attachments, editable)?.into()
     } else {
         "".into()
     };
@@ -295,20 +469,21 @@ struct CalendarListResponse(JsonBase<PaginatedCalendarList, Error>);
 #[openapi(description = "List Calendars")]
 pub async fn calendar_list(
     query: Query<MinModifiedQuery>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
     #[data] data: AppState,
 ) -> WarpResult<CalendarListResponse> {
     let query = query.into_inner();
-    let result = calendar_list_object(&query, &data.cal_sync).await?;
+    let result = calendar_list_object(&query, caller.calendar_scope(), &data.cal_sync).await?;
     Ok(JsonBase::new(result).into())
 }
 
 async fn calendar_list_object(
     query: &MinModifiedQuery,
+    gcal_id: Option<&StackString>,
     cal_sync: &CalendarSync,
 ) -> HttpResult<PaginatedCalendarList> {
     let min_modified = query.min_modified.map(Into::into);
-    let total = CalendarList::get_total(&cal_sync.pool, min_modified).await?;
+    let total = CalendarList::get_total(&cal_sync.pool, min_modified, gcal_id).await?;
     let limit = query.limit.unwrap_or(10);
     let offset = query.offset.unwrap_or(0);
     let pagination = Pagination {
@@ -316,11 +491,17 @@ async fn calendar_list_object(
         offset,
         total,
     };
-    let data = CalendarList::get_recent(&cal_sync.pool, min_modified, Some(offset), Some(limit))
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?;
+    let data = CalendarList::get_recent(
+        &cal_sync.pool,
+        min_modified,
+        gcal_id,
+        Some(offset),
+        Some(limit),
+    )
+    .await?
+    .map_ok(Into::into)
+    .try_collect()
+    .await?;
     Ok(PaginatedCalendarList { pagination, data })
 }
 
@@ -339,10 +520,11 @@ struct CalendarListUpdateResponse(JsonBase<Vec<CalendarListWrapper>, Error>);
 #[openapi(description = "Update Calendars")]
 pub async fn calendar_list_update(
     payload: Json<CalendarUpdateRequest>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
     #[data] data: AppState,
 ) -> WarpResult<CalendarListUpdateResponse> {
     let payload = payload.into_inner();
+    caller.check_write(payload.updates.iter().map(|c| c.0.gcal_id.as_str()))?;
     let calendars = calendar_list_update_object(payload, &data.cal_sync).await?;
     Ok(JsonBase::new(calendars).into())
 }
@@ -370,7 +552,7 @@ async fn calendar_list_update_object(
 #[schema(component = "PaginatedCalendarCache")]
 struct PaginatedCalendarCache {
     pagination: Pagination,
-    data: Vec<CalendarCacheWrapper>,
+    data: Vec<CalendarCacheSummary>,
 }
 
 #[derive(RwebResponse)]
@@ -380,21 +562,22 @@ struct CalendarCacheResponse(JsonBase<PaginatedCalendarCache, Error>);
 #[get("/calendar/calendar_cache")]
 #[openapi(description = "List Recent Calendar Events")]
 pub async fn calendar_cache(
-    query: Query<MinModifiedQuery>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    query: Query<CalendarCacheQuery>,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
     #[data] data: AppState,
 ) -> WarpResult<CalendarCacheResponse> {
     let query = query.into_inner();
-    let result = calendar_cache_events(&query, &data.cal_sync).await?;
+    let result = calendar_cache_events(&query, caller.calendar_scope(), &data.cal_sync).await?;
     Ok(JsonBase::new(result).into())
 }
 
 async fn calendar_cache_events(
-    query: &MinModifiedQuery,
+    query: &CalendarCacheQuery,
+    gcal_id: Option<&StackString>,
     cal_sync: &CalendarSync,
 ) -> HttpResult<PaginatedCalendarCache> {
     let min_modified = query.min_modified.map(Into::into);
-    let total = CalendarCache::get_total(&cal_sync.pool, min_modified).await?;
+    let total = CalendarCache::get_total(&cal_sync.pool, min_modified, gcal_id).await?;
     let limit = query.limit.unwrap_or(10);
     let offset = query.offset.unwrap_or(0);
     let pagination = Pagination {
@@ -402,14 +585,64 @@ async fn calendar_cache_events(
         offset,
         total,
     };
-    let data = CalendarCache::get_recent(&cal_sync.pool, min_modified, Some(offset), Some(limit))
-        .await?
-        .map_ok(Into::into)
-        .try_collect()
-        .await?;
+    let source_id = cal_sync
+        .config
+        .source_id
+        .as_ref()
+        .unwrap_or(&cal_sync.config.domain);
+    let mode = query.description.unwrap_or_default();
+    let max_length = cal_sync.config.description_summary_length;
+    let data = CalendarCache::get_recent(
+        &cal_sync.pool,
+        min_modified,
+        gcal_id,
+        Some(offset),
+        Some(limit),
+    )
+    .await?
+    .map_ok(|mut event| {
+        // Local edits are attributed to this deployment, so that another
+        // deployment merging them decides on the same clock
+        if event.source_id.is_none() {
+            event.source_id = Some(source_id.clone());
+            event.source_modified = Some(event.last_modified);
+        }
+        CalendarCacheSummary::new(event, mode, max_length)
+    })
+    .try_collect()
+    .await?;
     Ok(PaginatedCalendarCache { pagination, data })
 }
 
+#[derive(RwebResponse)]
+#[response(description = "Calendar Event")]
+struct CalendarCacheEventResponse(JsonBase<CalendarCacheWrapper, Error>);
+
+#[get("/calendar/calendar_cache/event")]
+#[openapi(description = "Get a Single Calendar Event with its Full Description")]
+pub async fn calendar_cache_event(
+    query: Query<GcalEventID>,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
+    #[data] data: AppState,
+) -> WarpResult<CalendarCacheEventResponse> {
+    let query = query.into_inner();
+    if !caller.allows_calendar(&query.gcal_id) {
+        return Err(Error::Unauthorized.into());
+    }
+    let event = calendar_cache_event_body(&query, &data.cal_sync).await?;
+    Ok(JsonBase::new(event).into())
+}
+
+async fn calendar_cache_event_body(
+    query: &GcalEventID,
+    cal_sync: &CalendarSync,
+) -> HttpResult<CalendarCacheWrapper> {
+    CalendarCache::get_by_gcal_id_event_id(&query.gcal_id, &query.event_id, &cal_sync.pool)
+        .await?
+        .map(Into::into)
+        .ok_or_else(|| Error::BadRequest("Event not found".into()))
+}
+
 #[derive(Serialize, Deserialize, Schema)]
 #[schema(component = "CalendarCacheUpdateRequest")]
 pub struct CalendarCacheUpdateRequest {
@@ -425,10 +658,11 @@ struct CalendarCacheUpdateResponse(JsonBase<Vec<CalendarCacheWrapper>, Error>);
 #[openapi(description = "Update Calendar Events")]
 pub async fn calendar_cache_update(
     payload: Json<CalendarCacheUpdateRequest>,
-    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
     #[data] data: AppState,
 ) -> WarpResult<CalendarCacheUpdateResponse> {
     let payload = payload.into_inner();
+    caller.check_write(payload.updates.iter().map(|e| e.gcal_id.as_str()))?;
     let events = calendar_cache_update_events(payload, &data.cal_sync).await?;
     Ok(JsonBase::new(events).into())
 }
@@ -437,15 +671,44 @@ async fn calendar_cache_update_events(
     payload: CalendarCacheUpdateRequest,
     cal_sync: &CalendarSync,
 ) -> HttpResult<Vec<CalendarCacheWrapper>> {
+    // Replication batches include events from read-only calendars, those are
+    // stored (as they are in gcal) but marked as not editable
+    let gcal_ids: HashSet<_> = payload.updates.iter().map(|e| &e.gcal_id).collect();
+    let mut read_only = HashSet::new();
+    for gcal_id in gcal_ids {
+        if let Some(calendar) = CalendarList::get_by_gcal_id(gcal_id, &cal_sync.pool).await? {
+            if !calendar.is_writable() {
+                read_only.insert(gcal_id.clone());
+            }
+        }
+    }
+    let source_id = cal_sync
+        .config
+        .source_id
+        .as_ref()
+        .unwrap_or(&cal_sync.config.domain);
     let futures: FuturesUnordered<_> = payload
         .updates
         .into_iter()
         .map(|event| {
             let pool = cal_sync.pool.clone();
-            let event: CalendarCache = event.into();
+            let mut event: CalendarCache = event.into();
+            if read_only.contains(&event.gcal_id) {
+                event.editable = false;
+            }
             async move {
-                event.upsert(&pool).await?;
-                Ok(event.into())
+                // Return the version that was kept, so callers can tell when
+                // their update lost to a newer edit
+                match event.merge(source_id, &pool).await? {
+                    Some(existing) => {
+                        info!(
+                            "calendar_cache conflict {} {}, kept {:?} over {:?}",
+                            event.gcal_id, event.event_id, existing.source_id, event.source_id
+                        );
+                        Ok(existing.into())
+                    }
+                    None => Ok(event.into()),
+                }
             }
         })
         .collect();
@@ -537,11 +800,15 @@ async fn build_calendar_event_body(
     query: BuildEventRequest,
     cal_sync: &CalendarSync,
 ) -> HttpResult<StackString> {
+    check_calendar_writable(&query.gcal_id, cal_sync).await?;
     let event = if let Some(event_id) = &query.event_id {
         CalendarCache::get_by_gcal_id_event_id(&query.gcal_id, event_id, &cal_sync.pool).await?
     } else {
         None
     };
+    if event.as_ref().map_or(false, |event| !event.editable) {
+        return Err(Error::BadRequest("Event is read-only".into()));
+    }
     let event = event.map_or_else(
         || {
             Event::new(
@@ -581,6 +848,16 @@ async fn create_calendar_event_body(
     payload: CreateCalendarEventRequest,
     cal_sync: &CalendarSync,
 ) -> HttpResult<String> {
+    check_calendar_writable(&payload.gcal_id, cal_sync).await?;
+    // gcal would reject the update on the next sync
+    if let Some(existing) =
+        CalendarCache::get_by_gcal_id_event_id(&payload.gcal_id, &payload.event_id, &cal_sync.pool)
+            .await?
+    {
+        if !existing.editable {
+            return Err(Error::BadRequest("Event is read-only".into()));
+        }
+    }
     let local = TimeZone::local().into();
     let start_datetime = payload.event_start_datetime.to_timezone(local);
     let end_datetime = payload.event_end_datetime.to_timezone(local);
@@ -597,6 +874,9 @@ async fn create_calendar_event_body(
         event_location_lat: None,
         event_location_lon: None,
         last_modified: OffsetDateTime::now_utc().into(),
+        source_id: None,
+        source_modified: None,
+        editable: true,
     };
 
     event.upsert(&cal_sync.pool).await?;
@@ -662,6 +942,11 @@ async fn edit_calendar_list(
         calendar.sync = sync;
     }
     if let Some(edit) = query.edit {
+        if edit && !calendar.is_writable() {
+            return Err(Error::BadRequest(format_sstr!(
+                "Calendar {gcal_id} is read-only"
+            )));
+        }
         calendar.edit = edit;
     }
     let calendar = if let Some(display) = query.display {
@@ -674,3 +959,1037 @@ async fn edit_calendar_list(
     calendar.update(&cal_sync.pool).await?;
     Ok(calendar.into())
 }
+
+const ATTACHMENT_LINK_DAYS: i64 = 7;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Schema)]
+#[schema(component = "EventAttachmentInfo")]
+pub struct EventAttachmentInfo {
+    #[schema(description = "Attachment ID")]
+    pub attachment_id: StackString,
+    #[schema(description = "File Name")]
+    pub filename: StackString,
+    #[schema(description = "Content Type")]
+    pub content_type: StackString,
+    #[schema(description = "Size in Bytes")]
+    pub size_bytes: i64,
+    #[schema(description = "Signed Download URL")]
+    pub url: StackString,
+    #[schema(description = "Ticket Flag")]
+    pub is_ticket: bool,
+}
+
+impl EventAttachmentInfo {
+    /// The download url is signed for `valid_for`, which shouldn't outlive
+    /// the page the link is shown on.
+    fn new(attachment: EventAttachment, signer: &UrlSigner, valid_for: Duration) -> Self {
+        let path = attachment_path(attachment.attachment_id);
+        Self {
+            attachment_id: StackString::from_display(attachment.attachment_id),
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            size_bytes: attachment.size_bytes,
+            url: signer.sign(&path, valid_for),
+            is_ticket: attachment.is_ticket,
+        }
+    }
+}
+
+fn attachment_path(attachment_id: Uuid) -> StackString {
+    format_sstr!("/calendar/attachment/{attachment_id}")
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Event Attachments")]
+struct ListAttachmentsResponse(JsonBase<Vec<EventAttachmentInfo>, Error>);
+
+#[get("/calendar/attachments")]
+#[openapi(description = "List Event Attachments")]
+pub async fn list_attachments(
+    query: Query<GcalEventID>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ListAttachmentsResponse> {
+    let query = query.into_inner();
+    let attachments = get_attachments(
+        &query.gcal_id,
+        &query.event_id,
+        Duration::days(ATTACHMENT_LINK_DAYS),
+        &data,
+    )
+    .await?;
+    Ok(JsonBase::new(attachments).into())
+}
+
+async fn get_attachments(
+    gcal_id: &str,
+    event_id: &str,
+    valid_for: Duration,
+    data: &AppState,
+) -> HttpResult<Vec<EventAttachmentInfo>> {
+    let attachments =
+        EventAttachment::get_by_gcal_id_event_id(gcal_id, event_id, &data.cal_sync.pool).await?;
+    Ok(attachments
+        .into_iter()
+        .map(|a| EventAttachmentInfo::new(a, &data.signer, valid_for))
+        .collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "AttachmentUploadRequest")]
+pub struct AttachmentUploadRequest {
+    #[schema(description = "GCal ID")]
+    pub gcal_id: StackString,
+    #[schema(description = "GCal Event ID")]
+    pub event_id: StackString,
+    #[schema(description = "File Name")]
+    pub filename: StackString,
+    #[schema(description = "Content Type")]
+    pub content_type: Option<StackString>,
+    #[schema(description = "Base64 Encoded File Contents")]
+    pub content: StackString,
+    #[schema(description = "Ticket Flag (detected from contents if not set)")]
+    pub is_ticket: Option<bool>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Uploaded Attachment", status = "CREATED")]
+struct UploadAttachmentResponse(JsonBase<EventAttachmentInfo, Error>);
+
+#[post("/calendar/attachments")]
+#[openapi(description = "Upload Attachment for Local Event")]
+pub async fn upload_attachment(
+    payload: Json<AttachmentUploadRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<UploadAttachmentResponse> {
+    let payload = payload.into_inner();
+    let attachment = upload_attachment_body(payload, &data).await?;
+    Ok(JsonBase::new(attachment).into())
+}
+
+async fn upload_attachment_body(
+    payload: AttachmentUploadRequest,
+    data: &AppState,
+) -> HttpResult<EventAttachmentInfo> {
+    let pool = &data.cal_sync.pool;
+    let Some(calendar) = CalendarList::get_by_gcal_id(&payload.gcal_id, pool).await? else {
+        return Err(Error::BadRequest("No such calendar".into()));
+    };
+    if !calendar.edit || !calendar.is_writable() {
+        return Err(Error::BadRequest(
+            "Attachments are only allowed on editable calendars".into(),
+        ));
+    }
+    let Some(event) =
+        CalendarCache::get_by_gcal_id_event_id(&payload.gcal_id, &payload.event_id, pool).await?
+    else {
+        return Err(Error::BadRequest("No such event".into()));
+    };
+    if !event.editable {
+        return Err(Error::BadRequest(
+            "Attachments are only allowed on editable events".into(),
+        ));
+    }
+    let content = STANDARD
+        .decode(payload.content.as_bytes())
+        .map_err(|e| Error::BadRequest(format_sstr!("Invalid attachment content: {e}")))?;
+    let max_size = data.cal_sync.config.max_attachment_size;
+    if content.len() > max_size {
+        return Err(Error::BadRequest(format_sstr!(
+            "Attachment is larger than {max_size} bytes"
+        )));
+    }
+    let content_type = payload
+        .content_type
+        .filter(|c| !c.is_empty())
+        .unwrap_or_else(|| "application/octet-stream".into());
+    // QR decoding is CPU bound, keep it off the async workers
+    let filename = payload.filename.clone();
+    let detect_content_type = content_type.clone();
+    let (content, (is_ticket, barcode_payload)) = spawn_blocking(move || {
+        let detected = detect_ticket(&filename, &detect_content_type, &content);
+        (content, detected)
+    })
+    .await?;
+    let mut attachment = EventAttachment::new(
+        payload.gcal_id,
+        payload.event_id,
+        payload.filename,
+        content_type,
+        content.len(),
+    );
+    attachment.is_ticket = payload.is_ticket.unwrap_or(is_ticket);
+    attachment.barcode_payload = barcode_payload;
+    data.attachments
+        .put(&attachment.storage_key, content)
+        .await?;
+    attachment.insert(pool).await?;
+    Ok(EventAttachmentInfo::new(
+        attachment,
+        &data.signer,
+        Duration::days(ATTACHMENT_LINK_DAYS),
+    ))
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "AttachmentID")]
+pub struct AttachmentID {
+    #[schema(description = "Attachment ID")]
+    pub attachment_id: StackString,
+}
+
+#[derive(RwebResponse)]
+#[response(
+    description = "Delete Attachment Output",
+    content = "html",
+    status = "NO_CONTENT"
+)]
+struct DeleteAttachmentResponse(HtmlBase<StackString, Error>);
+
+#[delete("/calendar/attachments")]
+#[openapi(description = "Delete Event Attachment")]
+pub async fn delete_attachment(
+    payload: Json<AttachmentID>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<DeleteAttachmentResponse> {
+    let payload = payload.into_inner();
+    let body = delete_attachment_body(&payload.attachment_id, &data).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn delete_attachment_body(attachment_id: &str, data: &AppState) -> HttpResult<StackString> {
+    let attachment_id: Uuid = attachment_id
+        .parse()
+        .map_err(|_| Error::BadRequest("Invalid attachment id".into()))?;
+    let body = if let Some(attachment) =
+        EventAttachment::get_by_id(attachment_id, &data.cal_sync.pool).await?
+    {
+        check_calendar_writable(&attachment.gcal_id, &data.cal_sync).await?;
+        data.attachments.delete(&attachment.storage_key).await?;
+        attachment.delete(&data.cal_sync.pool).await?;
+        format_sstr!("delete {}", attachment.filename)
+    } else {
+        "Attachment not deleted".into()
+    };
+    Ok(body)
+}
+
+#[derive(Deserialize)]
+struct SignedQuery {
+    expires: i64,
+    signature: StackString,
+}
+
+/// Public download route, access is controlled by the signature in the url
+/// rather than the login cookie so links work on shared pages.
+pub fn attachment_download(app: AppState) -> BoxedFilter<(impl Reply,)> {
+    rweb::path!("calendar" / "attachment" / Uuid)
+        .and(rweb::path::end())
+        .and(rweb::get())
+        .and(rweb::query::<SignedQuery>())
+        .and(rweb::any().map(move || app.clone()))
+        .and_then(
+            |attachment_id: Uuid, query: SignedQuery, data: AppState| async move {
+                attachment_download_body(attachment_id, query, &data)
+                    .await
+                    .map_err(rweb::reject::custom)
+            },
+        )
+        .boxed()
+}
+
+/// Content types the browser may display in place, anything else (html, svg,
+/// ...) is only ever downloaded so an upload can't run script on our origin
+const INLINE_CONTENT_TYPES: [&str; 5] = [
+    "application/pdf",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+];
+
+async fn attachment_download_body(
+    attachment_id: Uuid,
+    query: SignedQuery,
+    data: &AppState,
+) -> HttpResult<Response<Vec<u8>>> {
+    let path = attachment_path(attachment_id);
+    if !data.signer.verify(&path, query.expires, &query.signature) {
+        return Err(Error::Unauthorized);
+    }
+    let Some(attachment) = EventAttachment::get_by_id(attachment_id, &data.cal_sync.pool).await?
+    else {
+        return Err(Error::BadRequest("No such attachment".into()));
+    };
+    let content = data.attachments.get(&attachment.storage_key).await?;
+    let filename: String = attachment
+        .filename
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ' '))
+        .collect();
+    let disposition = if INLINE_CONTENT_TYPES.contains(&attachment.content_type.as_str()) {
+        "inline"
+    } else {
+        "attachment"
+    };
+    Response::builder()
+        .header(CONTENT_TYPE, attachment.content_type.as_str())
+        .header(
+            CONTENT_DISPOSITION,
+            format_sstr!(r#"{disposition}; filename="{filename}""#).as_str(),
+        )
+        .header(X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(content)
+        .map_err(|e| format_err!("{e}").into())
+}
+
+/// Key of the single `lookup_limiter` bucket
+const LOOKUP_KEY: &str = "lookup";
+
+/// Apply the public rate limit and look up a public feed token, usage is
+/// counted against the token (written out in batches) so the owner can see
+/// it.  Returns the calendar the token grants access to.
+async fn check_feed_token(token: &str, data: &AppState) -> HttpResult<StackString> {
+    // Tokens that resolved before have their own bucket, anything else shares
+    // the lookup limit so guessed tokens can't hammer the db
+    let token_hash = hash_token(token);
+    let known = data.public_limiter.check_existing(&token_hash);
+    if known == Some(false) {
+        data.token_usage.record(&token_hash, true);
+        return Err(Error::TooManyRequests);
+    }
+    if known.is_none() && !data.lookup_limiter.check(LOOKUP_KEY) {
+        return Err(Error::TooManyRequests);
+    }
+    let pool = &data.cal_sync.pool;
+    let Some(feed_token) = AccessToken::get_by_token_hash(&token_hash, pool)
+        .await?
+        .filter(|t| !t.is_api_key)
+    else {
+        return Err(Error::BadRequest("Invalid feed token".into()));
+    };
+    let Some(gcal_id) = feed_token.gcal_id.clone() else {
+        return Err(Error::BadRequest("Invalid feed token".into()));
+    };
+    if known.is_none() && !data.public_limiter.check(&token_hash) {
+        data.token_usage.record(&token_hash, true);
+        return Err(Error::TooManyRequests);
+    }
+    data.token_usage.record(&token_hash, false);
+    Ok(gcal_id)
+}
+
+async fn get_calendar_name(gcal_id: &str, cal_sync: &CalendarSync) -> HttpResult<StackString> {
+    let calendar = CalendarList::get_by_gcal_id(gcal_id, &cal_sync.pool).await?;
+    Ok(calendar.map_or_else(|| gcal_id.into(), |c| c.calendar_name))
+}
+
+/// Public ics feed, plain filter as the body isn't html or json.
+pub fn calendar_feed(app: AppState) -> BoxedFilter<(impl Reply,)> {
+    rweb::path!("calendar" / "feed" / StackString / "calendar.ics")
+        .and(rweb::path::end())
+        .and(rweb::get())
+        .and(rweb::any().map(move || app.clone()))
+        .and_then(|token: StackString, data: AppState| async move {
+            calendar_feed_body(&token, &data)
+                .await
+                .map_err(rweb::reject::custom)
+        })
+        .boxed()
+}
+
+async fn calendar_feed_body(token: &str, data: &AppState) -> HttpResult<Response<String>> {
+    let gcal_id = check_feed_token(token, data).await?;
+    let cal_sync = &data.cal_sync;
+    let today = OffsetDateTime::now_utc().date();
+    let mut events = cal_sync
+        .list_events(
+            &gcal_id,
+            today.checked_sub(Duration::days(30)),
+            today.checked_add(Duration::days(365)),
+        )
+        .await?;
+    events.sort_by_key(|event| event.start_time);
+    let calendar_name = get_calendar_name(&gcal_id, cal_sync).await?;
+    let body = events_to_ics(&calendar_name, &events);
+    Response::builder()
+        .header(CONTENT_TYPE, "text/calendar; charset=utf-8")
+        .body(body)
+        .map_err(|e| format_err!("{e}").into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Shared Calendar", content = "html")]
+struct SharedCalendarResponse(HtmlBase<String, Error>);
+
+#[get("/calendar/feed/{token}/agenda")]
+#[openapi(description = "Shared Calendar Page (embeddable)")]
+pub async fn shared_calendar(
+    token: StackString,
+    #[data] data: AppState,
+) -> WarpResult<SharedCalendarResponse> {
+    let body = shared_calendar_body(&token, &data).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn shared_calendar_body(token: &str, data: &AppState) -> HttpResult<String> {
+    let gcal_id = check_feed_token(token, data).await?;
+    let cal_sync = &data.cal_sync;
+    let mut events = cal_sync.list_events(&gcal_id, None, None).await?;
+    events.sort_by_key(|event| event.start_time);
+    let mut attachments: HashMap<StackString, Vec<EventAttachmentInfo>> = HashMap::new();
+    for attachment in EventAttachment::get_by_gcal_id(&gcal_id, &cal_sync.pool).await? {
+        attachments
+            .entry(attachment.event_id.clone())
+            .or_default()
+            .push(EventAttachmentInfo::new(
+                attachment,
+                &data.signer,
+                Duration::days(ATTACHMENT_LINK_DAYS),
+            ));
+    }
+    let calendar_name = get_calendar_name(&gcal_id, cal_sync).await?;
+    let body = shared_events_body(calendar_name, events, attachments, cal_sync.config.clone())?;
+    Ok(body)
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Access Tokens")]
+struct AccessTokensResponse(JsonBase<Vec<AccessTokenWrapper>, Error>);
+
+#[get("/calendar/access_tokens")]
+#[openapi(description = "List Feed Tokens and Api Keys with Usage")]
+pub async fn list_access_tokens(
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<AccessTokensResponse> {
+    let tokens = get_access_tokens(&user, &data.cal_sync).await?;
+    Ok(JsonBase::new(tokens).into())
+}
+
+async fn get_access_tokens(
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<Vec<AccessTokenWrapper>> {
+    let tokens = AccessToken::get_by_email(&user.email, &cal_sync.pool).await?;
+    Ok(tokens.into_iter().map(Into::into).collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "AccessTokenRequest")]
+pub struct AccessTokenRequest {
+    #[schema(description = "GCal ID (required for feeds, all calendars if unset for api keys)")]
+    pub gcal_id: Option<StackString>,
+    #[schema(description = "Description")]
+    pub description: Option<StackString>,
+    #[schema(description = "Create an api key rather than a feed token")]
+    pub api_key: Option<bool>,
+    #[schema(description = "Read-only api key (default true)")]
+    pub read_only: Option<bool>,
+    #[schema(description = "Expire after this many days")]
+    pub expires_in_days: Option<i64>,
+}
+
+/// Newly created or rotated token, `token` can't be retrieved again
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "NewAccessToken")]
+pub struct NewAccessToken {
+    #[schema(description = "Feed Token or Api Key (only shown once)")]
+    pub token: StackString,
+    #[schema(description = "Access Token")]
+    pub access_token: AccessTokenWrapper,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Created Access Token", status = "CREATED")]
+struct CreateAccessTokenResponse(JsonBase<NewAccessToken, Error>);
+
+#[post("/calendar/access_tokens")]
+#[openapi(description = "Create Feed Token or Api Key")]
+pub async fn create_access_token(
+    payload: Json<AccessTokenRequest>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<CreateAccessTokenResponse> {
+    let payload = payload.into_inner();
+    let token = create_access_token_body(payload, &user, &data.cal_sync).await?;
+    Ok(JsonBase::new(token).into())
+}
+
+async fn create_access_token_body(
+    payload: AccessTokenRequest,
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<NewAccessToken> {
+    if let Some(gcal_id) = &payload.gcal_id {
+        if CalendarList::get_by_gcal_id(gcal_id, &cal_sync.pool)
+            .await?
+            .is_none()
+        {
+            return Err(Error::BadRequest("No such calendar".into()));
+        }
+    }
+    let (mut access_token, token) = if payload.api_key.unwrap_or(false) {
+        AccessToken::new_api_key(
+            user.email.clone(),
+            payload.gcal_id,
+            payload.read_only.unwrap_or(true),
+            payload.description,
+        )
+    } else {
+        let Some(gcal_id) = payload.gcal_id else {
+            return Err(Error::BadRequest("Feed tokens require a calendar".into()));
+        };
+        AccessToken::new_feed(user.email.clone(), gcal_id, payload.description)
+    };
+    if let Some(days) = payload.expires_in_days {
+        if days <= 0 {
+            return Err(Error::BadRequest("expires_in_days must be positive".into()));
+        }
+        access_token.expires_at = Some((OffsetDateTime::now_utc() + Duration::days(days)).into());
+    }
+    access_token.insert(&cal_sync.pool).await?;
+    Ok(NewAccessToken {
+        token,
+        access_token: access_token.into(),
+    })
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "AccessTokenID")]
+pub struct AccessTokenID {
+    #[schema(description = "Token Hash, as listed in /calendar/access_tokens")]
+    pub token_hash: StackString,
+}
+
+async fn get_owned_token(
+    token_hash: &str,
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<Option<AccessToken>> {
+    let token = AccessToken::get_owned(token_hash, &user.email, &cal_sync.pool).await?;
+    Ok(token)
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Rotated Access Token", status = "CREATED")]
+struct RotateAccessTokenResponse(JsonBase<NewAccessToken, Error>);
+
+#[post("/calendar/access_tokens/rotate")]
+#[openapi(
+    description = "Rotate Feed Token or Api Key, the old value stays valid for a grace period"
+)]
+pub async fn rotate_access_token(
+    payload: Json<AccessTokenID>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<RotateAccessTokenResponse> {
+    let payload = payload.into_inner();
+    let token = rotate_access_token_body(&payload.token_hash, &user, &data.cal_sync).await?;
+    Ok(JsonBase::new(token).into())
+}
+
+async fn rotate_access_token_body(
+    token_hash: &str,
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<NewAccessToken> {
+    let Some(mut access_token) = get_owned_token(token_hash, user, cal_sync).await? else {
+        return Err(Error::BadRequest("No such token".into()));
+    };
+    let grace_period = Duration::hours(cal_sync.config.token_rotation_grace_hours);
+    let token = access_token.rotate(grace_period, &cal_sync.pool).await?;
+    if access_token.is_api_key {
+        fill_api_keys(&cal_sync.pool).await?;
+    }
+    Ok(NewAccessToken {
+        token,
+        access_token: access_token.into(),
+    })
+}
+
+#[derive(RwebResponse)]
+#[response(
+    description = "Delete Access Token Output",
+    content = "html",
+    status = "NO_CONTENT"
+)]
+struct DeleteAccessTokenResponse(HtmlBase<StackString, Error>);
+
+#[delete("/calendar/access_tokens")]
+#[openapi(description = "Revoke Feed Token or Api Key")]
+pub async fn delete_access_token(
+    payload: Json<AccessTokenID>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<DeleteAccessTokenResponse> {
+    let payload = payload.into_inner();
+    let body = delete_access_token_body(&payload.token_hash, &user, &data.cal_sync).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn delete_access_token_body(
+    token_hash: &str,
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<StackString> {
+    if let Some(token) = get_owned_token(token_hash, user, cal_sync).await? {
+        token.delete(&cal_sync.pool).await?;
+        if token.is_api_key {
+            fill_api_keys(&cal_sync.pool).await?;
+        }
+        Ok("revoked token".into())
+    } else {
+        Ok("Token not revoked".into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "ShareLinkRequest")]
+pub struct ShareLinkRequest {
+    #[schema(description = "Page path including query, e.g. /calendar/agenda")]
+    pub path: StackString,
+    #[schema(description = "Link lifetime in minutes (default 60)")]
+    pub expires_in_minutes: Option<i64>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Share Link", content = "html", status = "CREATED")]
+struct ShareLinkResponse(HtmlBase<StackString, Error>);
+
+#[post("/calendar/share_link")]
+#[openapi(description = "Create Short-Lived Signed Link to a Read-Only Page")]
+pub async fn share_link(
+    payload: Json<ShareLinkRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ShareLinkResponse> {
+    let payload = payload.into_inner();
+    let body = share_link_body(payload, &data).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn share_link_body(payload: ShareLinkRequest, data: &AppState) -> HttpResult<StackString> {
+    if !is_shareable(&payload.path) {
+        return Err(Error::BadRequest(format_sstr!(
+            "{} can not be shared",
+            payload.path
+        )));
+    }
+    let config = &data.cal_sync.config;
+    let minutes = payload
+        .expires_in_minutes
+        .unwrap_or(60)
+        .clamp(1, config.share_link_max_minutes.max(1));
+    let signed = data.signer.sign(&payload.path, Duration::minutes(minutes));
+    let url = format_sstr!("https://{}{signed}", config.domain);
+    Ok(format_sstr!(
+        r#"<a href="{url}">{url}</a> (valid for {minutes} minutes)"#
+    ))
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "InboundEventResponse")]
+pub struct InboundEventResponse {
+    #[schema(description = "GCal Calendar ID")]
+    pub gcal_id: StackString,
+    #[schema(description = "Event ID")]
+    pub event_id: StackString,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Inbound Event Stored", status = "CREATED")]
+struct InboundEventResp(JsonBase<InboundEventResponse, Error>);
+
+#[post("/calendar/inbound/{source_token}")]
+#[openapi(description = "Receive Event from External Producer")]
+pub async fn inbound_event(
+    source_token: StackString,
+    payload: Json<InboundEventWrapper>,
+    #[data] data: AppState,
+) -> WarpResult<InboundEventResp> {
+    let payload = payload.into_inner();
+    let body = inbound_event_body(&source_token, payload, &data).await?;
+    Ok(JsonBase::new(body).into())
+}
+
+async fn inbound_event_body(
+    source_token: &str,
+    payload: InboundEventWrapper,
+    data: &AppState,
+) -> HttpResult<InboundEventResponse> {
+    // Sources that resolved before have their own bucket (with the source's
+    // limit), anything else shares the lookup limit like feed tokens do
+    let known = data.public_limiter.check_existing(source_token);
+    if known == Some(false) {
+        return Err(Error::TooManyRequests);
+    }
+    if known.is_none() && !data.lookup_limiter.check(LOOKUP_KEY) {
+        return Err(Error::TooManyRequests);
+    }
+    let pool = &data.cal_sync.pool;
+    let Some(source) = InboundSource::get_by_token(source_token, pool).await? else {
+        return Err(Error::BadRequest("Invalid source token".into()));
+    };
+    let per_minute = u32::try_from(source.rate_limit_per_minute).unwrap_or(1);
+    if known.is_some() {
+        data.public_limiter
+            .set_limit(&source.source_token, per_minute);
+    } else if !data
+        .public_limiter
+        .check_limit(&source.source_token, per_minute)
+    {
+        return Err(Error::TooManyRequests);
+    }
+    let event: InboundEvent = payload.into();
+    if let Err(message) = event.validate(&source) {
+        data.token_usage.record_inbound(&source.source_token, true);
+        return Err(Error::BadRequest(message));
+    }
+    check_calendar_writable(&source.gcal_id, &data.cal_sync).await?;
+    let event = event.into_calendar_cache(&source);
+    event.upsert(pool).await?;
+    data.token_usage.record_inbound(&source.source_token, false);
+    Ok(InboundEventResponse {
+        gcal_id: event.gcal_id,
+        event_id: event.event_id,
+    })
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Inbound Sources")]
+struct InboundSourcesResponse(JsonBase<Vec<InboundSourceWrapper>, Error>);
+
+#[get("/calendar/inbound_sources")]
+#[openapi(description = "List Inbound Event Sources with Usage")]
+pub async fn list_inbound_sources(
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<InboundSourcesResponse> {
+    let sources = get_inbound_sources(&user, &data.cal_sync).await?;
+    Ok(JsonBase::new(sources).into())
+}
+
+async fn get_inbound_sources(
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<Vec<InboundSourceWrapper>> {
+    let sources = InboundSource::get_by_email(&user.email, &cal_sync.pool).await?;
+    Ok(sources.into_iter().map(Into::into).collect())
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "InboundSourceRequest")]
+pub struct InboundSourceRequest {
+    #[schema(description = "Source Name")]
+    pub source_name: StackString,
+    #[schema(description = "GCal ID of the (editable) calendar events are inserted into")]
+    pub gcal_id: StackString,
+    #[schema(
+        description = "Payload fields that must be present (external_id, end_time, \
+                       description, url, location)"
+    )]
+    pub required_fields: Option<Vec<StackString>>,
+    #[schema(description = "Maximum Event Duration in Minutes")]
+    pub max_duration_minutes: Option<i32>,
+    #[schema(description = "Rate Limit per Minute (default 10)")]
+    pub rate_limit_per_minute: Option<i32>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Created Inbound Source", status = "CREATED")]
+struct CreateInboundSourceResponse(JsonBase<InboundSourceWrapper, Error>);
+
+#[post("/calendar/inbound_sources")]
+#[openapi(description = "Create Inbound Event Source")]
+pub async fn create_inbound_source(
+    payload: Json<InboundSourceRequest>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<CreateInboundSourceResponse> {
+    let payload = payload.into_inner();
+    let source = create_inbound_source_body(payload, &user, &data.cal_sync).await?;
+    Ok(JsonBase::new(source).into())
+}
+
+async fn create_inbound_source_body(
+    payload: InboundSourceRequest,
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<InboundSourceWrapper> {
+    match CalendarList::get_by_gcal_id(&payload.gcal_id, &cal_sync.pool).await? {
+        Some(calendar) if calendar.edit && calendar.is_writable() => {}
+        Some(_) => return Err(Error::BadRequest("Calendar is not editable".into())),
+        None => return Err(Error::BadRequest("No such calendar".into())),
+    }
+    let mut source = InboundSource::new(payload.source_name, user.email.clone(), payload.gcal_id);
+    if let Some(required_fields) = payload.required_fields {
+        if let Some(field) = required_fields
+            .iter()
+            .find(|f| !OPTIONAL_FIELDS.contains(&f.as_str()))
+        {
+            return Err(Error::BadRequest(format_sstr!("Unknown field {field}")));
+        }
+        source.required_fields = required_fields;
+    }
+    if let Some(max_duration_minutes) = payload.max_duration_minutes {
+        if max_duration_minutes <= 0 {
+            return Err(Error::BadRequest(
+                "max_duration_minutes must be positive".into(),
+            ));
+        }
+        source.max_duration_minutes = Some(max_duration_minutes);
+    }
+    if let Some(rate_limit_per_minute) = payload.rate_limit_per_minute {
+        if rate_limit_per_minute <= 0 {
+            return Err(Error::BadRequest(
+                "rate_limit_per_minute must be positive".into(),
+            ));
+        }
+        source.rate_limit_per_minute = rate_limit_per_minute;
+    }
+    source.insert(&cal_sync.pool).await?;
+    Ok(source.into())
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "InboundSourceID")]
+pub struct InboundSourceID {
+    #[schema(description = "Source Token")]
+    pub source_token: StackString,
+}
+
+#[derive(RwebResponse)]
+#[response(
+    description = "Delete Inbound Source Output",
+    content = "html",
+    status = "NO_CONTENT"
+)]
+struct DeleteInboundSourceResponse(HtmlBase<StackString, Error>);
+
+#[delete("/calendar/inbound_sources")]
+#[openapi(description = "Delete Inbound Event Source")]
+pub async fn delete_inbound_source(
+    payload: Json<InboundSourceID>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<DeleteInboundSourceResponse> {
+    let payload = payload.into_inner();
+    let body = delete_inbound_source_body(&payload.source_token, &user, &data.cal_sync).await?;
+    Ok(HtmlBase::new(body).into())
+}
+
+async fn delete_inbound_source_body(
+    source_token: &str,
+    user: &LoggedUser,
+    cal_sync: &CalendarSync,
+) -> HttpResult<StackString> {
+    let source = InboundSource::get_by_token(source_token, &cal_sync.pool)
+        .await?
+        .filter(|s| s.email == user.email);
+    if let Some(source) = source {
+        source.delete(&cal_sync.pool).await?;
+        Ok("deleted source".into())
+    } else {
+        Ok("Source not deleted".into())
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Integration Status", content = "html")]
+struct IntegrationStatusResponse(HtmlBase<StackString, Error>);
+
+#[get("/calendar/integrations/status")]
+#[openapi(description = "Integration Health Check")]
+pub async fn integration_status() -> WarpResult<IntegrationStatusResponse> {
+    Ok(HtmlBase::new("ok".into()).into())
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "IntegrationUser")]
+pub struct IntegrationUser {
+    #[schema(description = "User ID")]
+    pub id: StackString,
+    #[schema(description = "Display Name")]
+    pub name: StackString,
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "IntegrationUserData")]
+pub struct IntegrationUserData {
+    #[schema(description = "Authenticated User")]
+    pub data: IntegrationUser,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Integration Auth Check")]
+struct IntegrationMeResponse(JsonBase<IntegrationUserData, Error>);
+
+#[get("/calendar/integrations/me")]
+#[openapi(description = "Integration Auth Check (Zapier test / IFTTT user info)")]
+pub async fn integration_me(
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
+) -> WarpResult<IntegrationMeResponse> {
+    let data = match caller {
+        ApiCaller::User(user) => IntegrationUser {
+            id: user.email.clone(),
+            name: user.email,
+        },
+        ApiCaller::ApiKey(key) => IntegrationUser {
+            name: key.description.unwrap_or_else(|| key.email.clone()),
+            id: key.email,
+        },
+    };
+    Ok(JsonBase::new(IntegrationUserData { data }).into())
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+#[schema(component = "IntegrationEvents")]
+pub struct IntegrationEvents {
+    #[schema(description = "Events, newest first")]
+    pub data: Vec<IntegrationEvent>,
+}
+
+#[derive(RwebResponse)]
+#[response(description = "New Events")]
+struct IntegrationNewEventResponse(JsonBase<IntegrationEvents, Error>);
+
+#[get("/calendar/integrations/triggers/new_event")]
+#[openapi(description = "Polling Trigger for New or Updated Events")]
+pub async fn integration_new_event(
+    query: Query<NewEventQuery>,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
+    #[data] data: AppState,
+) -> WarpResult<IntegrationNewEventResponse> {
+    let query = query.into_inner();
+    let events = integration_new_event_body(query, &caller, &data.cal_sync).await?;
+    Ok(JsonBase::new(events).into())
+}
+
+async fn integration_new_event_body(
+    query: NewEventQuery,
+    caller: &ApiCaller,
+    cal_sync: &CalendarSync,
+) -> HttpResult<IntegrationEvents> {
+    let limit = query.limit.unwrap_or(50).min(100);
+    let mode = query.description.unwrap_or_default();
+    let max_length = cal_sync.config.description_summary_length;
+    // A key scoped to one calendar polls that calendar, so the limit isn't
+    // used up by events the key can't see
+    let gcal_id = match (query.gcal_id, caller) {
+        (Some(gcal_id), _) => Some(gcal_id),
+        (None, ApiCaller::ApiKey(key)) => key.gcal_id.clone(),
+        (None, ApiCaller::User(_)) => None,
+    };
+    if let Some(gcal_id) = &gcal_id {
+        if !caller.allows_calendar(gcal_id) {
+            return Err(Error::BadRequest(format_sstr!(
+                "Api key not valid for calendar {gcal_id}"
+            )));
+        }
+    }
+    let data = CalendarCache::get_latest(gcal_id.as_deref(), limit, &cal_sync.pool)
+        .await?
+        .into_iter()
+        .map(|event| {
+            let mut event: IntegrationEvent = event.into();
+            event.apply_description(mode, max_length);
+            event
+        })
+        .collect();
+    Ok(IntegrationEvents { data })
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sample New Event")]
+struct IntegrationSampleResponse(JsonBase<IntegrationEvents, Error>);
+
+#[get("/calendar/integrations/triggers/new_event/sample")]
+#[openapi(description = "Static Sample for the New Event Trigger")]
+pub async fn integration_new_event_sample() -> WarpResult<IntegrationSampleResponse> {
+    Ok(JsonBase::new(sample_integration_events()).into())
+}
+
+fn sample_integration_events() -> IntegrationEvents {
+    let start_time = datetime!(2024-06-01 18:00:00 UTC);
+    let id: StackString = "sample@group.calendar.google.com/sampleevent0001".into();
+    IntegrationEvents {
+        data: vec![IntegrationEvent {
+            meta: IntegrationMeta {
+                id: id.clone(),
+                timestamp: start_time.unix_timestamp(),
+            },
+            id,
+            gcal_id: "sample@group.calendar.google.com".into(),
+            event_id: "sampleevent0001".into(),
+            name: "Sample Event".into(),
+            start_time: start_time.into(),
+            end_time: (start_time + Duration::hours(1)).into(),
+            description: Some("Sample event description".into()),
+            location: Some("Central Park".into()),
+            url: None,
+            description_truncated: false,
+        }],
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Created Event", status = "CREATED")]
+struct IntegrationCreateEventResponse(JsonBase<IntegrationEvents, Error>);
+
+#[post("/calendar/integrations/actions/create_event")]
+#[openapi(description = "Action to Create an Event")]
+pub async fn integration_create_event(
+    payload: Json<IntegrationEventRequest>,
+    #[filter = "ApiCaller::filter"] caller: ApiCaller,
+    #[data] data: AppState,
+) -> WarpResult<IntegrationCreateEventResponse> {
+    let payload = payload.into_inner();
+    let events = integration_create_event_body(payload, &caller, &data.cal_sync).await?;
+    Ok(JsonBase::new(events).into())
+}
+
+async fn integration_create_event_body(
+    payload: IntegrationEventRequest,
+    caller: &ApiCaller,
+    cal_sync: &CalendarSync,
+) -> HttpResult<IntegrationEvents> {
+    caller.check_write(std::iter::once(payload.gcal_id.as_str()))?;
+    match CalendarList::get_by_gcal_id(&payload.gcal_id, &cal_sync.pool).await? {
+        Some(calendar) if calendar.edit && calendar.is_writable() => {}
+        Some(_) => return Err(Error::BadRequest("Calendar is not editable".into())),
+        None => return Err(Error::BadRequest("No such calendar".into())),
+    }
+    if payload.name.trim().is_empty() {
+        return Err(Error::BadRequest("name must not be empty".into()));
+    }
+    let start_time: OffsetDateTime = payload.start_time.into();
+    let end_time: OffsetDateTime = payload
+        .end_time
+        .map_or(start_time + Duration::hours(1), Into::into);
+    if end_time < start_time {
+        return Err(Error::BadRequest("end_time is before start_time".into()));
+    }
+    let event = CalendarCache {
+        gcal_id: payload.gcal_id,
+        event_id: Uuid::new_v4().simple().to_string().into(),
+        event_start_time: start_time.into(),
+        event_end_time: end_time.into(),
+        event_url: payload.url,
+        event_name: payload.name,
+        event_description: payload.description,
+        event_location_name: payload.location,
+        event_location_lat: None,
+        event_location_lon: None,
+        last_modified: OffsetDateTime::now_utc().into(),
+        source_id: None,
+        source_modified: None,
+        editable: true,
+    };
+    event.upsert(&cal_sync.pool).await?;
+    Ok(IntegrationEvents {
+        data: vec![event.into()],
+    })
+}
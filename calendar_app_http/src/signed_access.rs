@@ -0,0 +1,114 @@
+use rweb::{filters::path::FullPath, Filter, Rejection};
+use time::{Duration, OffsetDateTime};
+
+use calendar_app_lib::signed_url::UrlSigner;
+
+use crate::{errors::ServiceError as Error, logged_user::LoggedUser};
+
+/// Read-only pages that may be opened through a signed url
+pub const SHAREABLE_PATHS: [&str; 3] = [
+    "/calendar/agenda",
+    "/calendar/event_detail",
+    "/calendar/list_events",
+];
+
+#[must_use]
+pub fn is_shareable(path: &str) -> bool {
+    let base = path.split_once('?').map_or(path, |(base, _)| base);
+    SHAREABLE_PATHS.contains(&base)
+}
+
+/// Access to a shareable page, either by a logged in user or through a
+/// signed url produced by `/calendar/share_link`.
+#[derive(Debug, Clone)]
+pub enum PageAccess {
+    User(LoggedUser),
+    SignedUrl { expires: OffsetDateTime },
+}
+
+impl PageAccess {
+    /// Only logged in users may change anything, a signed url is read-only
+    #[must_use]
+    pub fn can_edit(&self) -> bool {
+        matches!(self, Self::User(_))
+    }
+
+    /// How long links embedded in the page (e.g. signed attachment urls) may
+    /// stay valid, never past the expiry of the signed url itself.
+    #[must_use]
+    pub fn link_lifetime(&self, max: Duration) -> Duration {
+        match self {
+            Self::User(_) => max,
+            Self::SignedUrl { expires } => max.min(*expires - OffsetDateTime::now_utc()),
+        }
+    }
+}
+
+/// A request for a shareable page, turned into `PageAccess` by `authorize`
+/// with the app's `UrlSigner`.
+pub struct PageRequest {
+    user: Option<LoggedUser>,
+    path: FullPath,
+    query: String,
+}
+
+impl PageRequest {
+    #[must_use]
+    pub fn filter() -> impl Filter<Extract = (Self,), Error = Rejection> + Copy {
+        let user = LoggedUser::filter()
+            .map(Some)
+            .or(rweb::any().map(|| None))
+            .unify();
+        let query = rweb::query::raw().or(rweb::any().map(String::new)).unify();
+        user.and(rweb::path::full())
+            .and(query)
+            .map(|user, path, query| Self { user, path, query })
+    }
+
+    /// # Errors
+    /// Returns `Unauthorized` unless there's a logged in user or the url is a
+    /// valid signed link to a shareable page
+    pub fn authorize(self, signer: &UrlSigner) -> Result<PageAccess, Error> {
+        if let Some(user) = self.user {
+            return Ok(PageAccess::User(user));
+        }
+        let path = self.path.as_str();
+        if !is_shareable(path) {
+            return Err(Error::Unauthorized);
+        }
+        signer
+            .verify_url_expires(path, &self.query)
+            .map(|expires| PageAccess::SignedUrl { expires })
+            .ok_or(Error::Unauthorized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use crate::signed_access::{is_shareable, PageAccess};
+
+    #[test]
+    fn test_is_shareable() {
+        assert!(is_shareable("/calendar/agenda"));
+        assert!(is_shareable("/calendar/list_events?calendar_name=Work"));
+        assert!(!is_shareable("/calendar/calendar_cache"));
+        assert!(!is_shareable("/calendar/agenda_other"));
+    }
+
+    #[test]
+    fn test_link_lifetime() {
+        let max = Duration::days(7);
+        let access = PageAccess::SignedUrl {
+            expires: OffsetDateTime::now_utc() + Duration::minutes(60),
+        };
+        let lifetime = access.link_lifetime(max);
+        assert!(lifetime > Duration::minutes(59) && lifetime <= Duration::minutes(60));
+
+        let access = PageAccess::SignedUrl {
+            expires: OffsetDateTime::now_utc() + Duration::days(30),
+        };
+        assert_eq!(access.link_lifetime(max), max);
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::Error;
+use log::error;
+use parking_lot::Mutex;
+use stack_string::StackString;
+use std::{collections::HashMap, time::Duration};
+use time::OffsetDateTime;
+use tokio::time::interval;
+
+use calendar_app_lib::{
+    models::{AccessToken, InboundSource},
+    pgpool::PgPool,
+};
+
+/// How often counted usage is written to the db
+pub const USAGE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Usage {
+    requests: i64,
+    rejected: i64,
+    last_used_at: OffsetDateTime,
+}
+
+impl Usage {
+    /// A single request, `rejected` if the rate limiter turned it away
+    fn new(rejected: bool) -> Self {
+        Self {
+            requests: i64::from(!rejected),
+            rejected: i64::from(rejected),
+            last_used_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.requests += other.requests;
+        self.rejected += other.rejected;
+        self.last_used_at = self.last_used_at.max(other.last_used_at);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum UsageKey {
+    /// Access token, by token hash
+    AccessToken(StackString),
+    /// Inbound event source, by source token
+    InboundSource(StackString),
+}
+
+/// Access token and inbound source usage, counted in memory and written out
+/// every `USAGE_FLUSH_INTERVAL` so public hits don't each cost an UPDATE.
+#[derive(Default)]
+pub struct TokenUsage {
+    counts: Mutex<HashMap<UsageKey, Usage>>,
+}
+
+impl TokenUsage {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a request, `rejected` requests were turned away by the rate
+    /// limiter.  Only hashes of tokens that exist should be recorded, the
+    /// counts are kept until flushed.
+    pub fn record(&self, token_hash: &str, rejected: bool) {
+        self.add(
+            UsageKey::AccessToken(token_hash.into()),
+            Usage::new(rejected),
+        );
+    }
+
+    /// Count a request to an inbound source, `rejected` requests failed
+    /// validation.
+    pub fn record_inbound(&self, source_token: &str, rejected: bool) {
+        self.add(
+            UsageKey::InboundSource(source_token.into()),
+            Usage::new(rejected),
+        );
+    }
+
+    fn add(&self, key: UsageKey, usage: Usage) {
+        self.counts
+            .lock()
+            .entry(key)
+            .and_modify(|counts| counts.merge(usage))
+            .or_insert(usage);
+    }
+
+    fn take(&self) -> HashMap<UsageKey, Usage> {
+        std::mem::take(&mut *self.counts.lock())
+    }
+
+    /// Write out and reset the counts, counts that fail to write are kept
+    /// for the next flush.
+    /// # Errors
+    /// Returns the last error if any db query fails
+    pub async fn flush(&self, pool: &PgPool) -> Result<(), Error> {
+        let mut result = Ok(());
+        for (key, usage) in self.take() {
+            let Usage {
+                requests,
+                rejected,
+                last_used_at,
+            } = usage;
+            let written = match &key {
+                UsageKey::AccessToken(token_hash) => {
+                    AccessToken::add_usage(token_hash, requests, rejected, last_used_at, pool).await
+                }
+                UsageKey::InboundSource(source_token) => {
+                    InboundSource::add_usage(source_token, requests, rejected, last_used_at, pool)
+                        .await
+                }
+            };
+            if let Err(e) = written {
+                self.add(key, usage);
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Flush every `USAGE_FLUSH_INTERVAL`, meant to run until shutdown.
+    /// # Errors
+    /// Never returns an error, failed flushes are logged
+    pub async fn flush_loop(&self, pool: &PgPool) -> Result<(), Error> {
+        let mut i = interval(USAGE_FLUSH_INTERVAL);
+        loop {
+            i.tick().await;
+            if let Err(e) = self.flush(pool).await {
+                error!("failed to write token usage {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token_usage::{TokenUsage, UsageKey};
+
+    #[test]
+    fn test_token_usage() {
+        let usage = TokenUsage::new();
+        usage.record("a", false);
+        usage.record("a", false);
+        usage.record("a", true);
+        usage.record("b", true);
+        usage.record_inbound("a", false);
+
+        let a = UsageKey::AccessToken("a".into());
+        let b = UsageKey::AccessToken("b".into());
+        let inbound = UsageKey::InboundSource("a".into());
+        let counts = usage.take();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&a].requests, 2);
+        assert_eq!(counts[&a].rejected, 1);
+        assert_eq!(counts[&b].requests, 0);
+        assert_eq!(counts[&b].rejected, 1);
+        assert_eq!(counts[&inbound].requests, 1);
+        assert!(usage.take().is_empty());
+
+        // Counts put back after a failed flush add to newer ones
+        for (key, counts) in counts {
+            usage.add(key, counts);
+        }
+        usage.record("a", false);
+        let counts = usage.take();
+        assert_eq!(counts[&a].requests, 3);
+        assert_eq!(counts[&a].rejected, 1);
+        assert_eq!(counts[&b].rejected, 1);
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{format_err, Error};
+use aws_sdk_s3::{primitives::ByteStream, Client as S3Client};
+use stack_string::{format_sstr, StackString};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::{config::Config, s3_backup::build_s3_client};
+
+/// Where attachment contents live, metadata is kept in `event_attachments`.
+#[derive(Clone)]
+pub enum AttachmentStore {
+    Local(PathBuf),
+    S3 {
+        client: S3Client,
+        bucket: StackString,
+        prefix: StackString,
+    },
+}
+
+impl AttachmentStore {
+    /// Uses S3 if `ATTACHMENT_S3_BUCKET` is set, otherwise `ATTACHMENT_PATH`
+    pub async fn new(config: &Config) -> Self {
+        if let Some(bucket) = &config.attachment_s3_bucket {
+            Self::S3 {
+                client: build_s3_client(config).await,
+                bucket: bucket.clone(),
+                prefix: format_sstr!("{}/attachments", config.backup_s3_prefix),
+            }
+        } else {
+            Self::Local(config.attachment_path.clone())
+        }
+    }
+
+    /// Same as `new` without any network access, `None` if attachments are
+    /// kept in S3
+    #[must_use]
+    pub fn new_offline(config: &Config) -> Option<Self> {
+        if config.attachment_s3_bucket.is_some() {
+            None
+        } else {
+            Some(Self::Local(config.attachment_path.clone()))
+        }
+    }
+
+    fn local_path(directory: &Path, storage_key: &str) -> Result<PathBuf, Error> {
+        if storage_key.is_empty()
+            || !storage_key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(format_err!("Invalid storage key {storage_key}"));
+        }
+        Ok(directory.join(storage_key))
+    }
+
+    /// # Errors
+    /// Returns error if the write fails
+    pub async fn put(&self, storage_key: &str, data: Vec<u8>) -> Result<(), Error> {
+        match self {
+            Self::Local(directory) => {
+                let path = Self::local_path(directory, storage_key)?;
+                fs::create_dir_all(directory).await?;
+                fs::write(path, data).await?;
+            }
+            Self::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                client
+                    .put_object()
+                    .bucket(bucket.as_str())
+                    .key(format_sstr!("{prefix}/{storage_key}").as_str())
+                    .body(ByteStream::from(data))
+                    .send()
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns error if the read fails
+    pub async fn get(&self, storage_key: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Local(directory) => {
+                let path = Self::local_path(directory, storage_key)?;
+                fs::read(path).await.map_err(Into::into)
+            }
+            Self::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                let object = client
+                    .get_object()
+                    .bucket(bucket.as_str())
+                    .key(format_sstr!("{prefix}/{storage_key}").as_str())
+                    .send()
+                    .await?;
+                let data = object.body.collect().await?.into_bytes();
+                Ok(data.to_vec())
+            }
+        }
+    }
+
+    /// # Errors
+    /// Returns error if the delete fails
+    pub async fn delete(&self, storage_key: &str) -> Result<(), Error> {
+        match self {
+            Self::Local(directory) => {
+                let path = Self::local_path(directory, storage_key)?;
+                if path.exists() {
+                    fs::remove_file(path).await?;
+                }
+            }
+            Self::S3 {
+                client,
+                bucket,
+                prefix,
+            } => {
+                client
+                    .delete_object()
+                    .bucket(bucket.as_str())
+                    .key(format_sstr!("{prefix}/{storage_key}").as_str())
+                    .send()
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use uuid::Uuid;
+
+    use crate::attachments::AttachmentStore;
+
+    #[tokio::test]
+    async fn test_local_attachment_store() -> Result<(), Error> {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = AttachmentStore::Local(directory.clone());
+        let key = Uuid::new_v4().to_string();
+
+        store.put(&key, b"agenda".to_vec()).await?;
+        assert_eq!(store.get(&key).await?, b"agenda");
+        store.delete(&key).await?;
+        assert!(store.get(&key).await.is_err());
+
+        assert!(store.put("../escape", Vec::new()).await.is_err());
+        std::fs::remove_dir_all(directory)?;
+        Ok(())
+    }
+}
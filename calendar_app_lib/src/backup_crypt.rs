@@ -0,0 +1,88 @@
+use age::x25519::{Identity, Recipient};
+use anyhow::{format_err, Error};
+use std::io::{Read, Write};
+
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+
+#[must_use]
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(AGE_HEADER)
+}
+
+/// # Errors
+/// Returns error if `recipient` is not a valid age public key or encryption
+/// fails
+pub fn encrypt_backup(data: &[u8], recipient: &str) -> Result<Vec<u8>, Error> {
+    let recipient: Recipient = recipient.parse().map_err(|e| format_err!("{e}"))?;
+    let encryptor = age::Encryptor::with_recipients(vec![Box::new(recipient)])
+        .ok_or_else(|| format_err!("No backup recipient"))?;
+    let mut output = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut output)?;
+    writer.write_all(data)?;
+    writer.finish()?;
+    Ok(output)
+}
+
+/// # Errors
+/// Returns error if none of `identities` can decrypt `data`
+pub fn decrypt_backup(data: &[u8], identities: &[Identity]) -> Result<Vec<u8>, Error> {
+    let decryptor = match age::Decryptor::new(data)? {
+        age::Decryptor::Recipients(d) => d,
+        age::Decryptor::Passphrase(_) => {
+            return Err(format_err!(
+                "Passphrase encrypted backups are not supported"
+            ))
+        }
+    };
+    let mut reader = decryptor.decrypt(identities.iter().map(|i| i as &dyn age::Identity))?;
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// Parse the contents of an age identity file (as written by `age-keygen`),
+/// ignoring comments and blank lines.
+/// # Errors
+/// Returns error if any line is not a valid identity
+pub fn parse_identities(contents: &str) -> Result<Vec<Identity>, Error> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse().map_err(|e| format_err!("{e}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use age::{secrecy::ExposeSecret, x25519::Identity};
+    use anyhow::Error;
+    use stack_string::format_sstr;
+
+    use crate::backup_crypt::{decrypt_backup, encrypt_backup, is_encrypted, parse_identities};
+
+    #[test]
+    fn test_encrypt_decrypt_backup() -> Result<(), Error> {
+        let identity = Identity::generate();
+        let recipient = identity.to_public().to_string();
+        let data = br#"{"schema_version": 2}"#;
+
+        let encrypted = encrypt_backup(data, &recipient)?;
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(data));
+
+        let identity_file = format_sstr!(
+            "# created: today\n{}\n",
+            identity.to_string().expose_secret()
+        );
+        let identities = parse_identities(&identity_file)?;
+        assert_eq!(identities.len(), 1);
+
+        let decrypted = decrypt_backup(&encrypted, &identities)?;
+        assert_eq!(&decrypted, data);
+
+        let other = Identity::generate();
+        assert!(decrypt_backup(&encrypted, &[other]).is_err());
+        Ok(())
+    }
+}
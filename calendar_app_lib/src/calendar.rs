@@ -40,6 +40,7 @@ pub struct Calendar {
     pub sync: bool,
     pub edit: bool,
     pub display: bool,
+    pub access_role: Option<StackString>,
 }
 
 impl fmt::Display for Calendar {
@@ -72,6 +73,7 @@ impl From<CalendarList> for Calendar {
             sync: item.sync,
             edit: item.edit,
             display: item.display,
+            access_role: item.access_role,
         }
     }
 }
@@ -89,11 +91,18 @@ impl From<Calendar> for CalendarList {
             last_modified: DateTimeWrapper::now(),
             edit: false,
             display: false,
+            access_role: item.access_role,
         }
     }
 }
 
 impl Calendar {
+    /// False if gcal only grants read access to the calendar
+    #[must_use]
+    pub fn is_writable(&self) -> bool {
+        access_role_can_edit(self.access_role.as_deref())
+    }
+
     #[must_use]
     pub fn from_gcal_entry(item: &CalendarListEntry) -> Option<Self> {
         if item.deleted.unwrap_or(false) {
@@ -112,6 +121,7 @@ impl Calendar {
                 sync: false,
                 edit: false,
                 display: false,
+                access_role: item.access_role.clone().map(Into::into),
             })
         }
     }
@@ -127,6 +137,7 @@ pub struct Event {
     pub name: StackString,
     pub description: Option<StackString>,
     pub location: Option<Location>,
+    pub editable: bool,
 }
 
 impl fmt::Display for Event {
@@ -184,6 +195,7 @@ impl From<CalendarCache> for Event {
             name: item.event_name,
             description: item.event_description,
             location: loc,
+            editable: item.editable,
         }
     }
 }
@@ -208,6 +220,9 @@ impl From<Event> for CalendarCache {
                 .and_then(|l| l.lat_lon.map(|(_, lon)| lon.into())),
             event_location_name: item.location.map(|l| l.name),
             last_modified: DateTimeWrapper::now(),
+            source_id: None,
+            source_modified: None,
+            editable: item.editable,
         }
     }
 }
@@ -245,6 +260,28 @@ fn from_gcal_eventdatetime(dt: &EventDateTime) -> Option<OffsetDateTime> {
     )
 }
 
+/// Whether `access_role` (from the calendar list or an events listing)
+/// permits modifying events, an unknown role doesn't restrict anything.
+#[must_use]
+pub fn access_role_can_edit(access_role: Option<&str>) -> bool {
+    access_role.map_or(true, |role| matches!(role, "owner" | "writer"))
+}
+
+/// Whether changes to an event on calendar `gcal_id` will be accepted by
+/// gcal: the event is organized by this calendar (or has no organizer yet)
+/// or guests are allowed to modify it.  Invitations from outside the domain
+/// show up with an `unknownorganizer@calendar.google.com` organizer and are
+/// covered by the same rule.
+#[must_use]
+pub fn gcal_event_editable(item: &GCalEvent, gcal_id: &str) -> bool {
+    let Some(organizer) = &item.organizer else {
+        return true;
+    };
+    organizer.is_self.unwrap_or(false)
+        || organizer.email.as_deref() == Some(gcal_id)
+        || item.guests_can_modify.unwrap_or(false)
+}
+
 impl Event {
     pub fn new(
         gcal_id: impl Into<StackString>,
@@ -261,6 +298,7 @@ impl Event {
             name: name.into(),
             description: None,
             location: None,
+            editable: true,
         }
     }
 
@@ -273,8 +311,8 @@ impl Event {
             };
             loc.replace(location);
         }
+        let gcal_id = gcal_id.into();
         Some(Self {
-            gcal_id: gcal_id.into(),
             event_id: item.id.as_ref()?.into(),
             start_time: item
                 .start
@@ -286,6 +324,8 @@ impl Event {
             name: item.summary.as_ref()?.into(),
             description: item.description.as_ref().map(Into::into),
             location: loc,
+            editable: gcal_event_editable(item, &gcal_id),
+            gcal_id,
         })
     }
 
@@ -353,15 +393,67 @@ impl Event {
     }
 }
 
+/// Agenda entry as served by `/calendar/agenda.json`, flattened so clients
+/// (e.g. the tray companion) don't need the calendar list.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct AgendaEvent {
+    pub gcal_id: StackString,
+    pub event_id: StackString,
+    pub calendar_name: StackString,
+    pub name: StackString,
+    pub start_time: DateTimeWrapper,
+    pub end_time: DateTimeWrapper,
+    pub description: Option<StackString>,
+    pub location: Option<StackString>,
+    pub url: Option<StackString>,
+    #[serde(default)]
+    pub description_truncated: bool,
+}
+
+impl AgendaEvent {
+    #[must_use]
+    pub fn new(event: Event, calendar_name: impl Into<StackString>) -> Self {
+        Self {
+            gcal_id: event.gcal_id,
+            event_id: event.event_id,
+            calendar_name: calendar_name.into(),
+            name: event.name,
+            start_time: event.start_time,
+            end_time: event.end_time,
+            description: event.description,
+            location: event.location.map(|l| l.name),
+            url: event.url.map(|u| u.as_str().into()),
+            description_truncated: false,
+        }
+    }
+
+    /// One line summary in the style of `Event::get_summary`
+    #[must_use]
+    pub fn get_summary(&self, config: &Config) -> StackString {
+        let start_time = get_default_or_local_time(self.start_time.into(), config);
+        let url = self.url.as_ref().unwrap_or(&self.event_id);
+        format_sstr!(
+            "{start_time} {n} ({c}) {i} {e} {url}",
+            n = self.name,
+            c = self.calendar_name,
+            i = self.gcal_id,
+            e = self.event_id,
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
     use log::debug;
     use time::{Duration, OffsetDateTime};
 
-    use gcal_lib::gcal_instance::GCalendarInstance;
+    use gcal_lib::gcal_instance::{Event as GCalEvent, EventOrganizer, GCalendarInstance};
 
-    use crate::{calendar::Event, config::Config};
+    use crate::{
+        calendar::{access_role_can_edit, gcal_event_editable, Event},
+        config::Config,
+    };
 
     #[test]
     fn test_new_event() {
@@ -373,6 +465,40 @@ mod tests {
         );
         debug!("{:#?}", event);
         assert_eq!(&event.name, "Test event");
+        assert!(event.editable);
+    }
+
+    #[test]
+    fn test_gcal_event_editable() {
+        let gcal_id = "ddboline@gmail.com";
+        let mut event = GCalEvent::default();
+        assert!(gcal_event_editable(&event, gcal_id));
+
+        event.organizer = Some(EventOrganizer {
+            email: Some("unknownorganizer@calendar.google.com".into()),
+            ..EventOrganizer::default()
+        });
+        assert!(!gcal_event_editable(&event, gcal_id));
+        event.guests_can_modify = Some(true);
+        assert!(gcal_event_editable(&event, gcal_id));
+
+        event.guests_can_modify = None;
+        event.organizer = Some(EventOrganizer {
+            email: Some(gcal_id.into()),
+            ..EventOrganizer::default()
+        });
+        assert!(gcal_event_editable(&event, gcal_id));
+        event.organizer = Some(EventOrganizer {
+            is_self: Some(true),
+            ..EventOrganizer::default()
+        });
+        assert!(gcal_event_editable(&event, gcal_id));
+
+        assert!(access_role_can_edit(None));
+        assert!(access_role_can_edit(Some("owner")));
+        assert!(access_role_can_edit(Some("writer")));
+        assert!(!access_role_can_edit(Some("reader")));
+        assert!(!access_role_can_edit(Some("freeBusyReader")));
     }
 
     #[tokio::test]
@@ -1,21 +1,34 @@
 use anyhow::{format_err, Error};
 use clap::Parser;
-use futures::{future::try_join_all, TryStreamExt};
+use futures::{
+    future::{ready, try_join_all},
+    TryStreamExt,
+};
 use refinery::embed_migrations;
 use stack_string::{format_sstr, StackString};
-use std::path::PathBuf;
-use time::{Duration, OffsetDateTime};
+use std::{collections::HashSet, path::PathBuf};
+use time::{Date, Duration, OffsetDateTime};
+use time_tz::PrimitiveDateTimeExt;
 use tokio::{
-    fs::{read, File},
-    io::{stdin, stdout, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    fs::{read, read_to_string, File},
+    io::{stdin, stdout, AsyncReadExt, AsyncWriteExt},
 };
 
 use crate::{
+    backup_crypt::{decrypt_backup, encrypt_backup, is_encrypted, parse_identities},
     calendar::Event,
     calendar_sync::CalendarSync,
     config::Config,
-    models::{CalendarCache, CalendarList},
+    doctor::{doctor_report, run_doctor, CheckStatus},
+    export_format::{deserialize_export, serialize_export},
+    fsck::run_fsck,
+    get_default_or_local_time,
+    models::{AuthorizedUsers, CalendarCache, CalendarList, EventAttachment, SyncHistory},
+    notification_sim::simulate_notifications,
     pgpool::PgPool,
+    remote::RemoteClient,
+    s3_backup::S3Backup,
+    timezone::TimeZone,
     DateType,
 };
 
@@ -69,6 +82,12 @@ pub enum CalendarActions {
         #[clap(short, long)]
         /// Input file (if missinge will read from stdin)
         filepath: Option<PathBuf>,
+        #[clap(long)]
+        /// Restore from BACKUP_S3_BUCKET instead of a file
+        s3: bool,
+        #[clap(short, long)]
+        /// S3 snapshot key (defaults to the most recent snapshot)
+        key: Option<StackString>,
     },
     Export {
         #[clap(short, long)]
@@ -77,14 +96,193 @@ pub enum CalendarActions {
         #[clap(short, long)]
         /// Input file (if missinge will read from stdin)
         filepath: Option<PathBuf>,
+        #[clap(short, long)]
+        /// Encrypt output for BACKUP_RECIPIENT
+        encrypt: bool,
+        #[clap(long)]
+        /// Upload to BACKUP_S3_BUCKET, keeping the last BACKUP_RETENTION
+        /// snapshots
+        s3: bool,
+        #[clap(short, long)]
+        /// Only export this Google Calendar Id
+        gcal_id: Option<StackString>,
+        #[clap(long, value_parser=DateType::parse_from_str)]
+        /// Only export events ending on or after this date (calendar_cache
+        /// only)
+        min_date: Option<DateType>,
+        #[clap(long, value_parser=DateType::parse_from_str)]
+        /// Only export events starting on or before this date
+        /// (calendar_cache only)
+        max_date: Option<DateType>,
+        #[clap(short, long)]
+        /// Export all rows, not just those modified in the last 7 days
+        /// (implied by --gcal-id, --min-date or --max-date)
+        all: bool,
     },
     RunMigrations,
+    /// Check calendar data for integrity problems
+    Fsck {
+        #[clap(short, long)]
+        /// Repair issues that can be fixed automatically
+        repair: bool,
+    },
+    /// Check database, migrations, gcal token, scrapers, notification
+    /// credentials and timezone data
+    Doctor,
+    /// Replay which notifications would have fired on a given day
+    SimulateNotifications {
+        #[clap(short, long, value_parser=DateType::parse_from_str)]
+        /// Day to replay (in DEFAULT_TIME_ZONE)
+        date: DateType,
+    },
+    /// Talk to a running deployment's api (REMOTE_URL) with REMOTE_API_KEY
+    Remote {
+        #[clap(long)]
+        /// Deployment url (overrides REMOTE_URL)
+        url: Option<StackString>,
+        #[clap(long)]
+        /// Api key (overrides REMOTE_API_KEY)
+        api_key: Option<StackString>,
+        #[clap(subcommand)]
+        action: RemoteActions,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub enum RemoteActions {
+    /// Print the remote agenda
+    Agenda,
+    /// Sync calendars on the remote (needs an unscoped, writable api key)
+    Sync {
+        #[clap(short, long)]
+        /// Sync all events rather than future events
+        full: bool,
+    },
+    /// Print calendar, event and sync statistics of the remote
+    Stats,
+}
+
+impl CalendarActions {
+    fn requires_network(&self) -> bool {
+        match self {
+            Self::SyncCalendars
+            | Self::SyncCalendarsFull
+            | Self::Delete { .. }
+            | Self::Remote { .. } => true,
+            Self::Import { s3, .. } | Self::Export { s3, .. } => *s3,
+            _ => false,
+        }
+    }
+
+    fn reads_cache(&self) -> bool {
+        matches!(
+            self,
+            Self::PrintAgenda
+                | Self::ListCalendars
+                | Self::List { .. }
+                | Self::Detail { .. }
+                | Self::SimulateNotifications { .. }
+        )
+    }
+}
+
+fn format_age(age: Duration) -> StackString {
+    let (n, unit) = if age.whole_days() > 0 {
+        (age.whole_days(), "day")
+    } else if age.whole_hours() > 0 {
+        (age.whole_hours(), "hour")
+    } else {
+        (age.whole_minutes(), "minute")
+    };
+    let plural = if n == 1 { "" } else { "s" };
+    format_sstr!("{n} {unit}{plural}")
+}
+
+/// Banner shown before output read from the local cache, `None` if the
+/// cache is fresh (and we're online)
+#[must_use]
+pub fn staleness_banner(
+    last_sync: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+    max_age: Duration,
+    offline: bool,
+) -> Option<StackString> {
+    let prefix = if offline { "[offline] " } else { "" };
+    let Some(last_sync) = last_sync else {
+        return Some(format_sstr!(
+            "{prefix}WARNING: local data has never been synced"
+        ));
+    };
+    let age = now - last_sync;
+    if age > max_age {
+        Some(format_sstr!(
+            "{prefix}WARNING: local data is stale, last synced {} ago",
+            format_age(age)
+        ))
+    } else if offline {
+        Some(format_sstr!(
+            "{prefix}local data last synced {} ago",
+            format_age(age)
+        ))
+    } else {
+        None
+    }
+}
+
+/// Start of `min_date` and end of `max_date` in `timezone`, so both days are
+/// included in full
+#[must_use]
+pub fn export_date_range(
+    min_date: Option<Date>,
+    max_date: Option<Date>,
+    timezone: TimeZone,
+) -> (Option<OffsetDateTime>, Option<OffsetDateTime>) {
+    let start_of_day = |date: Date| {
+        date.midnight()
+            .assume_timezone(timezone.into())
+            .take()
+            .unwrap_or_else(|| date.midnight().assume_utc())
+    };
+    (
+        min_date.map(start_of_day),
+        max_date.and_then(Date::next_day).map(start_of_day),
+    )
+}
+
+/// Without `all` an export only covers rows modified in the last 7 days,
+/// unless it's already `narrowed` to a calendar or date range
+#[must_use]
+pub fn export_modified_since(
+    all: bool,
+    narrowed: bool,
+    now: OffsetDateTime,
+) -> Option<OffsetDateTime> {
+    if all || narrowed {
+        None
+    } else {
+        Some(now - Duration::days(7))
+    }
+}
+
+fn encrypt_export(data: Vec<u8>, encrypt: bool, config: &Config) -> Result<Vec<u8>, Error> {
+    if encrypt {
+        let recipient = config
+            .backup_recipient
+            .as_ref()
+            .ok_or_else(|| format_err!("BACKUP_RECIPIENT not set"))?;
+        encrypt_backup(&data, recipient)
+    } else {
+        Ok(data)
+    }
 }
 
 #[derive(Parser, Debug)]
 pub struct CalendarCliOpts {
     #[clap(subcommand)]
     action: Option<CalendarActions>,
+    #[clap(long, global = true)]
+    /// Only use the local cache, never make network calls
+    offline: bool,
 }
 
 impl CalendarCliOpts {
@@ -94,9 +292,35 @@ impl CalendarCliOpts {
         let opts = Self::parse();
         let action = opts.action.unwrap_or(CalendarActions::PrintAgenda);
 
+        if opts.offline && action.requires_network() {
+            return Err(format_err!("{action:?} requires network access"));
+        }
+
         let config = Config::init_config()?;
         let pool = PgPool::new(&config.database_url)?;
-        let cal_sync = CalendarSync::new(config, pool).await;
+        let cal_sync = if opts.offline {
+            CalendarSync::new_offline(config, pool)
+        } else {
+            CalendarSync::new(config, pool).await
+        };
+
+        if action.reads_cache() {
+            // Don't fail reads if the history can't be queried (e.g. before
+            // migrations have run)
+            let last_sync = SyncHistory::get_last_success(&cal_sync.pool)
+                .await
+                .ok()
+                .flatten()
+                .and_then(|history| history.finished_at);
+            if let Some(banner) = staleness_banner(
+                last_sync.map(Into::into),
+                OffsetDateTime::now_utc(),
+                Duration::hours(cal_sync.config.stale_sync_hours),
+                opts.offline,
+            ) {
+                cal_sync.stdout.send(banner);
+            }
+        }
 
         match action {
             CalendarActions::PrintAgenda => {
@@ -169,8 +393,18 @@ impl CalendarCliOpts {
                     cal_sync.stdout.send(event_str);
                 }
             }
-            CalendarActions::Import { table, filepath } => {
-                let data = if let Some(filepath) = filepath {
+            CalendarActions::Import {
+                table,
+                filepath,
+                s3,
+                key,
+            } => {
+                let data = if s3 {
+                    S3Backup::new(&cal_sync.config)
+                        .await?
+                        .download(&table, key.as_ref().map(StackString::as_str))
+                        .await?
+                } else if let Some(filepath) = filepath {
                     read(&filepath).await?
                 } else {
                     let mut stdin = stdin();
@@ -178,9 +412,20 @@ impl CalendarCliOpts {
                     stdin.read_to_end(&mut buf).await?;
                     buf
                 };
+                let data = if is_encrypted(&data) {
+                    let identity_file = cal_sync
+                        .config
+                        .backup_identity_file
+                        .as_ref()
+                        .ok_or_else(|| format_err!("BACKUP_IDENTITY_FILE not set"))?;
+                    let identities = parse_identities(&read_to_string(identity_file).await?)?;
+                    decrypt_backup(&data, &identities)?
+                } else {
+                    data
+                };
                 match table.as_str() {
                     "calendar_list" => {
-                        let calendars: Vec<CalendarList> = serde_json::from_slice(&data)?;
+                        let calendars: Vec<CalendarList> = deserialize_export(&table, &data)?;
                         let futures = calendars.into_iter().map(|calendar| {
                             let pool = cal_sync.pool.clone();
                             async move { calendar.upsert(&pool).await.map_err(Into::into) }
@@ -191,7 +436,7 @@ impl CalendarCliOpts {
                             .send(format_sstr!("calendar_list {}", results?.len()));
                     }
                     "calendar_cache" => {
-                        let events: Vec<CalendarCache> = serde_json::from_slice(&data)?;
+                        let events: Vec<CalendarCache> = deserialize_export(&table, &data)?;
                         let futures = events.into_iter().map(|event| {
                             let pool = cal_sync.pool.clone();
                             async move { event.upsert(&pool).await.map_err(Into::into) }
@@ -204,49 +449,255 @@ impl CalendarCliOpts {
                     _ => {}
                 }
             }
-            CalendarActions::Export { table, filepath } => {
-                let mut file: Box<dyn AsyncWrite + Unpin + Send + Sync> =
-                    if let Some(filepath) = filepath {
-                        Box::new(File::create(&filepath).await?)
-                    } else {
-                        Box::new(stdout())
-                    };
-                match table.as_str() {
+            CalendarActions::Export {
+                table,
+                filepath,
+                encrypt,
+                s3,
+                gcal_id,
+                min_date,
+                max_date,
+                all,
+            } => {
+                let narrowed = gcal_id.is_some() || min_date.is_some() || max_date.is_some();
+                let max_modified = export_modified_since(all, narrowed, OffsetDateTime::now_utc());
+                let timezone = cal_sync
+                    .config
+                    .default_time_zone
+                    .unwrap_or_else(TimeZone::local);
+                let (min_time, max_time) =
+                    export_date_range(min_date.map(Into::into), max_date.map(Into::into), timezone);
+                let data = match table.as_str() {
                     "calendar_list" => {
-                        let max_modified = OffsetDateTime::now_utc() - Duration::days(7);
+                        if min_time.is_some() || max_time.is_some() {
+                            return Err(format_err!(
+                                "--min-date and --max-date only apply to calendar_cache"
+                            ));
+                        }
                         let calendars: Vec<_> = CalendarList::get_recent(
                             &cal_sync.pool,
-                            Some(max_modified),
+                            max_modified,
+                            gcal_id.as_ref(),
                             None,
                             None,
                         )
                         .await?
                         .try_collect()
                         .await?;
-                        file.write_all(&serde_json::to_vec(&calendars)?).await?;
+                        serialize_export(&table, &calendars)?
                     }
                     "calendar_cache" => {
-                        let max_modified = OffsetDateTime::now_utc() - Duration::days(7);
-                        let events: Vec<_> = CalendarCache::get_recent(
+                        let events: Vec<_> = CalendarCache::get_filtered(
+                            gcal_id.as_ref().map(StackString::as_str),
+                            min_time,
+                            max_time,
+                            max_modified,
                             &cal_sync.pool,
-                            Some(max_modified),
-                            None,
-                            None,
                         )
                         .await?
                         .try_collect()
                         .await?;
-                        file.write_all(&serde_json::to_vec(&events)?).await?;
+                        serialize_export(&table, &events)?
                     }
-                    _ => {}
+                    _ => return Err(format_err!("Unknown table {table}")),
+                };
+                let data = encrypt_export(data, encrypt, &cal_sync.config)?;
+                if s3 {
+                    let key = S3Backup::new(&cal_sync.config)
+                        .await?
+                        .upload(&table, data, encrypt)
+                        .await?;
+                    cal_sync.stdout.send(format_sstr!("uploaded {key}"));
+                } else if let Some(filepath) = filepath {
+                    File::create(&filepath).await?.write_all(&data).await?;
+                } else {
+                    stdout().write_all(&data).await?;
                 }
             }
             CalendarActions::RunMigrations => {
                 let mut client = cal_sync.pool.get().await?;
                 migrations::runner().run_async(&mut **client).await?;
             }
+            CalendarActions::Fsck { repair } => {
+                for line in run_fsck(&cal_sync, repair).await? {
+                    cal_sync.stdout.send(line);
+                }
+            }
+            CalendarActions::Doctor => {
+                let checks = run_doctor(
+                    &cal_sync.config,
+                    &cal_sync.pool,
+                    &migrations::runner(),
+                    opts.offline,
+                )
+                .await;
+                for line in doctor_report(&checks) {
+                    cal_sync.stdout.send(line);
+                }
+                let failed = checks
+                    .iter()
+                    .filter(|c| c.status == CheckStatus::Fail)
+                    .count();
+                if failed > 0 {
+                    cal_sync.stdout.close().await?;
+                    return Err(format_err!("{failed} doctor checks failed"));
+                }
+            }
+            CalendarActions::SimulateNotifications { date } => {
+                let date: Date = date.into();
+                let timezone = cal_sync
+                    .config
+                    .default_time_zone
+                    .unwrap_or_else(TimeZone::local);
+                let min_time = date.midnight().assume_utc() - Duration::days(2);
+                let max_time = date.midnight().assume_utc() + Duration::days(3);
+                let events: Vec<_> =
+                    CalendarCache::get_by_datetime(min_time, max_time, &cal_sync.pool)
+                        .await?
+                        .try_collect()
+                        .await?;
+                let calendars: Vec<_> = CalendarList::get_calendars(&cal_sync.pool)
+                    .await?
+                    .try_collect()
+                    .await?;
+                let displayed: HashSet<_> = calendars
+                    .iter()
+                    .filter(|c| c.display)
+                    .map(|c| c.gcal_id.clone())
+                    .collect();
+                let mut tickets = HashSet::new();
+                for calendar in &calendars {
+                    for attachment in
+                        EventAttachment::get_by_gcal_id(&calendar.gcal_id, &cal_sync.pool).await?
+                    {
+                        if attachment.is_ticket {
+                            tickets.insert((attachment.gcal_id, attachment.event_id));
+                        }
+                    }
+                }
+                let chats: Vec<_> = AuthorizedUsers::get_authorized_users(&cal_sync.pool)
+                    .await?
+                    .try_filter(|user| ready(user.telegram_chatid.is_some()))
+                    .try_collect()
+                    .await?;
+                if chats.is_empty() {
+                    cal_sync.stdout.send(
+                        "no telegram chat has been initialized, bot notifications go nowhere"
+                            .into(),
+                    );
+                }
+                let notifications = simulate_notifications(
+                    date,
+                    timezone,
+                    &events,
+                    &displayed,
+                    &tickets,
+                    Duration::minutes(cal_sync.config.tray_reminder_minutes),
+                );
+                for notification in &notifications {
+                    cal_sync.stdout.send(notification.summary(timezone));
+                }
+                cal_sync.stdout.send(format_sstr!(
+                    "{} notifications, {} sent to {} telegram chats",
+                    notifications.len(),
+                    notifications.iter().filter(|n| n.sent).count(),
+                    chats.len(),
+                ));
+            }
+            CalendarActions::Remote {
+                url,
+                api_key,
+                action,
+            } => {
+                let client = RemoteClient::new(&cal_sync.config, url, api_key)?;
+                match action {
+                    RemoteActions::Agenda => {
+                        for event in client.agenda().await? {
+                            cal_sync.stdout.send(event.get_summary(&cal_sync.config));
+                        }
+                    }
+                    RemoteActions::Sync { full } => {
+                        for line in client.sync(full).await? {
+                            cal_sync.stdout.send(line);
+                        }
+                    }
+                    RemoteActions::Stats => {
+                        let stats = client.stats().await?;
+                        let last_sync = stats.last_sync.map_or_else(
+                            || "never".into(),
+                            |t| get_default_or_local_time(t.into(), &cal_sync.config),
+                        );
+                        cal_sync.stdout.send(format_sstr!(
+                            "calendars: {}\nevents: {}\nlast sync: {last_sync}\npending full syncs: {}",
+                            stats.calendars,
+                            stats.events,
+                            stats.pending_full_syncs,
+                        ));
+                        for alert in stats.scrape_alerts {
+                            cal_sync.stdout.send(format_sstr!("WARNING: {alert}"));
+                        }
+                    }
+                }
+            }
         }
         cal_sync.stdout.close().await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use time::{
+        macros::{date, datetime},
+        Duration,
+    };
+
+    use crate::{
+        calendar_cli_opts::{export_date_range, export_modified_since, staleness_banner},
+        timezone::TimeZone,
+    };
+
+    #[test]
+    fn test_staleness_banner() {
+        let now = datetime!(2024-03-01 18:30:00 UTC);
+        let max_age = Duration::hours(12);
+
+        assert_eq!(
+            staleness_banner(None, now, max_age, false).as_deref(),
+            Some("WARNING: local data has never been synced")
+        );
+        assert!(staleness_banner(Some(now - Duration::hours(1)), now, max_age, false).is_none());
+        assert_eq!(
+            staleness_banner(Some(now - Duration::hours(1)), now, max_age, true).as_deref(),
+            Some("[offline] local data last synced 1 hour ago")
+        );
+        assert_eq!(
+            staleness_banner(Some(now - Duration::days(3)), now, max_age, false).as_deref(),
+            Some("WARNING: local data is stale, last synced 3 days ago")
+        );
+    }
+
+    #[test]
+    fn test_export_date_range() {
+        let tz: TimeZone = "America/New_York".parse().unwrap();
+        assert_eq!(export_date_range(None, None, tz), (None, None));
+        assert_eq!(
+            export_date_range(Some(date!(2024 - 03 - 01)), Some(date!(2024 - 03 - 31)), tz),
+            (
+                Some(datetime!(2024-03-01 05:00:00 UTC)),
+                Some(datetime!(2024-04-01 04:00:00 UTC)),
+            )
+        );
+    }
+
+    #[test]
+    fn test_export_modified_since() {
+        let now = datetime!(2024-03-08 12:00:00 UTC);
+        assert_eq!(
+            export_modified_since(false, false, now),
+            Some(datetime!(2024-03-01 12:00:00 UTC))
+        );
+        assert_eq!(export_modified_since(true, false, now), None);
+        assert_eq!(export_modified_since(false, true, now), None);
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::{format_err, Error};
 use futures::{future::try_join_all, Stream, TryStreamExt};
-use log::debug;
+use log::{debug, error};
 use postgres_query::Error as PqError;
 use stack_string::{format_sstr, StackString};
 use std::{
@@ -12,15 +12,19 @@ use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime};
 use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt};
 use tokio::try_join;
 
-use gcal_lib::gcal_instance::{compare_gcal_events, Event as GCalEvent, GCalendarInstance};
+use gcal_lib::gcal_instance::{Event as GCalEvent, GCalendarInstance};
 
 use crate::{
+    attachments::AttachmentStore,
     calendar::{Calendar, Event},
     config::Config,
-    models::{CalendarCache, CalendarList},
+    models::{CalendarCache, CalendarList, EventAttachment, SyncHistory},
     parse_hashnyc::parse_hashnyc,
     parse_nycruns::parse_nycruns,
     pgpool::PgPool,
+    scrape_health::record_scrape,
+    sync_plan::{plan_sync, SyncMode, SyncPlan},
+    sync_schedule::{full_sync_reason, next_off_peak, SyncDrift},
     timezone::TimeZone,
 };
 
@@ -30,9 +34,25 @@ pub struct CalendarSync {
     pub gcal: Option<GCalendarInstance>,
     pub pool: PgPool,
     pub stdout: StdoutChannel<StackString>,
+    /// Shared by everything that removes attachments, `None` offline if
+    /// they're kept in S3
+    pub attachments: Option<AttachmentStore>,
 }
 
 impl CalendarSync {
+    /// No gcal instance, for use when no network calls may be made
+    #[must_use]
+    pub fn new_offline(config: Config, pool: PgPool) -> Self {
+        let attachments = AttachmentStore::new_offline(&config);
+        Self {
+            config,
+            gcal: None,
+            pool,
+            stdout: StdoutChannel::new(),
+            attachments,
+        }
+    }
+
     pub async fn new(config: Config, pool: PgPool) -> Self {
         let gcal = GCalendarInstance::new(
             &config.gcal_token_path,
@@ -41,11 +61,13 @@ impl CalendarSync {
         )
         .await
         .ok();
+        let attachments = Some(AttachmentStore::new(&config).await);
         Self {
             config,
             gcal,
             pool,
             stdout: StdoutChannel::new(),
+            attachments,
         }
     }
 
@@ -72,95 +94,68 @@ impl CalendarSync {
         try_join_all(futures).await
     }
 
-    async fn import_calendar_events<'a>(
-        &'a self,
-        gcal_id: &'a impl AsRef<str>,
-        calendar_events: impl IntoIterator<Item = &'a GCalEvent>,
-        upsert: bool,
-    ) -> Result<Vec<CalendarCache>, Error> {
-        let futures = calendar_events.into_iter().map(|item| async move {
-            let gcal_id = gcal_id.as_ref();
-            if item.start.is_none() {
-                return Ok(None);
-            } else if item.summary.is_none() {
-                self.stdout
-                    .send(format_sstr!("{:?} {:?}", item.start, item.description));
-                return Ok(None);
-            }
-            let event: CalendarCache = Event::from_gcal_event(item, gcal_id)
-                .ok_or_else(|| format_err!("Failed to convert event"))?
-                .into();
-            if upsert {
-                event.upsert(&self.pool).await?;
-                Ok(Some(event))
-            } else if CalendarCache::get_by_gcal_id_event_id(gcal_id, &event.event_id, &self.pool)
-                .await?
-                .is_none()
-            {
-                event.insert(&self.pool).await?;
-                Ok(Some(event))
-            } else {
-                Ok(None)
+    /// Remove a cached event along with its attachments (and their stored
+    /// content), returns the number of events deleted
+    /// # Errors
+    /// Returns error if db queries or removing stored content fail
+    pub async fn delete_cached_event(&self, item: &CalendarCache) -> Result<u64, Error> {
+        let attachments =
+            EventAttachment::get_by_gcal_id_event_id(&item.gcal_id, &item.event_id, &self.pool)
+                .await?;
+        if !attachments.is_empty() {
+            let store = self
+                .attachments
+                .as_ref()
+                .ok_or_else(|| format_err!("No attachment store found"))?;
+            for attachment in attachments {
+                store.delete(&attachment.storage_key).await?;
+                attachment.delete(&self.pool).await?;
             }
-        });
-        let inserted: Result<Vec<_>, Error> = try_join_all(futures).await;
-        Ok(inserted?.into_iter().flatten().collect())
+        }
+        item.delete(&self.pool).await
     }
 
-    async fn export_calendar_events<'a>(
+    /// Carry out `plan` for `gcal_id`, gcal calls that fail are skipped (and
+    /// retried on the next sync).  Returns the events written to gcal and to
+    /// the cache.
+    async fn apply_sync_plan(
         &self,
-        calendar_events: impl IntoIterator<Item = &'a GCalEvent>,
-        database_events: impl IntoIterator<Item = &'a CalendarCache>,
-        update: bool,
-    ) -> Result<Vec<GCalEvent>, Error> {
-        let event_map: HashMap<_, _> = calendar_events
-            .into_iter()
-            .filter_map(|item| item.id.as_ref().map(|event_id| (event_id.as_str(), item)))
-            .collect();
-        let event_map = Arc::new(event_map);
+        gcal_id: &str,
+        plan: SyncPlan,
+    ) -> Result<(Vec<GCalEvent>, Vec<CalendarCache>), Error> {
+        let gcal = self.gcal.as_ref();
+        let inserts = plan.inserts.iter().map(|item| (item, false));
+        let updates = plan.updates.iter().map(|item| (item, true));
+        let futures = inserts.chain(updates).map(|(item, update)| async move {
+            let gcal = gcal.ok_or_else(|| format_err!("No gcal instance found"))?;
+            let event: Event = item.clone().into();
+            let (gcal_id, event) = event.to_gcal_event();
+            let result = if update {
+                gcal.update_gcal_event(&gcal_id, event).await
+            } else {
+                gcal.insert_gcal_event(&gcal_id, event).await
+            };
+            Ok(result.ok())
+        });
+        let exported: Result<Vec<_>, Error> = try_join_all(futures).await;
+        let exported = exported?.into_iter().flatten().collect();
 
-        #[allow(clippy::manual_filter_map)]
-        let futures = database_events.into_iter().map(|item| {
-            let event_map = event_map.clone();
-            async move {
-                let event_id = item.event_id.as_str();
-                let event: Event = item.clone().into();
-                let (gcal_id, event) = event.to_gcal_event();
-                if let Some(gcal_event) = event_map.get(event_id) {
-                    let update = update
-                        && gcal_event
-                            .organizer
-                            .as_ref()
-                            .and_then(|o| o.email.as_deref())
-                            != Some("unknownorganizer@calendar.google.com");
-                    if !compare_gcal_events(gcal_event, &event) && update {
-                        if let Ok(new_event) = self
-                            .gcal
-                            .as_ref()
-                            .ok_or_else(|| format_err!("No gcal instance found"))?
-                            .update_gcal_event(&gcal_id, event)
-                            .await
-                        {
-                            Ok(Some(new_event))
-                        } else {
-                            Ok(None)
-                        }
-                    } else {
-                        Ok(None)
-                    }
-                } else {
-                    Ok(self
-                        .gcal
-                        .as_ref()
-                        .ok_or_else(|| format_err!("No gcal instance found"))?
-                        .insert_gcal_event(&gcal_id, event)
-                        .await
-                        .ok())
-                }
-            }
+        for item in &plan.deletes {
+            self.delete_cached_event(item).await?;
+        }
+        if !plan.deletes.is_empty() {
+            debug!(
+                "deleted {} events cancelled in {gcal_id}",
+                plan.deletes.len()
+            );
+        }
+
+        let futures = plan.imports.iter().map(|item| async move {
+            item.upsert(&self.pool).await?;
+            Ok(item.clone())
         });
-        let result: Result<Vec<_>, Error> = try_join_all(futures).await;
-        Ok(result?.into_iter().flatten().collect())
+        let imported: Result<Vec<_>, Error> = try_join_all(futures).await;
+        Ok((exported, imported?))
     }
 
     /// # Errors
@@ -170,27 +165,26 @@ impl CalendarSync {
         gcal_id: &str,
         edit: bool,
     ) -> Result<(Vec<GCalEvent>, Vec<CalendarCache>), Error> {
-        let calendar_events = self
+        let (calendar_events, access_role) = self
             .gcal
             .as_ref()
             .ok_or_else(|| format_err!("No gcal instance found"))?
-            .get_gcal_events(gcal_id, None, None)
+            .get_gcal_events_access_role(gcal_id, None, None)
             .await?;
-        let exported = if edit {
-            let database_events: Vec<_> =
-                CalendarCache::get_by_gcal_id_datetime(gcal_id, None, None, &self.pool)
-                    .await?
-                    .try_collect()
-                    .await?;
-            self.export_calendar_events(&calendar_events, &database_events, false)
+        let database_events: Vec<_> =
+            CalendarCache::get_by_gcal_id_datetime(gcal_id, None, None, &self.pool)
                 .await?
-        } else {
-            Vec::new()
-        };
-        let imported = self
-            .import_calendar_events(&gcal_id, calendar_events.iter(), false)
-            .await?;
-        Ok((exported, imported))
+                .try_collect()
+                .await?;
+        let plan = plan_sync(
+            gcal_id,
+            &calendar_events,
+            &database_events,
+            access_role.as_deref(),
+            edit,
+            SyncMode::Full,
+        );
+        self.apply_sync_plan(gcal_id, plan).await
     }
 
     /// # Errors
@@ -200,73 +194,235 @@ impl CalendarSync {
         gcal_id: &str,
         edit: bool,
     ) -> Result<(Vec<GCalEvent>, Vec<CalendarCache>), Error> {
-        let calendar_events = self
+        let (exported, imported, _) = self.sync_future_events_drift(gcal_id, edit).await?;
+        Ok((exported, imported))
+    }
+
+    /// Like `sync_future_events`, also reporting how far the local cache had
+    /// drifted from gcal before syncing
+    async fn sync_future_events_drift(
+        &self,
+        gcal_id: &str,
+        edit: bool,
+    ) -> Result<(Vec<GCalEvent>, Vec<CalendarCache>, SyncDrift), Error> {
+        let now = OffsetDateTime::now_utc();
+        let (calendar_events, access_role) = self
             .gcal
             .as_ref()
             .ok_or_else(|| format_err!("No gcal instance found"))?
-            .get_gcal_events(gcal_id, Some(OffsetDateTime::now_utc()), None)
+            .get_gcal_events_access_role(gcal_id, Some(now), None)
             .await?;
-        let exported = if edit {
-            let database_events: Vec<_> = CalendarCache::get_by_gcal_id_datetime(
-                gcal_id,
-                Some(OffsetDateTime::now_utc()),
-                None,
-                &self.pool,
-            )
-            .await?
-            .try_collect()
-            .await?;
-            self.export_calendar_events(&calendar_events, &database_events, true)
+        let database_events: Vec<_> =
+            CalendarCache::get_by_gcal_id_datetime(gcal_id, Some(now), None, &self.pool)
                 .await?
-        } else {
-            Vec::new()
+                .try_collect()
+                .await?;
+        let drift = SyncDrift::new(&calendar_events, &database_events);
+        let plan = plan_sync(
+            gcal_id,
+            &calendar_events,
+            &database_events,
+            access_role.as_deref(),
+            edit,
+            SyncMode::Future,
+        );
+        let (exported, imported) = self.apply_sync_plan(gcal_id, plan).await?;
+        Ok((exported, imported, drift))
+    }
+
+    /// Schedule an off-peak full sync of `gcal_id` if incremental syncs
+    /// aren't keeping up, returns the reason if one was scheduled
+    async fn schedule_full_sync(
+        &self,
+        gcal_id: &str,
+        drift: &SyncDrift,
+    ) -> Result<Option<StackString>, Error> {
+        let incremental_syncs =
+            SyncHistory::count_incremental_since_full(gcal_id, &self.pool).await?;
+        let Some(reason) = full_sync_reason(
+            incremental_syncs,
+            drift,
+            self.config.full_sync_drift_percent,
+            self.config.full_sync_after_incremental,
+        ) else {
+            return Ok(None);
         };
-        let imported = self
-            .import_calendar_events(&gcal_id, calendar_events.iter(), true)
+        let timezone = self
+            .config
+            .default_time_zone
+            .unwrap_or_else(TimeZone::local);
+        let scheduled_for = next_off_peak(
+            OffsetDateTime::now_utc(),
+            self.config.full_sync_hour,
+            timezone,
+        );
+        SyncHistory::schedule_full(gcal_id, reason.clone(), scheduled_for)
+            .insert(&self.pool)
             .await?;
-        Ok((exported, imported))
+        Ok(Some(reason))
     }
 
+    /// Run scheduled full syncs that are due, a full sync of everything
+    /// supersedes all pending ones.  Returns the calendars that still have a
+    /// full sync pending.
+    async fn run_scheduled_syncs(
+        &self,
+        gcal_set: &HashSet<StackString>,
+        full: bool,
+        output: &mut Vec<StackString>,
+    ) -> Result<HashSet<StackString>, Error> {
+        let now = OffsetDateTime::now_utc();
+        let mut pending_set = HashSet::new();
+        for mut pending in SyncHistory::get_pending(&self.pool).await? {
+            let Some(gcal_id) = pending.gcal_id.clone() else {
+                continue;
+            };
+            if full {
+                pending
+                    .finish(true, Some("superseded by full sync".into()), &self.pool)
+                    .await?;
+                continue;
+            }
+            if pending.scheduled_for.map_or(false, |t| *t > now) {
+                pending_set.insert(gcal_id);
+                continue;
+            }
+            let calendar = match CalendarList::get_by_gcal_id(&gcal_id, &self.pool).await? {
+                Some(calendar) if calendar.sync && gcal_set.contains(&gcal_id) => calendar,
+                _ => {
+                    pending
+                        .finish(false, Some("calendar no longer synced".into()), &self.pool)
+                        .await?;
+                    continue;
+                }
+            };
+            let result = self.sync_full_calendar(&gcal_id, calendar.edit).await;
+            let summary = match &result {
+                Ok((exported, inserted)) => format_sstr!(
+                    "scheduled full sync {} {} {}",
+                    calendar.calendar_name,
+                    exported.len(),
+                    inserted.len()
+                ),
+                Err(e) => format_sstr!("scheduled full sync {} failed {e}", calendar.calendar_name),
+            };
+            pending
+                .finish(result.is_ok(), Some(summary.clone()), &self.pool)
+                .await?;
+            output.push(summary);
+        }
+        Ok(pending_set)
+    }
+
+    /// Run a sync and record it in `sync_history`
     /// # Errors
     /// Returns error if api calls fail
     pub async fn run_syncing(&self, full: bool) -> Result<Vec<StackString>, Error> {
-        let mut output = Vec::new();
-
-        let hashnyc_future = parse_hashnyc(&self.pool);
-        let nycruns_future = parse_nycruns(&self.pool);
+        // Failing to record the history shouldn't stop (or hide the outcome
+        // of) the sync itself
+        let mut history = SyncHistory::new(full);
+        let recorded = match history.insert(&self.pool).await {
+            Ok(()) => true,
+            Err(e) => {
+                error!("failed to record sync history {e}");
+                false
+            }
+        };
+        let result = self.sync_all(full).await;
+        if recorded {
+            let summary = match &result {
+                Ok(output) => output.join("\n").into(),
+                Err(e) => format_sstr!("{e}"),
+            };
+            if let Err(e) = history
+                .finish(result.is_ok(), Some(summary), &self.pool)
+                .await
+            {
+                error!("failed to record sync history {e}");
+            }
+        }
+        result
+    }
 
-        let (hashnyc_events, nycruns_events) = try_join!(hashnyc_future, nycruns_future)?;
+    async fn sync_all(&self, full: bool) -> Result<Vec<StackString>, Error> {
+        let mut output = Vec::new();
 
-        output.push(format_sstr!("parse_hashnyc {}", hashnyc_events.len()));
-        output.push(format_sstr!("parse_nycruns {}", nycruns_events.len()));
+        let max_drop_percent = self.config.scrape_drop_percent;
+        let hashnyc_future = record_scrape(
+            "hashnyc",
+            parse_hashnyc(&self.pool),
+            max_drop_percent,
+            &self.pool,
+        );
+        let nycruns_future = record_scrape(
+            "nycruns",
+            parse_nycruns(&self.pool),
+            max_drop_percent,
+            &self.pool,
+        );
+
+        let ((_, hashnyc_run), (_, nycruns_run)) = try_join!(hashnyc_future, nycruns_future)?;
+
+        for run in [hashnyc_run, nycruns_run] {
+            output.push(format_sstr!(
+                "parse_{} found {} new {} updated {} parse failures {}",
+                run.source,
+                run.events_found,
+                run.events_new,
+                run.events_updated,
+                run.parse_failures
+            ));
+            if let Some(alert) = &run.alert {
+                output.push(format_sstr!("WARNING {}: {alert}", run.source));
+            }
+        }
 
         let inserted = self.sync_calendar_list().await?;
         output.push(format_sstr!("inserted {} calendars", inserted.len()));
 
         let gcal_set: HashSet<_> = inserted.iter().map(|cal| cal.gcal_id.clone()).collect();
+        let pending_set = self
+            .run_scheduled_syncs(&gcal_set, full, &mut output)
+            .await?;
         let gcal_set = Arc::new(gcal_set);
+        let pending_set = Arc::new(pending_set);
 
         let results: Result<Vec<_>, Error> = CalendarList::get_calendars(&self.pool)
             .await?
             .map_err(Into::into)
             .try_filter_map(|calendar| {
                 let gcal_set = gcal_set.clone();
+                let pending_set = pending_set.clone();
                 async move {
                     if calendar.sync && gcal_set.contains(&calendar.gcal_id) {
-                        let (exported, inserted) = if full {
-                            self.sync_full_calendar(&calendar.gcal_id, calendar.edit)
-                                .await?
+                        let (exported, inserted, drift) = if full {
+                            let (exported, inserted) = self
+                                .sync_full_calendar(&calendar.gcal_id, calendar.edit)
+                                .await?;
+                            (exported, inserted, None)
                         } else {
                             debug!("gcal_id {}", calendar.gcal_id);
-                            self.sync_future_events(&calendar.gcal_id, calendar.edit)
-                                .await?
+                            let (exported, inserted, drift) = self
+                                .sync_future_events_drift(&calendar.gcal_id, calendar.edit)
+                                .await?;
+                            (exported, inserted, Some(drift))
                         };
-                        let result = format_sstr!(
+                        let mut result = format_sstr!(
                             "future events {} {} {}",
                             calendar.calendar_name,
                             exported.len(),
                             inserted.len()
                         );
+                        if let Some(drift) = drift {
+                            if !pending_set.contains(&calendar.gcal_id) {
+                                if let Some(reason) =
+                                    self.schedule_full_sync(&calendar.gcal_id, &drift).await?
+                                {
+                                    result =
+                                        format_sstr!("{result} (full sync scheduled: {reason})");
+                                }
+                            }
+                        }
                         Ok(Some(result))
                     } else {
                         Ok(None)
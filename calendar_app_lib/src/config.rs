@@ -32,6 +32,61 @@ pub struct ConfigInner {
     pub secret_path: PathBuf,
     #[serde(default = "default_secret_path")]
     pub jwt_secret_path: PathBuf,
+    pub backup_recipient: Option<StackString>,
+    pub backup_identity_file: Option<PathBuf>,
+    pub backup_s3_bucket: Option<StackString>,
+    #[serde(default = "default_backup_s3_prefix")]
+    pub backup_s3_prefix: StackString,
+    pub backup_s3_endpoint: Option<StackString>,
+    pub backup_s3_region: Option<StackString>,
+    pub backup_s3_access_key_id: Option<StackString>,
+    pub backup_s3_secret_access_key: Option<StackString>,
+    #[serde(default = "default_backup_retention")]
+    pub backup_retention: usize,
+    #[serde(default = "default_attachment_path")]
+    pub attachment_path: PathBuf,
+    pub attachment_s3_bucket: Option<StackString>,
+    #[serde(default = "default_max_attachment_size")]
+    pub max_attachment_size: usize,
+    #[serde(default = "default_public_rate_limit")]
+    pub public_rate_limit: u32,
+    #[serde(default = "default_public_lookup_rate_limit")]
+    pub public_lookup_rate_limit: u32,
+    #[serde(default = "default_token_rotation_grace_hours")]
+    pub token_rotation_grace_hours: i64,
+    #[serde(default = "default_share_link_max_minutes")]
+    pub share_link_max_minutes: i64,
+    pub tray_api_key: Option<StackString>,
+    #[serde(default = "default_tray_reminder_minutes")]
+    pub tray_reminder_minutes: i64,
+    #[serde(default = "default_stale_sync_hours")]
+    pub stale_sync_hours: i64,
+    /// Identifies this deployment when merging `calendar_cache` updates,
+    /// defaults to `domain`
+    pub source_id: Option<StackString>,
+    #[serde(default = "default_description_summary_length")]
+    pub description_summary_length: usize,
+    /// Schedule a full sync of a calendar after this many incremental syncs
+    #[serde(default = "default_full_sync_after_incremental")]
+    pub full_sync_after_incremental: usize,
+    /// Schedule a full sync when more than this percentage of a calendar's
+    /// events drifted from gcal
+    #[serde(default = "default_full_sync_drift_percent")]
+    pub full_sync_drift_percent: usize,
+    /// Local hour (in `default_time_zone`) scheduled full syncs run at
+    #[serde(default = "default_full_sync_hour")]
+    pub full_sync_hour: u8,
+    /// Alert when a scraper finds this many percent fewer events than its
+    /// recent average
+    #[serde(default = "default_scrape_drop_percent")]
+    pub scrape_drop_percent: usize,
+    /// Seconds between agenda page refreshes, 0 disables auto-refresh
+    #[serde(default = "default_agenda_refresh_seconds")]
+    pub agenda_refresh_seconds: u64,
+    /// Deployment the `remote` subcommands talk to, e.g.
+    /// `https://www.example.com`
+    pub remote_url: Option<StackString>,
+    pub remote_api_key: Option<StackString>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
@@ -62,6 +117,57 @@ fn default_domain() -> StackString {
 fn default_n_db_workers() -> usize {
     2
 }
+fn default_backup_s3_prefix() -> StackString {
+    "calendar_app_rust".into()
+}
+fn default_backup_retention() -> usize {
+    7
+}
+fn default_attachment_path() -> PathBuf {
+    dirs::data_dir()
+        .expect("No DATA directory")
+        .join("calendar_app_rust")
+        .join("attachments")
+}
+fn default_max_attachment_size() -> usize {
+    10 * 1024 * 1024
+}
+fn default_public_rate_limit() -> u32 {
+    30
+}
+fn default_public_lookup_rate_limit() -> u32 {
+    120
+}
+fn default_token_rotation_grace_hours() -> i64 {
+    24
+}
+fn default_share_link_max_minutes() -> i64 {
+    24 * 60
+}
+fn default_tray_reminder_minutes() -> i64 {
+    5
+}
+fn default_stale_sync_hours() -> i64 {
+    12
+}
+fn default_description_summary_length() -> usize {
+    280
+}
+fn default_full_sync_after_incremental() -> usize {
+    96
+}
+fn default_full_sync_drift_percent() -> usize {
+    5
+}
+fn default_full_sync_hour() -> u8 {
+    3
+}
+fn default_scrape_drop_percent() -> usize {
+    50
+}
+fn default_agenda_refresh_seconds() -> u64 {
+    300
+}
 fn default_secret_path() -> PathBuf {
     dirs::config_dir()
         .expect("No CONFIG directory")
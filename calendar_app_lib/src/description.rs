@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+
+/// How much of each event description the json list endpoints return,
+/// clients fetch the full text of a single event when they need it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DescriptionMode {
+    Summary,
+    #[default]
+    Full,
+    None,
+}
+
+impl DescriptionMode {
+    /// Shorten or drop `description` in place, returns true if any text was
+    /// removed.
+    pub fn apply(self, description: &mut Option<StackString>, max_length: usize) -> bool {
+        match self {
+            Self::Full => false,
+            Self::None => description.take().is_some(),
+            Self::Summary => {
+                let Some(summary) = description.as_ref().and_then(|d| summarize(d, max_length))
+                else {
+                    return false;
+                };
+                description.replace(summary);
+                true
+            }
+        }
+    }
+}
+
+/// Cut `description` to at most `max_length` bytes (plus an ellipsis),
+/// preferring a word boundary.  Returns `None` if it already fits.
+#[must_use]
+pub fn summarize(description: &str, max_length: usize) -> Option<StackString> {
+    if description.len() <= max_length {
+        return None;
+    }
+    let mut end = max_length;
+    while !description.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = &description[..end];
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(idx) if idx > end / 2 => &cut[..idx],
+        _ => cut,
+    };
+    Some(format_sstr!("{}…", cut.trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::StackString;
+
+    use crate::description::{summarize, DescriptionMode};
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(summarize("short", 10), None);
+        assert_eq!(
+            summarize("the quick brown fox jumps", 12).unwrap().as_str(),
+            "the quick…"
+        );
+        assert_eq!(
+            summarize("abcdefghijklmnop", 8).unwrap().as_str(),
+            "abcdefgh…"
+        );
+        // Never split a multi byte character
+        assert_eq!(summarize("ééééé", 3).unwrap().as_str(), "é…");
+    }
+
+    #[test]
+    fn test_description_mode() {
+        let long: StackString = "a long description of the event".into();
+
+        let mut description = Some(long.clone());
+        assert!(!DescriptionMode::Full.apply(&mut description, 10));
+        assert_eq!(description.as_ref(), Some(&long));
+
+        assert!(DescriptionMode::Summary.apply(&mut description, 10));
+        assert_eq!(description.as_ref().unwrap().as_str(), "a long…");
+        assert!(!DescriptionMode::Summary.apply(&mut description, 100));
+
+        assert!(DescriptionMode::None.apply(&mut description, 10));
+        assert!(description.is_none());
+        assert!(!DescriptionMode::None.apply(&mut description, 10));
+
+        let mode: DescriptionMode = serde_json::from_str(r#""summary""#).unwrap();
+        assert_eq!(mode, DescriptionMode::Summary);
+        assert_eq!(DescriptionMode::default(), DescriptionMode::Full);
+    }
+}
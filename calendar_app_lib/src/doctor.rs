@@ -0,0 +1,241 @@
+use anyhow::{format_err, Error};
+use refinery::Runner;
+use reqwest::Client;
+use serde::Deserialize;
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashSet, fmt, time::Duration};
+use time_tz::TimeZone as _;
+
+use gcal_lib::gcal_instance::GCalendarInstance;
+
+use crate::{
+    config::Config, parse_hashnyc::URL as HASHNYC_URL, parse_nycruns::URL as NYCRUNS_URL,
+    pgpool::PgPool, timezone::TimeZone,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pass => f.write_str("PASS"),
+            Self::Fail => f.write_str("FAIL"),
+            Self::Skip => f.write_str("SKIP"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DoctorCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: StackString,
+}
+
+impl fmt::Display for DoctorCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.status, self.name, self.detail)
+    }
+}
+
+impl DoctorCheck {
+    fn from_result(name: &'static str, result: Result<StackString, Error>) -> Self {
+        match result {
+            Ok(detail) => Self {
+                name,
+                status: CheckStatus::Pass,
+                detail,
+            },
+            Err(e) => Self {
+                name,
+                status: CheckStatus::Fail,
+                detail: format_sstr!("{e}"),
+            },
+        }
+    }
+
+    fn skip(name: &'static str, detail: &str) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Skip,
+            detail: detail.into(),
+        }
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<StackString, Error> {
+    let conn = pool.get().await?;
+    let row = conn.query_one("SELECT version()", &[]).await?;
+    let version: String = row.try_get(0)?;
+    Ok(version.into())
+}
+
+async fn check_migrations(pool: &PgPool, runner: &Runner) -> Result<StackString, Error> {
+    let mut client = pool.get().await?;
+    let applied: HashSet<_> = runner
+        .get_applied_migrations_async(&mut **client)
+        .await?
+        .iter()
+        .map(|m| m.version())
+        .collect();
+    let pending: Vec<_> = runner
+        .get_migrations()
+        .iter()
+        .filter(|m| !applied.contains(&m.version()))
+        .map(|m| format_sstr!("V{:02}__{}", m.version(), m.name()))
+        .collect();
+    if pending.is_empty() {
+        Ok(format_sstr!("{} migrations applied", applied.len()))
+    } else {
+        Err(format_err!(
+            "{} pending migrations: {}",
+            pending.len(),
+            pending.join(", ")
+        ))
+    }
+}
+
+async fn check_gcal(config: &Config) -> Result<StackString, Error> {
+    let gcal = GCalendarInstance::new(
+        &config.gcal_token_path,
+        &config.gcal_secret_file,
+        "ddboline@gmail.com",
+    )
+    .await?;
+    let calendars = gcal.list_gcal_calendars().await?;
+    Ok(format_sstr!("token valid, {} calendars", calendars.len()))
+}
+
+async fn check_url(client: &Client, url: &str) -> Result<StackString, Error> {
+    let status = client.get(url).send().await?.error_for_status()?.status();
+    Ok(format_sstr!("{url} {status}"))
+}
+
+async fn check_telegram(client: &Client, token: &str) -> Result<StackString, Error> {
+    #[derive(Deserialize)]
+    struct GetMe {
+        ok: bool,
+        description: Option<StackString>,
+    }
+
+    // The url contains the bot token, keep it out of the reported error
+    let url = format_sstr!("https://api.telegram.org/bot{token}/getMe");
+    let result: GetMe = client
+        .get(url.as_str())
+        .send()
+        .await
+        .map_err(reqwest::Error::without_url)?
+        .json()
+        .await
+        .map_err(reqwest::Error::without_url)?;
+    if result.ok {
+        Ok("telegram bot token valid".into())
+    } else {
+        Err(format_err!(
+            "telegram rejected token: {}",
+            result.description.as_deref().unwrap_or("unknown error")
+        ))
+    }
+}
+
+fn check_timezones(config: &Config) -> Result<StackString, Error> {
+    let new_york: TimeZone = "America/New_York".parse()?;
+    let system = time_tz::system::get_timezone()
+        .map_err(|e| format_err!("system timezone unavailable: {e:?}"))?;
+    let default = config.default_time_zone.unwrap_or_else(TimeZone::local);
+    Ok(format_sstr!(
+        "{new_york} found, system {}, default {default}",
+        system.name()
+    ))
+}
+
+/// Check everything the app needs from its environment, one entry per
+/// check.  Network checks are skipped when `offline` is set.
+pub async fn run_doctor(
+    config: &Config,
+    pool: &PgPool,
+    runner: &Runner,
+    offline: bool,
+) -> Vec<DoctorCheck> {
+    let mut checks = vec![
+        DoctorCheck::from_result("database", check_database(pool).await),
+        DoctorCheck::from_result("migrations", check_migrations(pool, runner).await),
+        DoctorCheck::from_result("timezones", check_timezones(config)),
+    ];
+    if offline {
+        for name in ["gcal", "hashnyc", "nycruns", "telegram"] {
+            checks.push(DoctorCheck::skip(name, "offline"));
+        }
+        return checks;
+    }
+    checks.push(DoctorCheck::from_result("gcal", check_gcal(config).await));
+    let client = match Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            checks.push(DoctorCheck::from_result("http client", Err(e.into())));
+            return checks;
+        }
+    };
+    checks.push(DoctorCheck::from_result(
+        "hashnyc",
+        check_url(&client, HASHNYC_URL).await,
+    ));
+    checks.push(DoctorCheck::from_result(
+        "nycruns",
+        check_url(&client, NYCRUNS_URL).await,
+    ));
+    checks.push(match config.telegram_bot_token.as_ref() {
+        Some(token) => DoctorCheck::from_result("telegram", check_telegram(&client, token).await),
+        None => DoctorCheck::skip("telegram", "TELEGRAM_BOT_TOKEN not set"),
+    });
+    checks
+}
+
+/// Report lines for `checks` followed by a pass/fail summary
+#[must_use]
+pub fn doctor_report(checks: &[DoctorCheck]) -> Vec<StackString> {
+    let count = |status| checks.iter().filter(|c| c.status == status).count();
+    let mut output: Vec<StackString> = checks.iter().map(StackString::from_display).collect();
+    output.push(format_sstr!(
+        "{} passed, {} failed, {} skipped",
+        count(CheckStatus::Pass),
+        count(CheckStatus::Fail),
+        count(CheckStatus::Skip),
+    ));
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::format_err;
+    use stack_string::StackString;
+
+    use crate::doctor::{doctor_report, CheckStatus, DoctorCheck};
+
+    #[test]
+    fn test_doctor_report() {
+        let checks = vec![
+            DoctorCheck::from_result("database", Ok("PostgreSQL 16".into())),
+            DoctorCheck::from_result("gcal", Err(format_err!("token expired"))),
+            DoctorCheck::skip("telegram", "TELEGRAM_BOT_TOKEN not set"),
+        ];
+        assert_eq!(checks[1].status, CheckStatus::Fail);
+        let report = doctor_report(&checks);
+        assert_eq!(
+            report.iter().map(StackString::as_str).collect::<Vec<_>>(),
+            vec![
+                "[PASS] database: PostgreSQL 16",
+                "[FAIL] gcal: token expired",
+                "[SKIP] telegram: TELEGRAM_BOT_TOKEN not set",
+                "1 passed, 1 failed, 1 skipped",
+            ]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{format_err, Error};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use stack_string::StackString;
+use std::convert::TryInto;
+
+use gcal_lib::date_time_wrapper::DateTimeWrapper;
+
+/// Version of the export envelope written by `Export`, bump this (and add a
+/// step to `migrate_export`) whenever the exported models change shape.
+pub const EXPORT_SCHEMA_VERSION: u32 = 2;
+
+/// Exports written before versioning was introduced were bare json arrays.
+const LEGACY_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportEnvelope<T> {
+    pub schema_version: u32,
+    pub table: StackString,
+    pub exported_at: DateTimeWrapper,
+    pub data: T,
+}
+
+/// # Errors
+/// Returns error if serialization fails
+pub fn serialize_export<T: Serialize>(table: &str, data: &[T]) -> Result<Vec<u8>, Error> {
+    let envelope = ExportEnvelope {
+        schema_version: EXPORT_SCHEMA_VERSION,
+        table: table.into(),
+        exported_at: DateTimeWrapper::now(),
+        data,
+    };
+    serde_json::to_vec(&envelope).map_err(Into::into)
+}
+
+/// Parse an export produced by this or any previous release, migrating it to
+/// the current schema before deserializing.
+/// # Errors
+/// Returns error if the export is malformed, was written for another table or
+/// by a newer release
+pub fn deserialize_export<T: DeserializeOwned>(table: &str, data: &[u8]) -> Result<Vec<T>, Error> {
+    let value: Value = serde_json::from_slice(data)?;
+    let envelope = migrate_export(table, value)?;
+    let envelope: ExportEnvelope<Vec<T>> = serde_json::from_value(envelope)?;
+    Ok(envelope.data)
+}
+
+fn migrate_export(table: &str, value: Value) -> Result<Value, Error> {
+    let mut value = match value {
+        Value::Array(data) => serde_json::json!({
+            "schema_version": LEGACY_SCHEMA_VERSION,
+            "table": table,
+            "exported_at": DateTimeWrapper::now(),
+            "data": data,
+        }),
+        Value::Object(_) => value,
+        _ => return Err(format_err!("Unrecognized export format")),
+    };
+    let version = value
+        .get("schema_version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| format_err!("Export is missing schema_version"))?;
+    let mut version: u32 = version.try_into()?;
+    if version > EXPORT_SCHEMA_VERSION {
+        return Err(format_err!(
+            "Export schema version {version} is newer than supported version \
+             {EXPORT_SCHEMA_VERSION}"
+        ));
+    }
+    let export_table = value.get("table").and_then(Value::as_str).unwrap_or("");
+    if export_table != table {
+        return Err(format_err!(
+            "Export contains table {export_table}, expected {table}"
+        ));
+    }
+    while version < EXPORT_SCHEMA_VERSION {
+        // Version 1 -> 2 only introduced the envelope, the rows are unchanged
+        version += 1;
+        value["schema_version"] = version.into();
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::{Duration, OffsetDateTime};
+
+    use crate::{
+        calendar::Event,
+        export_format::{deserialize_export, serialize_export},
+        models::CalendarCache,
+    };
+
+    #[test]
+    fn test_export_roundtrip() -> Result<(), Error> {
+        let now = OffsetDateTime::now_utc();
+        let event: CalendarCache =
+            Event::new("ddboline@gmail.com", "Test", now, now + Duration::hours(1)).into();
+        let events = vec![event];
+
+        let data = serialize_export("calendar_cache", &events)?;
+        let result: Vec<CalendarCache> = deserialize_export("calendar_cache", &data)?;
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].event_id, events[0].event_id);
+
+        assert!(deserialize_export::<CalendarCache>("calendar_list", &data).is_err());
+
+        let legacy = serde_json::to_vec(&events)?;
+        let result: Vec<CalendarCache> = deserialize_export("calendar_cache", &legacy)?;
+        assert_eq!(result[0].event_id, events[0].event_id);
+
+        let future = br#"{"schema_version": 1000, "table": "calendar_cache", "data": []}"#;
+        assert!(deserialize_export::<CalendarCache>("calendar_cache", future).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::Error;
+use futures::TryStreamExt;
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, fmt};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{
+    calendar_sync::CalendarSync,
+    models::{CalendarCache, CalendarList},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckIssue {
+    EndBeforeStart {
+        gcal_id: StackString,
+        event_id: StackString,
+    },
+    InvalidLatitude {
+        gcal_id: StackString,
+        event_id: StackString,
+        latitude: f64,
+    },
+    InvalidLongitude {
+        gcal_id: StackString,
+        event_id: StackString,
+        longitude: f64,
+    },
+    OrphanedEvent {
+        gcal_id: StackString,
+        event_id: StackString,
+    },
+    EventPredatesCalendar {
+        gcal_id: StackString,
+        event_id: StackString,
+    },
+    InvalidUrl {
+        gcal_id: StackString,
+        event_id: StackString,
+        url: StackString,
+    },
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fixable = if self.is_repairable() {
+            "fixable"
+        } else {
+            "manual"
+        };
+        match self {
+            Self::EndBeforeStart { gcal_id, event_id } => {
+                write!(f, "[{fixable}] end before start {gcal_id} {event_id}")
+            }
+            Self::InvalidLatitude {
+                gcal_id,
+                event_id,
+                latitude,
+            } => write!(
+                f,
+                "[{fixable}] invalid latitude {latitude} {gcal_id} {event_id}"
+            ),
+            Self::InvalidLongitude {
+                gcal_id,
+                event_id,
+                longitude,
+            } => write!(
+                f,
+                "[{fixable}] invalid longitude {longitude} {gcal_id} {event_id}"
+            ),
+            Self::OrphanedEvent { gcal_id, event_id } => {
+                write!(f, "[{fixable}] orphaned event {gcal_id} {event_id}")
+            }
+            Self::EventPredatesCalendar { gcal_id, event_id } => write!(
+                f,
+                "[{fixable}] event cached before calendar was created {gcal_id} {event_id}"
+            ),
+            Self::InvalidUrl {
+                gcal_id,
+                event_id,
+                url,
+            } => write!(f, "[{fixable}] invalid url {url} {gcal_id} {event_id}"),
+        }
+    }
+}
+
+impl FsckIssue {
+    /// Whether `--repair` corrects the issue, the rest need a look by hand
+    #[must_use]
+    pub fn is_repairable(&self) -> bool {
+        !matches!(self, Self::EventPredatesCalendar { .. })
+    }
+
+    fn key(&self) -> (&str, &str) {
+        match self {
+            Self::EndBeforeStart { gcal_id, event_id }
+            | Self::InvalidLatitude {
+                gcal_id, event_id, ..
+            }
+            | Self::InvalidLongitude {
+                gcal_id, event_id, ..
+            }
+            | Self::OrphanedEvent { gcal_id, event_id }
+            | Self::EventPredatesCalendar { gcal_id, event_id }
+            | Self::InvalidUrl {
+                gcal_id, event_id, ..
+            } => (gcal_id.as_str(), event_id.as_str()),
+        }
+    }
+}
+
+/// Check cached events against the invariants the rest of the app assumes,
+/// `calendars` maps each known `gcal_id` to its creation time (if recorded).
+#[must_use]
+pub fn check_events(
+    events: &[CalendarCache],
+    calendars: &HashMap<StackString, Option<OffsetDateTime>>,
+) -> Vec<FsckIssue> {
+    let mut issues = Vec::new();
+
+    for event in events {
+        let gcal_id = event.gcal_id.clone();
+        let event_id = event.event_id.clone();
+
+        if event.event_end_time < event.event_start_time {
+            issues.push(FsckIssue::EndBeforeStart {
+                gcal_id: gcal_id.clone(),
+                event_id: event_id.clone(),
+            });
+        }
+        if let Some(latitude) = event.event_location_lat {
+            if !(-90.0..=90.0).contains(&latitude) {
+                issues.push(FsckIssue::InvalidLatitude {
+                    gcal_id: gcal_id.clone(),
+                    event_id: event_id.clone(),
+                    latitude,
+                });
+            }
+        }
+        if let Some(longitude) = event.event_location_lon {
+            if !(-180.0..=180.0).contains(&longitude) {
+                issues.push(FsckIssue::InvalidLongitude {
+                    gcal_id: gcal_id.clone(),
+                    event_id: event_id.clone(),
+                    longitude,
+                });
+            }
+        }
+        match calendars.get(&event.gcal_id) {
+            None => issues.push(FsckIssue::OrphanedEvent {
+                gcal_id: gcal_id.clone(),
+                event_id: event_id.clone(),
+            }),
+            Some(Some(created_at)) if *event.last_modified < *created_at => {
+                issues.push(FsckIssue::EventPredatesCalendar {
+                    gcal_id: gcal_id.clone(),
+                    event_id: event_id.clone(),
+                });
+            }
+            Some(_) => {}
+        }
+        if let Some(url) = &event.event_url {
+            if url.parse::<Url>().is_err() {
+                issues.push(FsckIssue::InvalidUrl {
+                    gcal_id,
+                    event_id,
+                    url: url.clone(),
+                });
+            }
+        }
+    }
+    issues
+}
+
+fn apply_fix(issue: &FsckIssue, event: &mut CalendarCache) {
+    match issue {
+        FsckIssue::EndBeforeStart { .. } => {
+            event.event_end_time = event.event_start_time;
+        }
+        FsckIssue::InvalidLatitude { .. } | FsckIssue::InvalidLongitude { .. } => {
+            event.event_location_lat = None;
+            event.event_location_lon = None;
+        }
+        FsckIssue::InvalidUrl { .. } => {
+            event.event_url = None;
+        }
+        FsckIssue::OrphanedEvent { .. } | FsckIssue::EventPredatesCalendar { .. } => {}
+    }
+}
+
+/// Run all consistency checks and return a report, one line per issue,
+/// if `repair` is set fixable issues are corrected in the database.
+/// Orphaned events are removed with their attachments.
+/// # Errors
+/// Returns error if db queries or removing attachments fail
+pub async fn run_fsck(cal_sync: &CalendarSync, repair: bool) -> Result<Vec<StackString>, Error> {
+    let pool = &cal_sync.pool;
+    let calendars = CalendarList::get_created_at_map(pool).await?;
+    let events: Vec<_> = CalendarCache::get_recent(pool, None, None, None, None)
+        .await?
+        .try_collect()
+        .await?;
+    let issues = check_events(&events, &calendars);
+
+    let mut output: Vec<StackString> = issues.iter().map(StackString::from_display).collect();
+    let fixable = issues.iter().filter(|i| i.is_repairable()).count();
+    output.push(format_sstr!(
+        "checked {} events in {} calendars, found {} issues ({fixable} fixable)",
+        events.len(),
+        calendars.len(),
+        issues.len(),
+    ));
+
+    if repair {
+        let event_map: HashMap<_, _> = events
+            .iter()
+            .map(|e| ((e.gcal_id.as_str(), e.event_id.as_str()), e))
+            .collect();
+        let mut fixed: HashMap<(&str, &str), (CalendarCache, bool)> = HashMap::new();
+        for issue in issues.iter().filter(|i| i.is_repairable()) {
+            let Some(event) = event_map.get(&issue.key()) else {
+                continue;
+            };
+            let (event, delete) = fixed
+                .entry(issue.key())
+                .or_insert_with(|| ((*event).clone(), false));
+            apply_fix(issue, event);
+            if let FsckIssue::OrphanedEvent { .. } = issue {
+                *delete = true;
+            }
+        }
+        // Rows removed since they were checked aren't counted
+        let mut repaired = 0;
+        for (event, delete) in fixed.values() {
+            repaired += if *delete {
+                cal_sync.delete_cached_event(event).await?
+            } else {
+                event.update(pool).await?
+            };
+        }
+        output.push(format_sstr!(
+            "repaired {repaired} of {} events",
+            fixed.len()
+        ));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::StackString;
+    use std::collections::HashMap;
+    use time::{Duration, OffsetDateTime};
+
+    use crate::{
+        calendar::Event,
+        fsck::{check_events, FsckIssue},
+        models::CalendarCache,
+    };
+
+    #[test]
+    fn test_check_events() {
+        let now = OffsetDateTime::now_utc();
+        let good: CalendarCache =
+            Event::new("good_calendar", "Good", now, now + Duration::hours(1)).into();
+
+        let mut bad: CalendarCache =
+            Event::new("good_calendar", "Bad", now, now - Duration::hours(1)).into();
+        bad.event_location_lat = Some(91.0);
+        bad.event_location_lon = Some(-181.0);
+        bad.event_url = Some("not a url".into());
+
+        let orphan: CalendarCache =
+            Event::new("missing_calendar", "Orphan", now, now + Duration::hours(1)).into();
+
+        let mut calendars: HashMap<StackString, Option<OffsetDateTime>> =
+            vec![("good_calendar".into(), None)].into_iter().collect();
+
+        let events = vec![good.clone(), bad.clone(), orphan.clone()];
+        let issues = check_events(&events, &calendars);
+
+        assert_eq!(issues.len(), 5);
+        assert_eq!(issues.iter().filter(|i| i.is_repairable()).count(), 5);
+        assert!(issues.contains(&FsckIssue::EndBeforeStart {
+            gcal_id: bad.gcal_id.clone(),
+            event_id: bad.event_id.clone(),
+        }));
+        assert!(issues.contains(&FsckIssue::OrphanedEvent {
+            gcal_id: orphan.gcal_id.clone(),
+            event_id: orphan.event_id.clone(),
+        }));
+        assert!(!issues.iter().any(|i| i.key().1 == good.event_id.as_str()));
+
+        // Calendars without a recorded creation time aren't checked
+        calendars.insert("good_calendar".into(), Some(now + Duration::days(1)));
+        let issues = check_events(&[good.clone()], &calendars);
+        let predates = FsckIssue::EventPredatesCalendar {
+            gcal_id: good.gcal_id.clone(),
+            event_id: good.event_id.clone(),
+        };
+        assert_eq!(issues, vec![predates.clone()]);
+        assert!(!predates.is_repairable());
+        assert!(StackString::from_display(&predates).starts_with("[manual] "));
+    }
+}
@@ -0,0 +1,116 @@
+use stack_string::{format_sstr, StackString};
+use time::{macros::format_description, OffsetDateTime, UtcOffset};
+
+use crate::calendar::Event;
+
+/// Escape TEXT values as described in RFC 5545 section 3.3.11
+fn escape_text(text: &str) -> StackString {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            ';' => output.push_str("\\;"),
+            ',' => output.push_str("\\,"),
+            '\n' => output.push_str("\\n"),
+            '\r' => {}
+            c => output.push(c),
+        }
+    }
+    output.into()
+}
+
+/// Fold content lines longer than 75 octets, continuation lines start with a
+/// single space.
+fn fold_line(line: &str, output: &mut String) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            output.push_str("\r\n ");
+            width = 1;
+        }
+        output.push(c);
+        width += c.len_utf8();
+    }
+    output.push_str("\r\n");
+}
+
+fn format_utc(dt: OffsetDateTime) -> StackString {
+    dt.to_offset(UtcOffset::UTC)
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]Z"
+        ))
+        .unwrap_or_default()
+        .into()
+}
+
+/// Render `events` as an iCalendar feed
+#[must_use]
+pub fn events_to_ics(calendar_name: &str, events: &[Event]) -> String {
+    let now = format_utc(OffsetDateTime::now_utc());
+    let mut output = String::new();
+    let mut push = |line: &str| fold_line(line, &mut output);
+    push("BEGIN:VCALENDAR");
+    push("VERSION:2.0");
+    push("PRODID:-//calendar_app_rust//EN");
+    push("CALSCALE:GREGORIAN");
+    push(&format_sstr!("X-WR-CALNAME:{}", escape_text(calendar_name)));
+    for event in events {
+        push("BEGIN:VEVENT");
+        push(&format_sstr!(
+            "UID:{}@{}",
+            escape_text(&event.event_id),
+            escape_text(&event.gcal_id)
+        ));
+        push(&format_sstr!("DTSTAMP:{now}"));
+        push(&format_sstr!(
+            "DTSTART:{}",
+            format_utc(event.start_time.into())
+        ));
+        push(&format_sstr!("DTEND:{}", format_utc(event.end_time.into())));
+        push(&format_sstr!("SUMMARY:{}", escape_text(&event.name)));
+        if let Some(description) = &event.description {
+            push(&format_sstr!("DESCRIPTION:{}", escape_text(description)));
+        }
+        if let Some(url) = &event.url {
+            push(&format_sstr!("URL:{url}"));
+        }
+        if let Some(location) = &event.location {
+            push(&format_sstr!("LOCATION:{}", escape_text(&location.name)));
+            if let Some((lat, lon)) = &location.lat_lon {
+                push(&format_sstr!("GEO:{lat};{lon}"));
+            }
+        }
+        push("END:VEVENT");
+    }
+    push("END:VCALENDAR");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+
+    use crate::{
+        calendar::Event,
+        ics::{escape_text, events_to_ics},
+    };
+
+    #[test]
+    fn test_events_to_ics() {
+        let start = datetime!(2024-03-01 18:30:00 UTC);
+        let mut event = Event::new("cal@example.com", "Dinner, drinks", start, start);
+        event.end_time = (start + Duration::hours(2)).into();
+        event.description = Some("Line one\nLine two; ".repeat(10).into());
+
+        let ics = events_to_ics("Test Calendar", &[event]);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert!(ics.contains("DTSTART:20240301T183000Z\r\n"));
+        assert!(ics.contains("DTEND:20240301T203000Z\r\n"));
+        assert!(ics.contains("SUMMARY:Dinner\\, drinks\r\n"));
+        assert!(ics
+            .lines()
+            .all(|line| line.trim_end_matches('\r').len() <= 75));
+        assert_eq!(&escape_text("a\\b;c,d\ne"), "a\\\\b\\;c\\,d\\ne");
+    }
+}
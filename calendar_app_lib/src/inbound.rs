@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+use gcal_lib::date_time_wrapper::DateTimeWrapper;
+
+use crate::models::{CalendarCache, InboundSource};
+
+/// Optional payload fields a source may declare as required
+pub const OPTIONAL_FIELDS: [&str; 5] =
+    ["external_id", "end_time", "description", "url", "location"];
+
+const MAX_NAME_LENGTH: usize = 1024;
+
+/// Event posted by an external producer to `/calendar/inbound/{source_token}`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InboundEvent {
+    pub external_id: Option<StackString>,
+    pub name: StackString,
+    pub start_time: DateTimeWrapper,
+    pub end_time: Option<DateTimeWrapper>,
+    pub description: Option<StackString>,
+    pub url: Option<StackString>,
+    pub location: Option<StackString>,
+}
+
+impl InboundEvent {
+    fn has_field(&self, field: &str) -> bool {
+        match field {
+            "external_id" => self.external_id.is_some(),
+            "end_time" => self.end_time.is_some(),
+            "description" => self.description.is_some(),
+            "url" => self.url.is_some(),
+            "location" => self.location.is_some(),
+            _ => false,
+        }
+    }
+
+    fn end_time(&self) -> OffsetDateTime {
+        self.end_time.unwrap_or(self.start_time).into()
+    }
+
+    /// Check the payload against the schema configured for `source`, returns
+    /// a message suitable for the producer on failure.
+    /// # Errors
+    /// Returns error describing the first problem found
+    pub fn validate(&self, source: &InboundSource) -> Result<(), StackString> {
+        if self.name.trim().is_empty() {
+            return Err("name must not be empty".into());
+        }
+        if self.name.len() > MAX_NAME_LENGTH {
+            return Err(format_sstr!("name longer than {MAX_NAME_LENGTH} bytes"));
+        }
+        if let Some(field) = source
+            .required_fields
+            .iter()
+            .find(|field| !self.has_field(field))
+        {
+            return Err(format_sstr!("missing required field {field}"));
+        }
+        let start_time: OffsetDateTime = self.start_time.into();
+        let duration = self.end_time() - start_time;
+        if duration.is_negative() {
+            return Err("end_time is before start_time".into());
+        }
+        if let Some(max_duration) = source.max_duration_minutes {
+            if duration > Duration::minutes(max_duration.into()) {
+                return Err(format_sstr!("event longer than {max_duration} minutes"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Events with an `external_id` map to a stable event id, so producers
+    /// can re-post an event to update it.  The id is derived from the source
+    /// token as source names aren't unique.
+    #[must_use]
+    pub fn into_calendar_cache(self, source: &InboundSource) -> CalendarCache {
+        let event_id = self.external_id.as_ref().map_or_else(Uuid::new_v4, |id| {
+            let name = format_sstr!("{}/{}/{id}", source.gcal_id, source.source_token);
+            Uuid::new_v5(&Uuid::NAMESPACE_URL, name.as_bytes())
+        });
+        let end_time = self.end_time();
+        CalendarCache {
+            event_id: event_id.simple().to_string().into(),
+            gcal_id: source.gcal_id.clone(),
+            event_start_time: self.start_time,
+            event_end_time: end_time.into(),
+            event_url: self.url,
+            event_name: self.name,
+            event_description: self.description,
+            event_location_name: self.location,
+            event_location_lat: None,
+            event_location_lon: None,
+            last_modified: OffsetDateTime::now_utc().into(),
+            source_id: None,
+            source_modified: None,
+            editable: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+
+    use crate::{inbound::InboundEvent, models::InboundSource};
+
+    #[test]
+    fn test_inbound_event() {
+        let mut source = InboundSource::new("ci", "user@example.com", "cal@example.com");
+        source.required_fields = vec!["external_id".into()];
+        source.max_duration_minutes = Some(120);
+
+        let start = datetime!(2024-05-01 12:00:00 UTC);
+        let mut event = InboundEvent {
+            external_id: None,
+            name: "Deploy".into(),
+            start_time: start.into(),
+            end_time: Some((start + Duration::minutes(30)).into()),
+            description: None,
+            url: None,
+            location: None,
+        };
+        assert_eq!(
+            event.validate(&source).unwrap_err().as_str(),
+            "missing required field external_id"
+        );
+        event.external_id = Some("build-42".into());
+        assert!(event.validate(&source).is_ok());
+
+        event.end_time = Some((start + Duration::hours(3)).into());
+        assert!(event.validate(&source).is_err());
+        event.end_time = Some((start - Duration::hours(1)).into());
+        assert!(event.validate(&source).is_err());
+        event.end_time = None;
+        assert!(event.validate(&source).is_ok());
+
+        let first = event.clone().into_calendar_cache(&source);
+        let second = event.clone().into_calendar_cache(&source);
+        assert_eq!(first.event_id, second.event_id);
+        let other_source = InboundSource::new("ci", "other@example.com", "cal@example.com");
+        let other = event.into_calendar_cache(&other_source);
+        assert_ne!(first.event_id, other.event_id);
+        assert_eq!(first.event_id.len(), 32);
+        assert_eq!(first.gcal_id, source.gcal_id);
+        assert_eq!(first.event_start_time, first.event_end_time);
+    }
+}
@@ -6,16 +6,34 @@
 #![allow(clippy::cast_possible_wrap)]
 #![allow(clippy::similar_names)]
 
+pub mod attachments;
+pub mod backup_crypt;
 pub mod calendar;
 pub mod calendar_cli_opts;
 pub mod calendar_sync;
 pub mod config;
+pub mod description;
+pub mod doctor;
+pub mod export_format;
+pub mod fsck;
+pub mod ics;
+pub mod inbound;
 pub mod latitude;
 pub mod longitude;
 pub mod models;
+pub mod notification_sim;
 pub mod parse_hashnyc;
 pub mod parse_nycruns;
 pub mod pgpool;
+pub mod remote;
+pub mod s3_backup;
+pub mod scrape_health;
+pub mod shutdown;
+pub mod signed_url;
+pub mod stats;
+pub mod sync_plan;
+pub mod sync_schedule;
+pub mod tickets;
 pub mod timezone;
 
 use anyhow::Error;
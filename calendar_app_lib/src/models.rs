@@ -5,13 +5,18 @@ use postgres_query::{
     client::GenericClient, query, query_dyn, Error as PqError, FromSqlRow, Parameter, Query,
 };
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use stack_string::{format_sstr, StackString};
-use std::{cmp, convert::TryInto, io};
-use time::OffsetDateTime;
+use std::{cmp, collections::HashMap, convert::TryInto, io};
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
 
 use gcal_lib::date_time_wrapper::DateTimeWrapper;
 
-use crate::pgpool::{PgPool, PgTransaction};
+use crate::{
+    calendar::access_role_can_edit,
+    pgpool::{PgPool, PgTransaction},
+};
 
 #[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize)]
 pub struct CalendarList {
@@ -25,6 +30,9 @@ pub struct CalendarList {
     pub last_modified: DateTimeWrapper,
     pub edit: bool,
     pub display: bool,
+    /// Our `accessRole` on the gcal calendar (owner, writer, reader or
+    /// freeBusyReader), unknown until the calendar list is synced
+    pub access_role: Option<StackString>,
 }
 
 impl CalendarList {
@@ -41,9 +49,16 @@ impl CalendarList {
             last_modified: DateTimeWrapper::now(),
             edit: false,
             display: false,
+            access_role: None,
         }
     }
 
+    /// False if gcal only grants read access to the calendar
+    #[must_use]
+    pub fn is_writable(&self) -> bool {
+        access_role_can_edit(self.access_role.as_deref())
+    }
+
     /// # Errors
     /// Returns error if db query fails
     pub async fn get_calendars(
@@ -58,6 +73,7 @@ impl CalendarList {
         select_str: &'a str,
         order_str: &'a str,
         modified: Option<&'a OffsetDateTime>,
+        gcal_id: Option<&'a StackString>,
         offset: Option<usize>,
         limit: Option<usize>,
     ) -> Result<Query<'a>, PqError> {
@@ -67,6 +83,10 @@ impl CalendarList {
             constraints.push(format_sstr!("last_modified > $modified"));
             bindings.push(("modified", modified as Parameter));
         }
+        if let Some(gcal_id) = gcal_id {
+            constraints.push(format_sstr!("gcal_id = $gcal_id"));
+            bindings.push(("gcal_id", gcal_id as Parameter));
+        }
         let where_str = if constraints.is_empty() {
             "".into()
         } else {
@@ -93,13 +113,15 @@ impl CalendarList {
     pub async fn get_total(
         pool: &PgPool,
         modified: Option<OffsetDateTime>,
+        gcal_id: Option<&StackString>,
     ) -> Result<usize, Error> {
         #[derive(FromSqlRow)]
         struct Count {
             count: i64,
         }
 
-        let query = Self::get_calendar_list_query("count(*)", "", modified.as_ref(), None, None)?;
+        let query =
+            Self::get_calendar_list_query("count(*)", "", modified.as_ref(), gcal_id, None, None)?;
 
         let conn = pool.get().await?;
         let count: Count = query.fetch_one(&conn).await?;
@@ -168,6 +190,7 @@ impl CalendarList {
                     gcal_description=$gcal_description,
                     gcal_location=$gcal_location,
                     gcal_timezone=$gcal_timezone,
+                    access_role=COALESCE($access_role, access_role),
                     last_modified=now()
                 WHERE gcal_id=$gcal_id
             "#,
@@ -177,6 +200,7 @@ impl CalendarList {
             gcal_description = self.gcal_description,
             gcal_location = self.gcal_location,
             gcal_timezone = self.gcal_timezone,
+            access_role = self.access_role,
         );
         query.execute(&conn).await?;
         Ok(())
@@ -194,11 +218,34 @@ impl CalendarList {
         Ok(result.map(Into::into))
     }
 
+    /// Creation time of each calendar, `None` for calendars added before
+    /// `created_at` was recorded
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_created_at_map(
+        pool: &PgPool,
+    ) -> Result<HashMap<StackString, Option<OffsetDateTime>>, Error> {
+        #[derive(FromSqlRow)]
+        struct CreatedAt {
+            gcal_id: StackString,
+            created_at: Option<OffsetDateTime>,
+        }
+
+        let query = query!("SELECT gcal_id, created_at FROM calendar_list");
+        let conn = pool.get().await?;
+        let rows: Vec<CreatedAt> = query.fetch(&conn).await?;
+        Ok(rows
+            .into_iter()
+            .map(|c| (c.gcal_id, c.created_at))
+            .collect())
+    }
+
     /// # Errors
     /// Returns error if db query fails
     pub async fn get_recent(
         pool: &PgPool,
         modified: Option<OffsetDateTime>,
+        gcal_id: Option<&StackString>,
         offset: Option<usize>,
         limit: Option<usize>,
     ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
@@ -206,6 +253,7 @@ impl CalendarList {
             "*",
             "ORDER BY calendar_name",
             modified.as_ref(),
+            gcal_id,
             offset,
             limit,
         )?;
@@ -222,10 +270,10 @@ impl CalendarList {
             r#"
                 INSERT INTO calendar_list (
                     calendar_name, gcal_id, gcal_name, gcal_description, gcal_location,
-                    gcal_timezone, sync, last_modified, edit, display
+                    gcal_timezone, sync, last_modified, edit, display, access_role
                 ) VALUES (
                     $calendar_name, $gcal_id, $gcal_name, $gcal_description, $gcal_location,
-                    $gcal_timezone, $sync, now(), $edit, $display
+                    $gcal_timezone, $sync, now(), $edit, $display, $access_role
                 )
             "#,
             calendar_name = self.calendar_name,
@@ -237,6 +285,7 @@ impl CalendarList {
             sync = self.sync,
             edit = self.edit,
             display = self.display,
+            access_role = self.access_role,
         );
         query.execute(conn).await?;
         Ok(())
@@ -273,9 +322,41 @@ pub struct CalendarCache {
     pub event_location_lat: Option<f64>,
     pub event_location_lon: Option<f64>,
     pub last_modified: DateTimeWrapper,
+    pub source_id: Option<StackString>,
+    pub source_modified: Option<DateTimeWrapper>,
+    /// False if gcal would reject changes to the event
+    #[serde(default = "default_editable")]
+    pub editable: bool,
+}
+
+fn default_editable() -> bool {
+    true
 }
 
 impl CalendarCache {
+    /// Merge clock of this version of the event: when and where the edit was
+    /// originally made.  Rows without a source were edited locally, so fall
+    /// back to `last_modified` and `local_source_id`, the clock they are
+    /// exported with.
+    fn merge_clock<'a>(&'a self, local_source_id: &'a str) -> (OffsetDateTime, &'a str) {
+        let modified = self.source_modified.unwrap_or(self.last_modified);
+        let source_id = self
+            .source_id
+            .as_ref()
+            .map_or(local_source_id, StackString::as_str);
+        (modified.into(), source_id)
+    }
+
+    /// Whether this version should replace `existing` when both are merged.
+    /// The later edit wins, ties are broken on `source_id` so that every
+    /// deployment picks the same winner regardless of the order it receives
+    /// updates in.  Re-applying the same version is a no-op.
+    /// `local_source_id` is this deployment's configured source id.
+    #[must_use]
+    pub fn wins_over(&self, existing: &Self, local_source_id: &str) -> bool {
+        self.merge_clock(local_source_id) >= existing.merge_clock(local_source_id)
+    }
+
     /// # Errors
     /// Returns error if db query fails
     pub async fn get_by_gcal_id(
@@ -373,15 +454,57 @@ impl CalendarCache {
         query.fetch_streaming(&conn).await.map_err(Into::into)
     }
 
+    /// Events of `gcal_id` (every calendar if `None`) overlapping
+    /// `min_time..max_time` and modified after `modified`, for exports
     /// # Errors
     /// Returns error if db query fails
-    pub async fn update(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn get_filtered(
+        gcal_id: Option<&str>,
+        min_time: Option<OffsetDateTime>,
+        max_time: Option<OffsetDateTime>,
+        modified: Option<OffsetDateTime>,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<CalendarCache, PqError>>, Error> {
+        let mut conditions = Vec::new();
+        let mut bindings = Vec::new();
+
+        if let Some(gcal_id) = &gcal_id {
+            conditions.push("gcal_id = $gcal_id");
+            bindings.push(("gcal_id", gcal_id as Parameter));
+        }
+        if let Some(max_time) = &max_time {
+            conditions.push("event_start_time < $max_time");
+            bindings.push(("max_time", max_time as Parameter));
+        }
+        if let Some(min_time) = &min_time {
+            conditions.push("event_end_time >= $min_time");
+            bindings.push(("min_time", min_time as Parameter));
+        }
+        if let Some(modified) = &modified {
+            conditions.push("last_modified > $modified");
+            bindings.push(("modified", modified as Parameter));
+        }
+        let where_str = if conditions.is_empty() {
+            "".into()
+        } else {
+            format_sstr!("WHERE {}", conditions.join(" AND "))
+        };
+        let query =
+            format_sstr!("SELECT * FROM calendar_cache {where_str} ORDER BY event_start_time");
+        let query = query_dyn!(&query, ..bindings)?;
         let conn = pool.get().await?;
-        self.update_conn(&conn).await?;
-        Ok(())
+        query.fetch_streaming(&conn).await.map_err(Into::into)
     }
 
-    async fn update_conn<C>(&self, conn: &C) -> Result<(), Error>
+    /// Returns the number of rows updated
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn update(&self, pool: &PgPool) -> Result<u64, Error> {
+        let conn = pool.get().await?;
+        self.update_conn(&conn).await
+    }
+
+    async fn update_conn<C>(&self, conn: &C) -> Result<u64, Error>
     where
         C: GenericClient + Sync,
     {
@@ -396,6 +519,9 @@ impl CalendarCache {
                     event_location_name=$event_location_name,
                     event_location_lat=$event_location_lat,
                     event_location_lon=$event_location_lon,
+                    source_id=$source_id,
+                    source_modified=$source_modified,
+                    editable=$editable,
                     last_modified=now()
                 WHERE gcal_id=$gcal_id AND event_id=$event_id
             "#,
@@ -409,22 +535,24 @@ impl CalendarCache {
             event_location_name = self.event_location_name,
             event_location_lat = self.event_location_lat,
             event_location_lon = self.event_location_lon,
+            source_id = self.source_id,
+            source_modified = self.source_modified,
+            editable = self.editable,
         );
-        query.execute(conn).await?;
-        Ok(())
+        query.execute(conn).await.map_err(Into::into)
     }
 
+    /// Returns the number of rows deleted
     /// # Errors
     /// Returns error if db query fails
-    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn delete(&self, pool: &PgPool) -> Result<u64, Error> {
         let query = query!(
             "DELETE FROM calendar_cache WHERE event_id=$event_id AND gcal_id=$gcal_id",
             event_id = self.event_id,
             gcal_id = self.gcal_id,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
-        Ok(())
+        query.execute(&conn).await.map_err(Into::into)
     }
 
     /// # Errors
@@ -442,6 +570,7 @@ impl CalendarCache {
         select_str: &'a str,
         order_str: &'a str,
         modified: Option<&'a OffsetDateTime>,
+        gcal_id: Option<&'a StackString>,
         offset: Option<usize>,
         limit: Option<usize>,
     ) -> Result<Query<'a>, PqError> {
@@ -451,6 +580,10 @@ impl CalendarCache {
             constraints.push(format_sstr!("last_modified > $modified"));
             bindings.push(("modified", modified as Parameter));
         }
+        if let Some(gcal_id) = gcal_id {
+            constraints.push(format_sstr!("gcal_id = $gcal_id"));
+            bindings.push(("gcal_id", gcal_id as Parameter));
+        }
         let where_str = if constraints.is_empty() {
             "".into()
         } else {
@@ -478,6 +611,7 @@ impl CalendarCache {
     pub async fn get_recent(
         pool: &PgPool,
         modified: Option<OffsetDateTime>,
+        gcal_id: Option<&StackString>,
         offset: Option<usize>,
         limit: Option<usize>,
     ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
@@ -485,6 +619,7 @@ impl CalendarCache {
             "*",
             "ORDER BY event_start_time",
             modified.as_ref(),
+            gcal_id,
             offset,
             limit,
         )?;
@@ -493,18 +628,43 @@ impl CalendarCache {
         query.fetch_streaming(&conn).await.map_err(Into::into)
     }
 
+    /// Most recently added or modified events, newest first
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_latest(
+        gcal_id: Option<&str>,
+        limit: usize,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let mut bindings = Vec::new();
+        let where_str = if let Some(gcal_id) = &gcal_id {
+            bindings.push(("gcal_id", gcal_id as Parameter));
+            "WHERE gcal_id = $gcal_id"
+        } else {
+            ""
+        };
+        let query = format_sstr!(
+            "SELECT * FROM calendar_cache {where_str} ORDER BY last_modified DESC LIMIT {limit}"
+        );
+        let query = query_dyn!(&query, ..bindings)?;
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
     /// # Errors
     /// Returns error if db query fails
     pub async fn get_total(
         pool: &PgPool,
         modified: Option<OffsetDateTime>,
+        gcal_id: Option<&StackString>,
     ) -> Result<usize, Error> {
         #[derive(FromSqlRow)]
         struct Count {
             count: i64,
         }
 
-        let query = Self::get_calendar_cache_query("count(*)", "", modified.as_ref(), None, None)?;
+        let query =
+            Self::get_calendar_cache_query("count(*)", "", modified.as_ref(), gcal_id, None, None)?;
 
         let conn = pool.get().await?;
         let count: Count = query.fetch_one(&conn).await?;
@@ -528,11 +688,13 @@ impl CalendarCache {
                 INSERT INTO calendar_cache (
                     gcal_id, event_id, event_start_time, event_end_time, event_url,
                     event_name, event_description, event_location_name,
-                    event_location_lat, event_location_lon, last_modified
+                    event_location_lat, event_location_lon, source_id, source_modified,
+                    editable, last_modified
                 ) VALUES (
                     $gcal_id, $event_id, $event_start_time, $event_end_time, $event_url,
                     $event_name, $event_description, $event_location_name,
-                    $event_location_lat, $event_location_lon, now()
+                    $event_location_lat, $event_location_lon, $source_id, $source_modified,
+                    $editable, now()
                 )
             "#,
             gcal_id = self.gcal_id,
@@ -545,6 +707,9 @@ impl CalendarCache {
             event_location_name = self.event_location_name,
             event_location_lat = self.event_location_lat,
             event_location_lon = self.event_location_lon,
+            source_id = self.source_id,
+            source_modified = self.source_modified,
+            editable = self.editable,
         );
         query.execute(conn).await?;
         Ok(())
@@ -567,6 +732,43 @@ impl CalendarCache {
         tran.commit().await?;
         Ok(())
     }
+
+    /// Like `upsert`, but only replaces an existing row if this version
+    /// `wins_over` it.  Returns the existing row if it was kept instead.
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn merge(&self, local_source_id: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let conn: &PgTransaction = &tran;
+        let query = query!(
+            r#"
+                SELECT * FROM calendar_cache
+                WHERE gcal_id=$gcal_id AND event_id=$event_id
+                FOR UPDATE
+            "#,
+            gcal_id = self.gcal_id,
+            event_id = self.event_id,
+        );
+        let existing: Option<Self> = query.fetch_opt(conn).await?;
+        // Store the clock the merge was decided on, `last_modified` is reset
+        // on write
+        let mut item = self.clone();
+        item.source_modified.get_or_insert(self.last_modified);
+        let kept = match existing {
+            Some(existing) if !self.wins_over(&existing, local_source_id) => Some(existing),
+            Some(_) => {
+                item.update_conn(conn).await?;
+                None
+            }
+            None => {
+                item.insert_conn(conn).await?;
+                None
+            }
+        };
+        tran.commit().await?;
+        Ok(kept)
+    }
 }
 
 #[derive(FromSqlRow, Clone, Debug)]
@@ -755,6 +957,790 @@ impl ShortenedLinks {
     }
 }
 
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize)]
+pub struct EventAttachment {
+    pub attachment_id: Uuid,
+    pub gcal_id: StackString,
+    pub event_id: StackString,
+    pub filename: StackString,
+    pub content_type: StackString,
+    pub size_bytes: i64,
+    pub storage_key: StackString,
+    pub created_at: DateTimeWrapper,
+    pub is_ticket: bool,
+    pub barcode_payload: Option<StackString>,
+}
+
+impl EventAttachment {
+    #[must_use]
+    pub fn new(
+        gcal_id: impl Into<StackString>,
+        event_id: impl Into<StackString>,
+        filename: impl Into<StackString>,
+        content_type: impl Into<StackString>,
+        size_bytes: usize,
+    ) -> Self {
+        let attachment_id = Uuid::new_v4();
+        Self {
+            attachment_id,
+            gcal_id: gcal_id.into(),
+            event_id: event_id.into(),
+            filename: filename.into(),
+            content_type: content_type.into(),
+            size_bytes: size_bytes as i64,
+            storage_key: StackString::from_display(attachment_id),
+            created_at: DateTimeWrapper::now(),
+            is_ticket: false,
+            barcode_payload: None,
+        }
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_id(attachment_id: Uuid, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM event_attachments WHERE attachment_id=$attachment_id",
+            attachment_id = attachment_id,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_gcal_id(gcal_id: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM event_attachments WHERE gcal_id=$gcal_id ORDER BY created_at",
+            gcal_id = gcal_id,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_gcal_id_event_id(
+        gcal_id: &str,
+        event_id: &str,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM event_attachments
+                WHERE gcal_id=$gcal_id AND event_id=$event_id
+                ORDER BY created_at
+            "#,
+            gcal_id = gcal_id,
+            event_id = event_id,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO event_attachments (
+                    attachment_id, gcal_id, event_id, filename, content_type,
+                    size_bytes, storage_key, created_at, is_ticket, barcode_payload
+                ) VALUES (
+                    $attachment_id, $gcal_id, $event_id, $filename, $content_type,
+                    $size_bytes, $storage_key, now(), $is_ticket, $barcode_payload
+                )
+            "#,
+            attachment_id = self.attachment_id,
+            gcal_id = self.gcal_id,
+            event_id = self.event_id,
+            filename = self.filename,
+            content_type = self.content_type,
+            size_bytes = self.size_bytes,
+            storage_key = self.storage_key,
+            is_ticket = self.is_ticket,
+            barcode_payload = self.barcode_payload,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "DELETE FROM event_attachments WHERE attachment_id=$attachment_id",
+            attachment_id = self.attachment_id,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+/// Token granting access without a login session, either to a public feed of
+/// one calendar or, for api keys, to the json api.  A `gcal_id` of `None`
+/// (api keys only) means all calendars.  After rotation the previous token
+/// is still accepted until `previous_expires_at`.  Only a hash of the token
+/// is stored, the token itself is handed out once on creation or rotation.
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct AccessToken {
+    pub token_hash: StackString,
+    pub email: StackString,
+    pub gcal_id: Option<StackString>,
+    pub description: Option<StackString>,
+    pub request_count: i64,
+    pub rejected_count: i64,
+    pub last_used_at: Option<DateTimeWrapper>,
+    pub created_at: DateTimeWrapper,
+    pub is_api_key: bool,
+    pub read_only: bool,
+    pub expires_at: Option<DateTimeWrapper>,
+    pub previous_token_hash: Option<StackString>,
+    pub previous_expires_at: Option<DateTimeWrapper>,
+}
+
+fn generate_token() -> StackString {
+    format_sstr!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Hex encoded sha256 of an access token, as stored in `access_tokens`
+#[must_use]
+pub fn hash_token(token: &str) -> StackString {
+    hex::encode(Sha256::digest(token.as_bytes())).into()
+}
+
+impl AccessToken {
+    /// Returns the new feed token along with the token itself, which isn't
+    /// stored
+    #[must_use]
+    pub fn new_feed(
+        email: impl Into<StackString>,
+        gcal_id: impl Into<StackString>,
+        description: Option<StackString>,
+    ) -> (Self, StackString) {
+        let token = generate_token();
+        let access_token = Self {
+            token_hash: hash_token(&token),
+            email: email.into(),
+            gcal_id: Some(gcal_id.into()),
+            description,
+            request_count: 0,
+            rejected_count: 0,
+            last_used_at: None,
+            created_at: DateTimeWrapper::now(),
+            is_api_key: false,
+            read_only: true,
+            expires_at: None,
+            previous_token_hash: None,
+            previous_expires_at: None,
+        };
+        (access_token, token)
+    }
+
+    /// Returns the new api key along with the key itself, which isn't stored
+    #[must_use]
+    pub fn new_api_key(
+        email: impl Into<StackString>,
+        gcal_id: Option<StackString>,
+        read_only: bool,
+        description: Option<StackString>,
+    ) -> (Self, StackString) {
+        let (feed, token) = Self::new_feed(email, "", description);
+        let access_token = Self {
+            is_api_key: true,
+            gcal_id,
+            read_only,
+            ..feed
+        };
+        (access_token, token)
+    }
+
+    /// Whether `token` (current, or previous within the grace period) is
+    /// accepted at `now`
+    #[must_use]
+    pub fn accepts(&self, token: &str, now: OffsetDateTime) -> bool {
+        self.accepts_hash(&hash_token(token), now)
+    }
+
+    /// Same as `accepts` for an already hashed token
+    #[must_use]
+    pub fn accepts_hash(&self, token_hash: &str, now: OffsetDateTime) -> bool {
+        if let Some(expires_at) = &self.expires_at {
+            if **expires_at <= now {
+                return false;
+            }
+        }
+        if self.token_hash.as_str() == token_hash {
+            return true;
+        }
+        match (&self.previous_token_hash, &self.previous_expires_at) {
+            (Some(previous), Some(previous_expires_at)) => {
+                previous.as_str() == token_hash && now < **previous_expires_at
+            }
+            _ => false,
+        }
+    }
+
+    #[must_use]
+    pub fn allows_calendar(&self, gcal_id: &str) -> bool {
+        self.gcal_id
+            .as_ref()
+            .map_or(true, |g| g.as_str() == gcal_id)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_token(token: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        Self::get_by_token_hash(&hash_token(token), pool).await
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_token_hash(token_hash: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM access_tokens
+                WHERE token_hash=$token_hash
+                   OR (previous_token_hash=$token_hash AND previous_expires_at > now())
+            "#,
+            token_hash = token_hash
+        );
+        let conn = pool.get().await?;
+        let token_row: Option<Self> = query.fetch_opt(&conn).await?;
+        Ok(token_row.filter(|t| t.accepts_hash(token_hash, OffsetDateTime::now_utc())))
+    }
+
+    /// Token of `email` with `token_hash` for managing it, unlike
+    /// `get_by_token_hash` expired tokens are returned so they can still be
+    /// rotated or deleted.
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_owned(
+        token_hash: &str,
+        email: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM access_tokens WHERE token_hash=$token_hash AND email=$email",
+            token_hash = token_hash,
+            email = email,
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_email(email: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM access_tokens WHERE email=$email ORDER BY created_at",
+            email = email,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_api_keys(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM access_tokens
+                WHERE is_api_key AND (expires_at IS NULL OR expires_at > now())
+            "#
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO access_tokens (
+                    token_hash, email, gcal_id, description, created_at, is_api_key,
+                    read_only, expires_at
+                ) VALUES (
+                    $token_hash, $email, $gcal_id, $description, now(), $is_api_key,
+                    $read_only, $expires_at
+                )
+            "#,
+            token_hash = self.token_hash,
+            email = self.email,
+            gcal_id = self.gcal_id,
+            description = self.description,
+            is_api_key = self.is_api_key,
+            read_only = self.read_only,
+            expires_at = self.expires_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// Replace the token with a new one, which is returned, the current token
+    /// keeps working for `grace_period`.
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn rotate(
+        &mut self,
+        grace_period: Duration,
+        pool: &PgPool,
+    ) -> Result<StackString, Error> {
+        let token = generate_token();
+        let old_token_hash = std::mem::replace(&mut self.token_hash, hash_token(&token));
+        let previous_expires_at: DateTimeWrapper =
+            (OffsetDateTime::now_utc() + grace_period).into();
+        let query = query!(
+            r#"
+                UPDATE access_tokens
+                SET token_hash=$token_hash,previous_token_hash=$previous_token_hash,
+                    previous_expires_at=$previous_expires_at
+                WHERE token_hash=$previous_token_hash
+            "#,
+            token_hash = self.token_hash,
+            previous_token_hash = old_token_hash,
+            previous_expires_at = previous_expires_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        self.previous_token_hash = Some(old_token_hash);
+        self.previous_expires_at = Some(previous_expires_at);
+        Ok(token)
+    }
+
+    /// Add batched usage counts to the token (current or previous) with
+    /// `token_hash`, `rejected` requests were turned away by the rate
+    /// limiter.
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn add_usage(
+        token_hash: &str,
+        requests: i64,
+        rejected: i64,
+        last_used_at: OffsetDateTime,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE access_tokens
+                SET request_count=request_count+$requests,
+                    rejected_count=rejected_count+$rejected,
+                    last_used_at=GREATEST(last_used_at, $last_used_at)
+                WHERE token_hash=$token_hash OR previous_token_hash=$token_hash
+            "#,
+            token_hash = token_hash,
+            requests = requests,
+            rejected = rejected,
+            last_used_at = last_used_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "DELETE FROM access_tokens WHERE token_hash=$token_hash",
+            token_hash = self.token_hash
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+/// External producer (CI pipeline, home automation, ...) allowed to post
+/// events into `gcal_id` through `/calendar/inbound/{source_token}`.
+/// `required_fields` and `max_duration_minutes` are checked against every
+/// payload.
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct InboundSource {
+    pub source_token: StackString,
+    pub source_name: StackString,
+    pub email: StackString,
+    pub gcal_id: StackString,
+    pub required_fields: Vec<StackString>,
+    pub max_duration_minutes: Option<i32>,
+    pub rate_limit_per_minute: i32,
+    pub request_count: i64,
+    pub rejected_count: i64,
+    pub last_used_at: Option<DateTimeWrapper>,
+    pub created_at: DateTimeWrapper,
+}
+
+impl InboundSource {
+    #[must_use]
+    pub fn new(
+        source_name: impl Into<StackString>,
+        email: impl Into<StackString>,
+        gcal_id: impl Into<StackString>,
+    ) -> Self {
+        Self {
+            source_token: generate_token(),
+            source_name: source_name.into(),
+            email: email.into(),
+            gcal_id: gcal_id.into(),
+            required_fields: Vec::new(),
+            max_duration_minutes: None,
+            rate_limit_per_minute: 10,
+            request_count: 0,
+            rejected_count: 0,
+            last_used_at: None,
+            created_at: DateTimeWrapper::now(),
+        }
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_token(source_token: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM inbound_sources WHERE source_token=$source_token",
+            source_token = source_token
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_by_email(email: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM inbound_sources WHERE email=$email ORDER BY created_at",
+            email = email,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO inbound_sources (
+                    source_token, source_name, email, gcal_id, required_fields,
+                    max_duration_minutes, rate_limit_per_minute, created_at
+                ) VALUES (
+                    $source_token, $source_name, $email, $gcal_id, $required_fields,
+                    $max_duration_minutes, $rate_limit_per_minute, now()
+                )
+            "#,
+            source_token = self.source_token,
+            source_name = self.source_name,
+            email = self.email,
+            gcal_id = self.gcal_id,
+            required_fields = self.required_fields,
+            max_duration_minutes = self.max_duration_minutes,
+            rate_limit_per_minute = self.rate_limit_per_minute,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// Add batched usage counts to the source with `source_token`,
+    /// `rejected` requests failed validation.
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn add_usage(
+        source_token: &str,
+        requests: i64,
+        rejected: i64,
+        last_used_at: OffsetDateTime,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE inbound_sources
+                SET request_count=request_count+$requests,
+                    rejected_count=rejected_count+$rejected,
+                    last_used_at=GREATEST(last_used_at, $last_used_at)
+                WHERE source_token=$source_token
+            "#,
+            source_token = source_token,
+            requests = requests,
+            rejected = rejected,
+            last_used_at = last_used_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "DELETE FROM inbound_sources WHERE source_token=$source_token",
+            source_token = self.source_token
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+}
+
+/// One run of `CalendarSync::run_syncing`, the last successful run tells
+/// the CLI how stale the local cache is.  Rows with a `gcal_id` are full
+/// syncs of a single calendar, scheduled for `scheduled_for` because of
+/// `reason`, which stay pending until `finished_at` is set.
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize)]
+pub struct SyncHistory {
+    pub sync_id: Uuid,
+    pub full_sync: bool,
+    pub success: bool,
+    pub summary: Option<StackString>,
+    pub started_at: DateTimeWrapper,
+    pub finished_at: Option<DateTimeWrapper>,
+    pub gcal_id: Option<StackString>,
+    pub reason: Option<StackString>,
+    pub scheduled_for: Option<DateTimeWrapper>,
+}
+
+impl SyncHistory {
+    #[must_use]
+    pub fn new(full_sync: bool) -> Self {
+        Self {
+            sync_id: Uuid::new_v4(),
+            full_sync,
+            success: false,
+            summary: None,
+            started_at: DateTimeWrapper::now(),
+            finished_at: None,
+            gcal_id: None,
+            reason: None,
+            scheduled_for: None,
+        }
+    }
+
+    #[must_use]
+    pub fn schedule_full(
+        gcal_id: impl Into<StackString>,
+        reason: impl Into<StackString>,
+        scheduled_for: OffsetDateTime,
+    ) -> Self {
+        Self {
+            gcal_id: Some(gcal_id.into()),
+            reason: Some(reason.into()),
+            scheduled_for: Some(scheduled_for.into()),
+            ..Self::new(true)
+        }
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO sync_history (
+                    sync_id, full_sync, started_at, gcal_id, reason, scheduled_for
+                ) VALUES (
+                    $sync_id, $full_sync, $started_at, $gcal_id, $reason, $scheduled_for
+                )
+            "#,
+            sync_id = self.sync_id,
+            full_sync = self.full_sync,
+            started_at = self.started_at,
+            gcal_id = self.gcal_id,
+            reason = self.reason,
+            scheduled_for = self.scheduled_for,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn finish(
+        &mut self,
+        success: bool,
+        summary: Option<StackString>,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        self.success = success;
+        self.summary = summary;
+        self.finished_at = Some(DateTimeWrapper::now());
+        let query = query!(
+            r#"
+                UPDATE sync_history
+                SET success=$success,summary=$summary,finished_at=$finished_at
+                WHERE sync_id=$sync_id
+            "#,
+            sync_id = self.sync_id,
+            success = self.success,
+            summary = self.summary,
+            finished_at = self.finished_at,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_last_success(pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM sync_history
+                WHERE success AND finished_at IS NOT NULL AND gcal_id IS NULL
+                ORDER BY finished_at DESC
+                LIMIT 1
+            "#
+        );
+        let conn = pool.get().await?;
+        query.fetch_opt(&conn).await.map_err(Into::into)
+    }
+
+    /// Scheduled single calendar syncs that haven't run yet
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_pending(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM sync_history
+                WHERE scheduled_for IS NOT NULL AND finished_at IS NULL
+                ORDER BY scheduled_for
+            "#
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// Successful incremental syncs since the last full sync covering
+    /// `gcal_id`
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn count_incremental_since_full(
+        gcal_id: &str,
+        pool: &PgPool,
+    ) -> Result<usize, Error> {
+        #[derive(FromSqlRow)]
+        struct Count {
+            count: i64,
+        }
+
+        let query = query!(
+            r#"
+                SELECT count(*) FROM sync_history
+                WHERE NOT full_sync AND success AND gcal_id IS NULL
+                AND started_at > COALESCE(
+                    (
+                        SELECT max(finished_at) FROM sync_history
+                        WHERE full_sync AND success
+                        AND (gcal_id IS NULL OR gcal_id = $gcal_id)
+                    ),
+                    '-infinity'
+                )
+            "#,
+            gcal_id = gcal_id,
+        );
+        let conn = pool.get().await?;
+        let count: Count = query.fetch_one(&conn).await?;
+        Ok(count.count.try_into()?)
+    }
+}
+
+/// One run of a scraper (hashnyc, nycruns), `alert` is set when the run
+/// looks broken compared to earlier runs of the same source.
+#[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScrapeRun {
+    pub run_id: Uuid,
+    pub source: StackString,
+    pub started_at: DateTimeWrapper,
+    pub events_found: i32,
+    pub events_new: i32,
+    pub events_updated: i32,
+    pub parse_failures: i32,
+    pub error: Option<StackString>,
+    pub alert: Option<StackString>,
+}
+
+impl ScrapeRun {
+    #[must_use]
+    pub fn new(source: impl Into<StackString>) -> Self {
+        Self {
+            run_id: Uuid::new_v4(),
+            source: source.into(),
+            started_at: DateTimeWrapper::now(),
+            events_found: 0,
+            events_new: 0,
+            events_updated: 0,
+            parse_failures: 0,
+            error: None,
+            alert: None,
+        }
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO scrape_runs (
+                    run_id, source, started_at, events_found, events_new,
+                    events_updated, parse_failures, error, alert
+                ) VALUES (
+                    $run_id, $source, $started_at, $events_found, $events_new,
+                    $events_updated, $parse_failures, $error, $alert
+                )
+            "#,
+            run_id = self.run_id,
+            source = self.source,
+            started_at = self.started_at,
+            events_found = self.events_found,
+            events_new = self.events_new,
+            events_updated = self.events_updated,
+            parse_failures = self.parse_failures,
+            error = self.error,
+            alert = self.alert,
+        );
+        let conn = pool.get().await?;
+        query.execute(&conn).await?;
+        Ok(())
+    }
+
+    /// Most recent successful runs of `source`, newest first.  Runs that
+    /// raised an alert are left out so a broken scrape doesn't become the
+    /// baseline for the next one.
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_latest_successful(
+        source: &str,
+        limit: usize,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = format_sstr!(
+            "SELECT * FROM scrape_runs WHERE source=$source AND error IS NULL AND alert IS NULL \
+             ORDER BY started_at DESC LIMIT {limit}"
+        );
+        let query = query_dyn!(&query, source = source)?;
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+
+    /// All runs since `min_time`, oldest first
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_since(min_time: OffsetDateTime, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM scrape_runs
+                WHERE started_at >= $min_time
+                ORDER BY started_at
+            "#,
+            min_time = min_time,
+        );
+        let conn = pool.get().await?;
+        query.fetch(&conn).await.map_err(Into::into)
+    }
+}
+
 fn write_hex_output(mut output: blake3::OutputReader, mut len: u64) -> StackString {
     // Encoding multiples of the block size is most efficient.
     let mut block = [0; blake3::guts::BLOCK_LEN];
@@ -794,3 +1780,51 @@ fn hash_reader(
     copy_wide(reader, &mut hasher)?;
     Ok(hasher.finalize_xof())
 }
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+
+    use crate::{calendar::Event, models::CalendarCache};
+
+    #[test]
+    fn test_calendar_cache_wins_over() {
+        let start = datetime!(2024-06-01 12:00:00 UTC);
+        let mut local: CalendarCache = Event::new(
+            "cal@example.com",
+            "Lunch",
+            start,
+            start + Duration::hours(1),
+        )
+        .into();
+        local.last_modified = start.into();
+
+        let mut remote = local.clone();
+        remote.event_name = "Late Lunch".into();
+        remote.source_id = Some("b.example.com".into());
+        remote.source_modified = Some((start + Duration::minutes(5)).into());
+        let local_id = "c.example.com";
+        assert!(remote.wins_over(&local, local_id));
+        assert!(!local.wins_over(&remote, local_id));
+
+        // Same edit time, the larger source id wins on every deployment
+        let mut other = remote.clone();
+        other.event_name = "Early Lunch".into();
+        other.source_id = Some("a.example.com".into());
+        assert!(remote.wins_over(&other, local_id));
+        assert!(!other.wins_over(&remote, local_id));
+
+        // Local rows tie break on the configured source id, as they would
+        // once exported
+        local.source_modified = remote.source_modified;
+        assert!(local.wins_over(&remote, local_id));
+        assert!(!remote.wins_over(&local, local_id));
+        assert!(remote.wins_over(&local, "a.example.com"));
+
+        // last_modified is reset on write, source_modified takes precedence
+        other.last_modified = (start + Duration::hours(1)).into();
+        assert!(remote.wins_over(&other, local_id));
+
+        assert!(remote.wins_over(&remote.clone(), local_id));
+    }
+}
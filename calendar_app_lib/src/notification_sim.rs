@@ -0,0 +1,242 @@
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashSet, fmt};
+use time::{macros::format_description, Date, Duration, OffsetDateTime, Time};
+use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt};
+
+use crate::{models::CalendarCache, timezone::TimeZone};
+
+/// Minutes before an event starts that the telegram bot reminds about it
+pub const REMINDER_MINUTES: i64 = 5;
+/// Hour (UTC) the telegram bot sends the daily agenda
+pub const AGENDA_HOUR: u8 = 12;
+
+/// When the telegram bot sends the agenda on `date`
+#[must_use]
+pub fn agenda_time(date: Date) -> OffsetDateTime {
+    date.with_time(Time::from_hms(AGENDA_HOUR, 0, 0).unwrap_or(Time::MIDNIGHT))
+        .assume_utc()
+}
+
+/// Whether the agenda loaded at `loaded_at` contains `event`, mirrors
+/// `CalendarSync::list_agenda(0, 1)`
+fn in_agenda(event: &CalendarCache, loaded_at: OffsetDateTime) -> bool {
+    *event.event_end_time >= loaded_at && *event.event_start_time <= loaded_at + Duration::days(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    Agenda,
+    Reminder,
+    Ticket,
+    TrayReminder,
+}
+
+impl fmt::Display for NotificationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Agenda => f.write_str("agenda"),
+            Self::Reminder => f.write_str("reminder"),
+            Self::Ticket => f.write_str("ticket"),
+            Self::TrayReminder => f.write_str("tray reminder"),
+        }
+    }
+}
+
+/// A notification that would have fired (or been suppressed, with the
+/// reason in `note`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulatedNotification {
+    pub at: OffsetDateTime,
+    pub kind: NotificationKind,
+    pub gcal_id: StackString,
+    pub event_id: StackString,
+    pub event_name: StackString,
+    pub sent: bool,
+    pub note: Option<StackString>,
+}
+
+impl SimulatedNotification {
+    fn new(at: OffsetDateTime, kind: NotificationKind, event: &CalendarCache) -> Self {
+        Self {
+            at,
+            kind,
+            gcal_id: event.gcal_id.clone(),
+            event_id: event.event_id.clone(),
+            event_name: event.event_name.clone(),
+            sent: true,
+            note: None,
+        }
+    }
+
+    fn skip(mut self, note: impl Into<StackString>) -> Self {
+        self.sent = false;
+        self.note = Some(note.into());
+        self
+    }
+
+    /// One line report, times shown in `timezone`
+    #[must_use]
+    pub fn summary(&self, timezone: TimeZone) -> StackString {
+        let at = notification_time(self.at, timezone);
+        let status = if self.sent { "sent" } else { "skipped" };
+        let mut line = format_sstr!(
+            "{at} [{status}] {} {} ({} {})",
+            self.kind,
+            self.event_name,
+            self.gcal_id,
+            self.event_id
+        );
+        if let Some(note) = &self.note {
+            line = format_sstr!("{line}: {note}");
+        }
+        line
+    }
+}
+
+/// Replay the telegram bot and tray reminder rules for `date` (in
+/// `timezone`), assuming both ran all day.  `events` should cover at least
+/// the day before and after `date`, `displayed` holds the calendars shown in
+/// the agenda and `tickets` the events with ticket attachments.
+#[must_use]
+pub fn simulate_notifications(
+    date: Date,
+    timezone: TimeZone,
+    events: &[CalendarCache],
+    displayed: &HashSet<StackString>,
+    tickets: &HashSet<(StackString, StackString)>,
+    tray_reminder: Duration,
+) -> Vec<SimulatedNotification> {
+    let day_start = date
+        .midnight()
+        .assume_timezone(timezone.into())
+        .take()
+        .unwrap_or_else(|| date.midnight().assume_utc());
+    let day_end = day_start + Duration::days(1);
+    let in_day = |t: OffsetDateTime| t >= day_start && t < day_end;
+    let agenda_times = (-1..=1)
+        .filter_map(|offset| date.checked_add(Duration::days(offset)))
+        .map(agenda_time);
+
+    let mut output = Vec::new();
+    for loaded_at in agenda_times.filter(|t| in_day(*t)) {
+        for event in events.iter().filter(|e| in_agenda(e, loaded_at)) {
+            let notification =
+                SimulatedNotification::new(loaded_at, NotificationKind::Agenda, event);
+            output.push(if displayed.contains(&event.gcal_id) {
+                notification
+            } else {
+                notification.skip("calendar not displayed")
+            });
+        }
+    }
+
+    for event in events {
+        let start_time = *event.event_start_time;
+
+        // The bot checks the current agenda every minute, so every event is
+        // reminded about (once) however late it was added or moved
+        let reminder_at = start_time - Duration::minutes(REMINDER_MINUTES);
+        if in_day(reminder_at) {
+            let notification =
+                SimulatedNotification::new(reminder_at, NotificationKind::Reminder, event);
+            let notification = if displayed.contains(&event.gcal_id) {
+                notification
+            } else {
+                notification.skip("calendar not displayed")
+            };
+            let key = (event.gcal_id.clone(), event.event_id.clone());
+            let send_tickets = notification.sent && tickets.contains(&key);
+            output.push(notification);
+            if send_tickets {
+                output.push(SimulatedNotification::new(
+                    reminder_at,
+                    NotificationKind::Ticket,
+                    event,
+                ));
+            }
+        }
+
+        let tray_at = start_time - tray_reminder;
+        if in_day(tray_at) {
+            let notification =
+                SimulatedNotification::new(tray_at, NotificationKind::TrayReminder, event);
+            output.push(if displayed.contains(&event.gcal_id) {
+                notification
+            } else {
+                notification.skip("calendar not displayed")
+            });
+        }
+    }
+    output.sort_by_key(|n| (n.at, n.kind != NotificationKind::Agenda));
+    output
+}
+
+fn notification_time(t: OffsetDateTime, timezone: TimeZone) -> StackString {
+    t.to_timezone(timezone.into())
+        .format(format_description!("[hour]:[minute]"))
+        .map_or_else(|_| StackString::new(), Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::{format_sstr, StackString};
+    use std::collections::HashSet;
+    use time::{macros::datetime, Duration, OffsetDateTime};
+
+    use crate::{
+        calendar::Event,
+        models::CalendarCache,
+        notification_sim::{simulate_notifications, NotificationKind},
+        timezone::TimeZone,
+    };
+
+    fn event(gcal_id: &str, name: &str, start: OffsetDateTime) -> CalendarCache {
+        let mut event: CalendarCache =
+            Event::new(gcal_id, name, start, start + Duration::hours(1)).into();
+        event.last_modified = (start - Duration::days(7)).into();
+        event
+    }
+
+    #[test]
+    fn test_simulate_notifications() {
+        let utc = TimeZone::utc();
+        let date = datetime!(2024-07-01 00:00:00 UTC).date();
+        let run = event("shown", "Run", datetime!(2024-07-01 18:00:00 UTC));
+        let hidden = event("hidden", "Hidden", datetime!(2024-07-01 19:00:00 UTC));
+        let mut late = event("shown", "Late Add", datetime!(2024-07-01 20:00:00 UTC));
+        late.last_modified = datetime!(2024-07-01 13:00:00 UTC).into();
+
+        let displayed: HashSet<StackString> = ["shown".into()].into_iter().collect();
+        let tickets: HashSet<_> = [(run.gcal_id.clone(), run.event_id.clone())]
+            .into_iter()
+            .collect();
+        let events = vec![run.clone(), hidden, late.clone()];
+        let notifications = simulate_notifications(
+            date,
+            utc,
+            &events,
+            &displayed,
+            &tickets,
+            Duration::minutes(15),
+        );
+        let lines: Vec<_> = notifications.iter().map(|n| n.summary(utc)).collect();
+
+        let count = |kind| notifications.iter().filter(|n| n.kind == kind).count();
+        assert_eq!(count(NotificationKind::Agenda), 3);
+        assert_eq!(count(NotificationKind::Reminder), 3);
+        assert_eq!(count(NotificationKind::Ticket), 1);
+        assert_eq!(count(NotificationKind::TrayReminder), 3);
+        assert!(lines.contains(&format_sstr!(
+            "17:55 [sent] reminder Run (shown {})",
+            run.event_id
+        )));
+        assert!(lines.contains(&format_sstr!(
+            "19:55 [sent] reminder Late Add (shown {})",
+            late.event_id
+        )));
+        assert!(lines
+            .iter()
+            .any(|l| l.starts_with("18:55 [skipped] reminder Hidden")
+                && l.ends_with("calendar not displayed")));
+    }
+}
@@ -11,15 +11,24 @@ use crate::{
     calendar::{Event, Location},
     models::CalendarCache,
     pgpool::PgPool,
+    scrape_health::ScrapeCounts,
 };
 
 const CALID: &str = "8hfjg0d8ls2od3s9bd1k1v9jtc@group.calendar.google.com";
-const URL: &str = "https://hashnyc.com/?days=all";
+pub(crate) const URL: &str = "https://hashnyc.com/?days=all";
 
 /// # Errors
 /// Return error if parsing datetime string fails
 pub fn parse_hashnyc_text(body: &str) -> Result<Vec<Event>, Error> {
+    parse_hashnyc_rows(body).map(|(events, _)| events)
+}
+
+/// Like `parse_hashnyc_text`, also counting entries that couldn't be parsed
+/// # Errors
+/// Return error if parsing datetime fails
+pub fn parse_hashnyc_rows(body: &str) -> Result<(Vec<Event>, usize), Error> {
     let mut events = Vec::new();
+    let mut parse_failures = 0;
     for table in Document::from(body).find(Name("table")) {
         if table.attr("class") != Some("future_hashes") {
             continue;
@@ -29,7 +38,9 @@ pub fn parse_hashnyc_text(body: &str) -> Result<Vec<Event>, Error> {
             let mut name = None;
             let mut description = None;
             let mut location = None;
+            let mut has_cells = false;
             for td in tr.find(Name("td")) {
+                has_cells = true;
                 let mut year = None;
                 for a in td.find(Name("a")) {
                     if let Some(id) = a.attr("id") {
@@ -76,6 +87,10 @@ pub fn parse_hashnyc_text(body: &str) -> Result<Vec<Event>, Error> {
                     }
                 }
             }
+            // Header rows have no cells, anything else should be an event
+            if has_cells && (name.is_none() || start_time.is_none()) {
+                parse_failures += 1;
+            }
             if let Some(name) = name {
                 if let Some(start_time) = start_time {
                     let end_time = start_time + Duration::hours(1);
@@ -95,13 +110,13 @@ pub fn parse_hashnyc_text(body: &str) -> Result<Vec<Event>, Error> {
         }
     }
 
-    Ok(events)
+    Ok((events, parse_failures))
 }
 
 /// # Errors
 /// Return error if `get_by_gcal_id` fails, reqwest call fals,
 /// `parse_nycruns_text` fails, or any db update fails.
-pub async fn parse_hashnyc(pool: &PgPool) -> Result<Vec<CalendarCache>, Error> {
+pub async fn parse_hashnyc(pool: &PgPool) -> Result<(Vec<CalendarCache>, ScrapeCounts), Error> {
     let current_event_map: HashMap<_, _> = CalendarCache::get_by_gcal_id(CALID, pool)
         .await?
         .map_ok(|event| {
@@ -114,7 +129,9 @@ pub async fn parse_hashnyc(pool: &PgPool) -> Result<Vec<CalendarCache>, Error> {
 
     let body = reqwest::get(URL).await?.text().await?;
 
-    let futures = parse_hashnyc_text(&body)?.into_iter().map(|event| {
+    let (events, parse_failures) = parse_hashnyc_rows(&body)?;
+    let found = events.len();
+    let futures = events.into_iter().map(|event| {
         let current_event_map = current_event_map.clone();
         async move {
             let mut event: CalendarCache = event.into();
@@ -127,18 +144,29 @@ pub async fn parse_hashnyc(pool: &PgPool) -> Result<Vec<CalendarCache>, Error> {
                 {
                     event.event_id = existing_event.event_id.as_str().into();
                     event.upsert(pool).await?;
-                    Ok(Some(event))
+                    Ok(Some((event, false)))
                 } else {
                     Ok(None)
                 }
             } else {
                 event.insert(pool).await?;
-                Ok(Some(event))
+                Ok(Some((event, true)))
             }
         }
     });
-    let new_events: Result<Vec<_>, Error> = try_join_all(futures).await;
-    Ok(new_events?.into_iter().flatten().collect())
+    let changed: Result<Vec<_>, Error> = try_join_all(futures).await;
+    let changed: Vec<_> = changed?.into_iter().flatten().collect();
+    let new = changed.iter().filter(|(_, is_new)| *is_new).count();
+    let counts = ScrapeCounts {
+        found,
+        new,
+        updated: changed.len() - new,
+        parse_failures,
+    };
+    Ok((
+        changed.into_iter().map(|(event, _)| event).collect(),
+        counts,
+    ))
 }
 
 #[cfg(test)]
@@ -13,16 +13,25 @@ use crate::{
     calendar::{Event, Location},
     models::CalendarCache,
     pgpool::PgPool,
+    scrape_health::ScrapeCounts,
 };
 
 const CALID: &str = "ufdpqtvophgg2qn643rducu1a4@group.calendar.google.com";
 const BASE_URL: &str = "https://nycruns.com";
-const URL: &str = "https://nycruns.com/races/?show=registerable";
+pub(crate) const URL: &str = "https://nycruns.com/races/?show=registerable";
 
 /// # Errors
 /// Return error if parsing datetime fails
 pub fn parse_nycruns_text(body: &str) -> Result<Vec<Event>, Error> {
+    parse_nycruns_rows(body).map(|(events, _)| events)
+}
+
+/// Like `parse_nycruns_text`, also counting entries that couldn't be parsed
+/// # Errors
+/// Return error if parsing datetime fails
+pub fn parse_nycruns_rows(body: &str) -> Result<(Vec<Event>, usize), Error> {
     let mut events = Vec::new();
+    let mut parse_failures = 0;
     for race in Document::from(body).find(Class("_race")) {
         let mut current_date = None;
         let mut current_time = None;
@@ -73,6 +82,9 @@ pub fn parse_nycruns_text(body: &str) -> Result<Vec<Event>, Error> {
                 }
             }
         }
+        if name.is_none() || current_date.is_none() || current_time.is_none() {
+            parse_failures += 1;
+        }
         if let Some(name) = name {
             if let Some(current_date) = current_date {
                 if let Some(current_time) = current_time {
@@ -92,13 +104,13 @@ pub fn parse_nycruns_text(body: &str) -> Result<Vec<Event>, Error> {
             }
         }
     }
-    Ok(events)
+    Ok((events, parse_failures))
 }
 
 /// # Errors
 /// Return error if `get_by_gcal_id` fails, reqwest call fals,
 /// `parse_nycruns_text` fails, or any db update fails.
-pub async fn parse_nycruns(pool: &PgPool) -> Result<Vec<CalendarCache>, Error> {
+pub async fn parse_nycruns(pool: &PgPool) -> Result<(Vec<CalendarCache>, ScrapeCounts), Error> {
     let current_event_map: HashMap<_, _> = CalendarCache::get_by_gcal_id(CALID, pool)
         .await?
         .map_ok(|event| {
@@ -110,7 +122,9 @@ pub async fn parse_nycruns(pool: &PgPool) -> Result<Vec<CalendarCache>, Error> {
     let current_event_map = Arc::new(current_event_map);
     let body = reqwest::get(URL).await?.text().await?;
 
-    let futures = parse_nycruns_text(&body)?.into_iter().map(|event| {
+    let (events, parse_failures) = parse_nycruns_rows(&body)?;
+    let found = events.len();
+    let futures = events.into_iter().map(|event| {
         let current_event_map = current_event_map.clone();
         async move {
             let mut event: CalendarCache = event.into();
@@ -123,18 +137,29 @@ pub async fn parse_nycruns(pool: &PgPool) -> Result<Vec<CalendarCache>, Error> {
                     event.event_id = existing_event.event_id.as_str().into();
                     debug!("modifying event {:#?} {:#?}", event, existing_event);
                     event.upsert(pool).await?;
-                    Ok(Some(event))
+                    Ok(Some((event, false)))
                 } else {
                     Ok(None)
                 }
             } else {
                 event.insert(pool).await?;
-                Ok(Some(event))
+                Ok(Some((event, true)))
             }
         }
     });
-    let new_events: Result<Vec<_>, Error> = try_join_all(futures).await;
-    Ok(new_events?.into_iter().flatten().collect())
+    let changed: Result<Vec<_>, Error> = try_join_all(futures).await;
+    let changed: Vec<_> = changed?.into_iter().flatten().collect();
+    let new = changed.iter().filter(|(_, is_new)| *is_new).count();
+    let counts = ScrapeCounts {
+        found,
+        new,
+        updated: changed.len() - new,
+        parse_failures,
+    };
+    Ok((
+        changed.into_iter().map(|(event, _)| event).collect(),
+        counts,
+    ))
 }
 
 #[cfg(test)]
@@ -0,0 +1,91 @@
+use anyhow::{format_err, Error};
+use reqwest::{header::AUTHORIZATION, Client, RequestBuilder};
+use stack_string::{format_sstr, StackString};
+
+use crate::{calendar::AgendaEvent, config::Config, stats::CalendarStats};
+
+/// Client for the json api of a running deployment, authenticated with an
+/// api key as `Authorization: Bearer <key>`.
+pub struct RemoteClient {
+    client: Client,
+    base_url: StackString,
+    api_key: StackString,
+}
+
+impl RemoteClient {
+    /// `url` and `api_key` override `REMOTE_URL` and `REMOTE_API_KEY`
+    /// # Errors
+    /// Returns error if no url or api key is configured
+    pub fn new(
+        config: &Config,
+        url: Option<StackString>,
+        api_key: Option<StackString>,
+    ) -> Result<Self, Error> {
+        let base_url = url
+            .or_else(|| config.remote_url.clone())
+            .ok_or_else(|| format_err!("REMOTE_URL not set"))?;
+        let api_key = api_key
+            .or_else(|| config.remote_api_key.clone())
+            .ok_or_else(|| format_err!("REMOTE_API_KEY not set"))?;
+        Ok(Self {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').into(),
+            api_key,
+        })
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        request.header(
+            AUTHORIZATION,
+            format_sstr!("Bearer {}", self.api_key).as_str(),
+        )
+    }
+
+    /// # Errors
+    /// Returns error if the request fails
+    pub async fn agenda(&self) -> Result<Vec<AgendaEvent>, Error> {
+        let url = format_sstr!("{}/calendar/agenda.json?description=none", self.base_url);
+        let events = self
+            .authorized(self.client.get(url.as_str()))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(events)
+    }
+
+    /// Run a sync on the remote, returns its output
+    /// # Errors
+    /// Returns error if the request fails
+    pub async fn sync(&self, full: bool) -> Result<Vec<StackString>, Error> {
+        let path = if full {
+            "sync_calendars_full"
+        } else {
+            "sync_calendars"
+        };
+        let url = format_sstr!("{}/calendar/{path}", self.base_url);
+        let body = self
+            .authorized(self.client.post(url.as_str()))
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        Ok(body.split("<br>").map(Into::into).collect())
+    }
+
+    /// # Errors
+    /// Returns error if the request fails
+    pub async fn stats(&self) -> Result<CalendarStats, Error> {
+        let url = format_sstr!("{}/calendar/stats", self.base_url);
+        let stats = self
+            .authorized(self.client.get(url.as_str()))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(stats)
+    }
+}
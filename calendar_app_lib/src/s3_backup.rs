@@ -0,0 +1,220 @@
+use anyhow::{format_err, Error};
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::{
+    config::{Builder as S3ConfigBuilder, Credentials, Region},
+    primitives::ByteStream,
+    Client as S3Client,
+};
+use stack_string::{format_sstr, StackString};
+use time::{macros::format_description, OffsetDateTime};
+
+use crate::config::Config;
+
+/// Snapshot storage in an S3-compatible bucket, each table is kept under
+/// `{prefix}/{table}/` with timestamped keys so that lexical order matches
+/// chronological order.
+#[derive(Clone)]
+pub struct S3Backup {
+    client: S3Client,
+    bucket: StackString,
+    prefix: StackString,
+    retention: usize,
+}
+
+impl S3Backup {
+    /// # Errors
+    /// Returns error if `BACKUP_S3_BUCKET` is not set
+    pub async fn new(config: &Config) -> Result<Self, Error> {
+        let bucket = config
+            .backup_s3_bucket
+            .clone()
+            .ok_or_else(|| format_err!("BACKUP_S3_BUCKET not set"))?;
+        Ok(Self {
+            client: build_s3_client(config).await,
+            bucket,
+            prefix: config.backup_s3_prefix.clone(),
+            retention: config.backup_retention,
+        })
+    }
+
+    fn table_prefix(&self, table: &str) -> StackString {
+        format_sstr!("{}/{table}/", self.prefix)
+    }
+
+    /// List snapshot keys for `table`, oldest first
+    /// # Errors
+    /// Returns error if s3 api call fails
+    pub async fn list_snapshots(&self, table: &str) -> Result<Vec<StackString>, Error> {
+        let prefix = self.table_prefix(table);
+        let mut keys = Vec::new();
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let response = self
+                .client
+                .list_objects_v2()
+                .bucket(self.bucket.as_str())
+                .prefix(prefix.as_str())
+                .set_continuation_token(continuation_token.take())
+                .send()
+                .await?;
+            keys.extend(
+                response
+                    .contents()
+                    .iter()
+                    .filter_map(|object| object.key().map(Into::into)),
+            );
+            match response.next_continuation_token() {
+                Some(token) => continuation_token = Some(token.into()),
+                None => break,
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Upload a snapshot and prune old ones, returns the new key
+    /// # Errors
+    /// Returns error if s3 api call fails
+    pub async fn upload(
+        &self,
+        table: &str,
+        data: Vec<u8>,
+        encrypted: bool,
+    ) -> Result<StackString, Error> {
+        let key = snapshot_key(
+            &self.table_prefix(table),
+            table,
+            OffsetDateTime::now_utc(),
+            encrypted,
+        )?;
+        self.client
+            .put_object()
+            .bucket(self.bucket.as_str())
+            .key(key.as_str())
+            .body(ByteStream::from(data))
+            .send()
+            .await?;
+        self.prune(table).await?;
+        Ok(key)
+    }
+
+    /// Download the snapshot `key`, or the most recent snapshot of `table`
+    /// # Errors
+    /// Returns error if s3 api call fails or there are no snapshots
+    pub async fn download(&self, table: &str, key: Option<&str>) -> Result<Vec<u8>, Error> {
+        let key: StackString = match key {
+            Some(key) => key.into(),
+            None => self
+                .list_snapshots(table)
+                .await?
+                .pop()
+                .ok_or_else(|| format_err!("No snapshots found for {table}"))?,
+        };
+        let object = self
+            .client
+            .get_object()
+            .bucket(self.bucket.as_str())
+            .key(key.as_str())
+            .send()
+            .await?;
+        let data = object.body.collect().await?.into_bytes();
+        Ok(data.to_vec())
+    }
+
+    /// Delete all but the most recent `BACKUP_RETENTION` snapshots
+    /// # Errors
+    /// Returns error if s3 api call fails
+    pub async fn prune(&self, table: &str) -> Result<Vec<StackString>, Error> {
+        let keys = self.list_snapshots(table).await?;
+        let expired = expired_snapshots(&keys, self.retention.max(1));
+        for key in expired {
+            self.client
+                .delete_object()
+                .bucket(self.bucket.as_str())
+                .key(key.as_str())
+                .send()
+                .await?;
+        }
+        Ok(expired.to_vec())
+    }
+}
+
+/// Build a client for the configured S3-compatible endpoint, shared by backups
+/// and attachment storage.
+pub(crate) async fn build_s3_client(config: &Config) -> S3Client {
+    let sdk_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+    let mut builder = S3ConfigBuilder::from(&sdk_config);
+    if let Some(endpoint) = &config.backup_s3_endpoint {
+        builder = builder
+            .endpoint_url(endpoint.as_str())
+            .force_path_style(true);
+    }
+    if let Some(region) = &config.backup_s3_region {
+        builder = builder.region(Region::new(region.to_string()));
+    }
+    if let (Some(key_id), Some(secret)) = (
+        &config.backup_s3_access_key_id,
+        &config.backup_s3_secret_access_key,
+    ) {
+        let credentials = Credentials::new(
+            key_id.as_str(),
+            secret.as_str(),
+            None,
+            None,
+            "calendar_app_rust",
+        );
+        builder = builder.credentials_provider(credentials);
+    }
+    S3Client::from_conf(builder.build())
+}
+
+fn snapshot_key(
+    table_prefix: &str,
+    table: &str,
+    timestamp: OffsetDateTime,
+    encrypted: bool,
+) -> Result<StackString, Error> {
+    let timestamp = timestamp.format(format_description!(
+        "[year][month][day]T[hour][minute][second]Z"
+    ))?;
+    let extension = if encrypted { "json.age" } else { "json" };
+    Ok(format_sstr!(
+        "{table_prefix}{table}_{timestamp}.{extension}"
+    ))
+}
+
+/// `keys` must be sorted oldest first
+fn expired_snapshots(keys: &[StackString], retention: usize) -> &[StackString] {
+    &keys[..keys.len().saturating_sub(retention)]
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::StackString;
+    use time::macros::datetime;
+
+    use crate::s3_backup::{expired_snapshots, snapshot_key};
+
+    #[test]
+    fn test_snapshot_key() -> Result<(), Error> {
+        let key = snapshot_key(
+            "backups/calendar_cache/",
+            "calendar_cache",
+            datetime!(2024-03-01 12:30:05 UTC),
+            true,
+        )?;
+        assert_eq!(
+            &key,
+            "backups/calendar_cache/calendar_cache_20240301T123005Z.json.age"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_expired_snapshots() {
+        let keys: Vec<StackString> = (0..5).map(StackString::from_display).collect();
+        assert_eq!(expired_snapshots(&keys, 3), &keys[..2]);
+        assert!(expired_snapshots(&keys, 10).is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::Error;
+use log::error;
+use stack_string::{format_sstr, StackString};
+use std::future::Future;
+
+use crate::{
+    models::{CalendarCache, ScrapeRun},
+    pgpool::PgPool,
+};
+
+/// Number of earlier successful runs a new run is compared against
+pub const SCRAPE_HISTORY_WINDOW: usize = 10;
+/// Fewer earlier runs than this aren't enough to call a drop
+const MIN_HISTORY: usize = 3;
+
+/// What one scrape of a source produced, `parse_failures` counts rows that
+/// looked like events but couldn't be parsed.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrapeCounts {
+    pub found: usize,
+    pub new: usize,
+    pub updated: usize,
+    pub parse_failures: usize,
+}
+
+/// Why a run that found `counts` looks broken next to the `previous`
+/// successful runs of the same source, `None` if it looks normal.
+#[must_use]
+pub fn detect_drop(
+    previous: &[ScrapeRun],
+    counts: &ScrapeCounts,
+    max_drop_percent: usize,
+) -> Option<StackString> {
+    if previous.len() < MIN_HISTORY {
+        return None;
+    }
+    let total: usize = previous
+        .iter()
+        .map(|run| run.events_found.max(0) as usize)
+        .sum();
+    let average = total / previous.len();
+    if average == 0 {
+        return None;
+    }
+    if counts.found * 100 < average * (100 - max_drop_percent.min(100)) {
+        Some(format_sstr!(
+            "found {} events, down from an average of {average}",
+            counts.found
+        ))
+    } else if counts.parse_failures > counts.found {
+        Some(format_sstr!(
+            "{} parse failures but only {} events found",
+            counts.parse_failures,
+            counts.found
+        ))
+    } else {
+        None
+    }
+}
+
+/// Run `scrape` for `source` and record the outcome in `scrape_runs`, a
+/// failed scrape is recorded before its error is returned.
+/// # Errors
+/// Returns error if the scrape or db queries fail
+pub async fn record_scrape<F>(
+    source: &str,
+    scrape: F,
+    max_drop_percent: usize,
+    pool: &PgPool,
+) -> Result<(Vec<CalendarCache>, ScrapeRun), Error>
+where
+    F: Future<Output = Result<(Vec<CalendarCache>, ScrapeCounts), Error>>,
+{
+    let mut run = ScrapeRun::new(source);
+    let result = scrape.await;
+    match &result {
+        Ok((_, counts)) => {
+            let previous =
+                ScrapeRun::get_latest_successful(source, SCRAPE_HISTORY_WINDOW, pool).await?;
+            run.events_found = counts.found.try_into()?;
+            run.events_new = counts.new.try_into()?;
+            run.events_updated = counts.updated.try_into()?;
+            run.parse_failures = counts.parse_failures.try_into()?;
+            run.alert = detect_drop(&previous, counts, max_drop_percent);
+            if let Some(alert) = &run.alert {
+                error!("scrape of {source} looks broken: {alert}");
+            }
+        }
+        Err(e) => {
+            run.error = Some(format_sstr!("{e}"));
+        }
+    }
+    run.insert(pool).await?;
+    let (events, _) = result?;
+    Ok((events, run))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::ScrapeRun,
+        scrape_health::{detect_drop, ScrapeCounts},
+    };
+
+    #[test]
+    fn test_detect_drop() {
+        let previous: Vec<_> = [20, 22, 18]
+            .into_iter()
+            .map(|found| {
+                let mut run = ScrapeRun::new("nycruns");
+                run.events_found = found;
+                run
+            })
+            .collect();
+        let counts = |found, parse_failures| ScrapeCounts {
+            found,
+            parse_failures,
+            ..ScrapeCounts::default()
+        };
+
+        assert!(detect_drop(&previous, &counts(19, 0), 50).is_none());
+        assert!(detect_drop(&previous[..2], &counts(0, 0), 50).is_none());
+        assert_eq!(
+            detect_drop(&previous, &counts(0, 0), 50).as_deref(),
+            Some("found 0 events, down from an average of 20")
+        );
+        assert!(detect_drop(&previous, &counts(9, 0), 50).is_some());
+        assert!(detect_drop(&previous, &counts(10, 0), 50).is_none());
+        assert_eq!(
+            detect_drop(&previous, &counts(12, 15), 50).as_deref(),
+            Some("15 parse failures but only 12 events found")
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::Error;
+use log::{debug, error};
+use stack_string::StackString;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    select,
+    signal::ctrl_c,
+    sync::watch,
+    task::JoinHandle,
+    time::{timeout_at, Instant},
+};
+
+/// Shutdown signal shared (by cloning) between a binary's background loops
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Self {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    #[must_use]
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once `trigger` has been called on any clone
+    pub async fn cancelled(&self) {
+        let mut receiver = self.receiver.clone();
+        // Only fails if the sender is dropped, and self holds a reference
+        receiver.wait_for(|triggered| *triggered).await.ok();
+    }
+
+    /// Run `fut` until it finishes or shutdown is triggered, `None` if it
+    /// was cancelled
+    pub async fn run_until<F: Future>(&self, fut: F) -> Option<F::Output> {
+        select! {
+            biased;
+            () = self.cancelled() => None,
+            output = fut => Some(output),
+        }
+    }
+
+    /// Trigger shutdown on ctrl-c (or SIGTERM on unix), returns early if
+    /// shutdown is triggered some other way
+    /// # Errors
+    /// Returns error if the signal handlers can't be installed
+    pub async fn trigger_on_signal(&self) -> Result<(), Error> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut sigterm = signal(SignalKind::terminate())?;
+            select! {
+                result = ctrl_c() => result?,
+                _ = sigterm.recv() => {},
+                () = self.cancelled() => return Ok(()),
+            }
+        }
+        #[cfg(not(unix))]
+        select! {
+            result = ctrl_c() => result?,
+            () = self.cancelled() => return Ok(()),
+        }
+        debug!("shutdown signal received");
+        self.trigger();
+        Ok(())
+    }
+}
+
+/// Background tasks spawned under a `Shutdown`, so a binary can wait for them
+/// to stop on exit instead of leaving them detached
+#[derive(Clone, Debug)]
+pub struct TaskTracker {
+    shutdown: Shutdown,
+    tasks: Arc<Mutex<Vec<(StackString, JoinHandle<()>)>>>,
+}
+
+impl TaskTracker {
+    #[must_use]
+    pub fn new(shutdown: Shutdown) -> Self {
+        Self {
+            shutdown,
+            tasks: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    #[must_use]
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Spawn `task`, it's dropped at its next await point once shutdown is
+    /// triggered.  Errors are logged rather than returned.
+    pub fn spawn<F>(&self, name: &str, task: F)
+    where
+        F: Future<Output = Result<(), Error>> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        let task_name: StackString = name.into();
+        let handle = tokio::spawn(async move {
+            match shutdown.run_until(task).await {
+                Some(Ok(())) => debug!("task {task_name} finished"),
+                Some(Err(e)) => error!("task {task_name} failed {e}"),
+                None => debug!("task {task_name} cancelled"),
+            }
+        });
+        let mut tasks = self.tasks.lock().unwrap_or_else(PoisonError::into_inner);
+        tasks.retain(|(_, handle)| !handle.is_finished());
+        tasks.push((name.into(), handle));
+    }
+
+    /// Number of tasks that haven't stopped yet
+    #[must_use]
+    pub fn running(&self) -> usize {
+        let tasks = self.tasks.lock().unwrap_or_else(PoisonError::into_inner);
+        tasks
+            .iter()
+            .filter(|(_, handle)| !handle.is_finished())
+            .count()
+    }
+
+    /// Trigger shutdown and wait up to `grace` for every task to stop,
+    /// tasks still running after that are aborted and their names returned
+    pub async fn shutdown_and_wait(&self, grace: Duration) -> Vec<StackString> {
+        self.shutdown.trigger();
+        let tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .drain(..)
+            .collect();
+        let deadline = Instant::now() + grace;
+        let mut aborted = Vec::new();
+        for (name, mut handle) in tasks {
+            if timeout_at(deadline, &mut handle).await.is_err() {
+                error!("task {name} didn't stop within {grace:?}, aborting");
+                handle.abort();
+                aborted.push(name);
+            }
+        }
+        aborted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::time::sleep;
+
+    use crate::shutdown::{Shutdown, TaskTracker};
+
+    async fn tick_forever(ticks: Arc<AtomicUsize>) -> Result<(), Error> {
+        loop {
+            ticks.fetch_add(1, Ordering::SeqCst);
+            sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_task_tracker_shutdown() -> Result<(), Error> {
+        let tracker = TaskTracker::new(Shutdown::new());
+        let ticks = Arc::new(AtomicUsize::new(0));
+        tracker.spawn("first", tick_forever(ticks.clone()));
+        tracker.spawn("second", tick_forever(ticks.clone()));
+        tracker.spawn("finishes", async { Ok::<_, Error>(()) });
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(tracker.running(), 2);
+        assert!(ticks.load(Ordering::SeqCst) > 0);
+
+        let aborted = tracker.shutdown_and_wait(Duration::from_secs(1)).await;
+        assert!(aborted.is_empty());
+        assert!(tracker.shutdown().is_triggered());
+        assert_eq!(tracker.running(), 0);
+
+        let stopped_at = ticks.load(Ordering::SeqCst);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), stopped_at);
+
+        // Nothing runs once shutdown has been triggered
+        assert_eq!(tracker.shutdown().run_until(async { 1 }).await, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use stack_string::{format_sstr, StackString};
+use std::{borrow::Cow, sync::Arc};
+use time::{Duration, OffsetDateTime};
+use url::form_urlencoded;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs paths so they can be fetched without a login (e.g. attachment links
+/// on shared pages) until the embedded expiry time.
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Arc<[u8]>,
+}
+
+impl UrlSigner {
+    #[must_use]
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn mac(&self, path: &str, expires: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC can take key of any size");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(expires.to_string().as_bytes());
+        mac
+    }
+
+    /// Returns `path` with `expires` and `signature` query parameters
+    /// appended, `path` may already contain a query string which is then
+    /// covered by the signature and percent-encoded in the returned url.
+    #[must_use]
+    pub fn sign(&self, path: &str, expires_in: Duration) -> StackString {
+        let expires = (OffsetDateTime::now_utc() + expires_in).unix_timestamp();
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let signed_path = canonical_path(path, form_urlencoded::parse(query.as_bytes()));
+        let signature = hex::encode(self.mac(&signed_path, expires).finalize().into_bytes());
+        let separator = if signed_path.contains('?') { '&' } else { '?' };
+        format_sstr!("{signed_path}{separator}expires={expires}&signature={signature}")
+    }
+
+    /// Verify a request for `path` with raw (percent-encoded) query string
+    /// `query` against a url produced by `sign`.
+    #[must_use]
+    pub fn verify_url(&self, path: &str, query: &str) -> bool {
+        self.verify_url_expires(path, query).is_some()
+    }
+
+    /// Like `verify_url`, returning when the signed url expires.
+    #[must_use]
+    pub fn verify_url_expires(&self, path: &str, query: &str) -> Option<OffsetDateTime> {
+        let mut expires = None;
+        let mut signature = None;
+        let mut rest = Vec::new();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "expires" => expires = value.parse().ok(),
+                "signature" => signature = Some(value),
+                _ => rest.push((key, value)),
+            }
+        }
+        let (Some(expires), Some(signature)) = (expires, signature) else {
+            return None;
+        };
+        if !self.verify(&canonical_path(path, rest), expires, &signature) {
+            return None;
+        }
+        OffsetDateTime::from_unix_timestamp(expires).ok()
+    }
+
+    #[must_use]
+    pub fn verify(&self, path: &str, expires: i64, signature: &str) -> bool {
+        if expires < OffsetDateTime::now_utc().unix_timestamp() {
+            return false;
+        }
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+        self.mac(path, expires).verify_slice(&signature).is_ok()
+    }
+}
+
+/// `path` with the decoded `params` re-encoded, so a url signs the same
+/// however its query string was escaped
+fn canonical_path<'a>(
+    path: &str,
+    params: impl IntoIterator<Item = (Cow<'a, str>, Cow<'a, str>)>,
+) -> StackString {
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in params {
+        serializer.append_pair(&key, &value);
+    }
+    let query = serializer.finish();
+    if query.is_empty() {
+        path.into()
+    } else {
+        format_sstr!("{path}?{query}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use crate::signed_url::UrlSigner;
+
+    #[test]
+    fn test_sign_verify() {
+        let signer = UrlSigner::new(b"test secret");
+        let signed = signer.sign("/calendar/attachment/abc", Duration::hours(1));
+        let (path, query) = signed.split_once('?').unwrap();
+        assert_eq!(path, "/calendar/attachment/abc");
+        let params: Vec<_> = query.split('&').filter_map(|p| p.split_once('=')).collect();
+        assert_eq!(params[0].0, "expires");
+        assert_eq!(params[1].0, "signature");
+        let expires: i64 = params[0].1.parse().unwrap();
+        let signature = params[1].1;
+
+        assert!(signer.verify(path, expires, signature));
+        assert!(!signer.verify("/calendar/attachment/def", expires, signature));
+        assert!(!signer.verify(path, expires + 1, signature));
+        assert!(!UrlSigner::new(b"other secret").verify(path, expires, signature));
+
+        let expired = signer.sign(path, Duration::hours(-1));
+        let (_, query) = expired.split_once('?').unwrap();
+        let params: Vec<_> = query.split('&').filter_map(|p| p.split_once('=')).collect();
+        assert!(!signer.verify(path, params[0].1.parse().unwrap(), params[1].1));
+    }
+
+    #[test]
+    fn test_sign_verify_url() {
+        let signer = UrlSigner::new(b"test secret");
+        let signed = signer.sign(
+            "/calendar/list_events?calendar_name=Work",
+            Duration::minutes(30),
+        );
+        let (path, query) = signed.split_once('?').unwrap();
+        assert_eq!(path, "/calendar/list_events");
+        assert!(query.starts_with("calendar_name=Work&expires="));
+        assert!(signer.verify_url(path, query));
+
+        let tampered = query.replace("Work", "Home");
+        assert!(!signer.verify_url(path, &tampered));
+        assert!(!signer.verify_url("/calendar/agenda", query));
+        assert!(!signer.verify_url(path, "calendar_name=Work"));
+
+        let signed = signer.sign("/calendar/agenda", Duration::minutes(30));
+        let (path, query) = signed.split_once('?').unwrap();
+        assert!(signer.verify_url(path, query));
+
+        let expires = signer.verify_url_expires(path, query).unwrap();
+        let remaining = expires - OffsetDateTime::now_utc();
+        assert!(remaining > Duration::minutes(29) && remaining <= Duration::minutes(30));
+    }
+
+    #[test]
+    fn test_sign_verify_url_encoded() {
+        let signer = UrlSigner::new(b"test secret");
+        let signed = signer.sign(
+            "/calendar/list_events?gcal_id=foo@group.calendar.google.com&calendar_name=Run \
+             Club #1",
+            Duration::minutes(30),
+        );
+        let (path, query) = signed.split_once('?').unwrap();
+        assert!(query
+            .starts_with("gcal_id=foo%40group.calendar.google.com&calendar_name=Run+Club+%231&"));
+        assert!(signer.verify_url(path, query));
+
+        // Browsers and proxies don't all escape the same characters
+        let reencoded = query.replace("%40", "@").replace('+', "%20");
+        assert!(signer.verify_url(path, &reencoded));
+        let tampered = query.replace("foo%40", "bar%40");
+        assert!(!signer.verify_url(path, &tampered));
+    }
+}
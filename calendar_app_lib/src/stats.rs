@@ -0,0 +1,58 @@
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use std::collections::BTreeMap;
+use time::{Duration, OffsetDateTime};
+
+use gcal_lib::date_time_wrapper::DateTimeWrapper;
+
+use crate::{
+    models::{CalendarCache, CalendarList, ScrapeRun, SyncHistory},
+    pgpool::PgPool,
+};
+
+/// Overview of a deployment served by `/calendar/stats`
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct CalendarStats {
+    pub calendars: usize,
+    pub events: usize,
+    pub last_sync: Option<DateTimeWrapper>,
+    pub pending_full_syncs: usize,
+    /// Errors and alerts from the latest run of each scraper
+    pub scrape_alerts: Vec<StackString>,
+}
+
+impl CalendarStats {
+    /// # Errors
+    /// Returns error if db queries fail
+    pub async fn get(pool: &PgPool) -> Result<Self, Error> {
+        let calendars = CalendarList::get_total(pool, None, None).await?;
+        let events = CalendarCache::get_total(pool, None, None).await?;
+        let last_sync = SyncHistory::get_last_success(pool)
+            .await?
+            .and_then(|history| history.finished_at);
+        let pending_full_syncs = SyncHistory::get_pending(pool).await?.len();
+
+        let min_time = OffsetDateTime::now_utc() - Duration::days(1);
+        let latest: BTreeMap<_, _> = ScrapeRun::get_since(min_time, pool)
+            .await?
+            .into_iter()
+            .map(|run| (run.source.clone(), run))
+            .collect();
+        let scrape_alerts = latest
+            .values()
+            .filter_map(|run| {
+                let problem = run.error.as_ref().or(run.alert.as_ref())?;
+                Some(format_sstr!("{}: {problem}", run.source))
+            })
+            .collect();
+
+        Ok(Self {
+            calendars,
+            events,
+            last_sync,
+            pending_full_syncs,
+            scrape_alerts,
+        })
+    }
+}
@@ -0,0 +1,472 @@
+use log::debug;
+use std::collections::{HashMap, HashSet};
+
+use gcal_lib::gcal_instance::{compare_gcal_events, Event as GCalEvent};
+
+use crate::{
+    calendar::{access_role_can_edit, gcal_event_editable, Event},
+    models::CalendarCache,
+};
+
+/// Events deleted in gcal are listed (with `showDeleted`) as cancelled
+/// tombstones rather than dropped.
+#[must_use]
+pub fn is_cancelled(item: &GCalEvent) -> bool {
+    item.status.as_deref() == Some("cancelled")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Only copy events missing on either side, events both sides have are
+    /// left alone
+    Full,
+    /// For events both sides have, the most recent edit wins
+    Future,
+}
+
+/// What syncing a calendar will do, decided up front from gcal's events and
+/// the local cache so the rules can be checked without either.  An event id
+/// appears in at most one list.
+#[derive(Default, Debug, Clone)]
+pub struct SyncPlan {
+    /// Local events missing from gcal, to create there
+    pub inserts: Vec<CalendarCache>,
+    /// Local events edited after gcal's copy, to write to gcal
+    pub updates: Vec<CalendarCache>,
+    /// gcal events to write to the local cache
+    pub imports: Vec<CalendarCache>,
+    /// Cached events deleted in gcal
+    pub deletes: Vec<CalendarCache>,
+}
+
+impl SyncPlan {
+    /// Every event id the plan touches, with repeats if it's inconsistent
+    pub fn event_ids(&self) -> impl Iterator<Item = &str> {
+        self.inserts
+            .iter()
+            .chain(&self.updates)
+            .chain(&self.imports)
+            .chain(&self.deletes)
+            .map(|event| event.event_id.as_str())
+    }
+}
+
+fn to_gcal_event(item: &CalendarCache) -> GCalEvent {
+    let event: Event = item.clone().into();
+    event.to_gcal_event().1
+}
+
+/// A local edit only overwrites gcal's copy if it was made later, gcal
+/// events without an `updated` time never win.
+fn local_is_newer(item: &CalendarCache, gcal_event: &GCalEvent) -> bool {
+    gcal_event
+        .updated
+        .map_or(true, |updated| item.last_modified > updated)
+}
+
+/// Decide how to sync `gcal_id`, `edit` allows writing to gcal (which also
+/// needs a writable `access_role`).  `calendar_events` and `database_events`
+/// must cover the same time range.
+#[must_use]
+pub fn plan_sync(
+    gcal_id: &str,
+    calendar_events: &[GCalEvent],
+    database_events: &[CalendarCache],
+    access_role: Option<&str>,
+    edit: bool,
+    mode: SyncMode,
+) -> SyncPlan {
+    let can_write = edit && access_role_can_edit(access_role);
+    let mut database_map: HashMap<_, _> = database_events
+        .iter()
+        .map(|item| (item.event_id.as_str(), item))
+        .collect();
+    let mut plan = SyncPlan::default();
+    let mut seen = HashSet::new();
+
+    for gcal_event in calendar_events {
+        let Some(event_id) = gcal_event.id.as_deref() else {
+            continue;
+        };
+        // gcal can list an event more than once, the first copy wins
+        if !seen.insert(event_id) {
+            continue;
+        }
+        let local = database_map.remove(event_id);
+        if is_cancelled(gcal_event) {
+            plan.deletes.extend(local.cloned());
+            continue;
+        }
+        let Some(event) = Event::from_gcal_event(gcal_event, gcal_id) else {
+            debug!("skipping {event_id} {:?}", gcal_event.start);
+            continue;
+        };
+        let mut event: CalendarCache = event.into();
+        event.editable = event.editable && access_role_can_edit(access_role);
+        match (local, mode) {
+            (None, _) => plan.imports.push(event),
+            (Some(_), SyncMode::Full) => {}
+            (Some(local), SyncMode::Future) => {
+                // Compare as the cache stores events, all-day dates and time
+                // zones aren't kept locally so would otherwise always differ
+                if can_write
+                    && gcal_event_editable(gcal_event, gcal_id)
+                    && !compare_gcal_events(&to_gcal_event(&event), &to_gcal_event(local))
+                    && local_is_newer(local, gcal_event)
+                {
+                    plan.updates.push(local.clone());
+                } else {
+                    plan.imports.push(event);
+                }
+            }
+        }
+    }
+
+    if can_write {
+        plan.inserts.extend(
+            database_events
+                .iter()
+                .filter(|item| database_map.contains_key(item.event_id.as_str()))
+                .filter(|item| seen.insert(item.event_id.as_str()))
+                .cloned(),
+        );
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use stack_string::format_sstr;
+    use std::collections::{BTreeMap, HashSet};
+    use time::{macros::datetime, Duration, OffsetDateTime};
+
+    use gcal_lib::gcal_instance::{compare_gcal_events, Event as GCalEvent, EventDateTime};
+
+    use crate::{
+        calendar::{access_role_can_edit, Event},
+        models::CalendarCache,
+        sync_plan::{is_cancelled, plan_sync, to_gcal_event, SyncMode, SyncPlan},
+    };
+
+    const GCAL_ID: &str = "test@example.com";
+
+    /// In memory stand-in for gcal (listing with tombstones) and the local
+    /// cache, applying a `SyncPlan` the way `CalendarSync` does.  The clock
+    /// ticks once per step so edit times are always distinct.
+    #[derive(Default, Debug)]
+    struct MockCalendar {
+        remote: BTreeMap<String, GCalEvent>,
+        local: BTreeMap<String, CalendarCache>,
+        clock: i64,
+    }
+
+    #[derive(Debug, Clone)]
+    enum Step {
+        RemoteCreate(usize),
+        RemoteEdit(usize),
+        RemoteDelete(usize),
+        LocalCreate(usize),
+        LocalEdit(usize),
+        Sync(SyncMode, &'static str),
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            (0..8usize).prop_map(Step::RemoteCreate),
+            (0..8usize).prop_map(Step::RemoteEdit),
+            (0..8usize).prop_map(Step::RemoteDelete),
+            (0..8usize).prop_map(Step::LocalCreate),
+            (0..8usize).prop_map(Step::LocalEdit),
+            Just(Step::Sync(SyncMode::Full, "owner")),
+            Just(Step::Sync(SyncMode::Future, "owner")),
+            Just(Step::Sync(SyncMode::Future, "reader")),
+        ]
+    }
+
+    impl MockCalendar {
+        fn tick(&mut self) -> OffsetDateTime {
+            self.clock += 1;
+            datetime!(2024-01-01 00:00:00 UTC) + Duration::minutes(self.clock)
+        }
+
+        fn new_event(&mut self, n: usize) -> CalendarCache {
+            let start = datetime!(2024-06-01 12:00:00 UTC) + Duration::hours(n as i64);
+            let mut event: CalendarCache = Event::new(
+                GCAL_ID,
+                format_sstr!("event {n} v{}", self.clock),
+                start,
+                start + Duration::hours(1),
+            )
+            .into();
+            event.last_modified = self.tick().into();
+            event
+        }
+
+        /// Events created in gcal are timed, all-day (only a date) or timed
+        /// with a time zone, depending on `n`
+        fn new_remote_event(&mut self, n: usize) -> GCalEvent {
+            let mut gcal_event = to_gcal_event(&self.new_event(n));
+            match n % 3 {
+                1 => {
+                    let all_day = |day: usize| {
+                        Some(EventDateTime {
+                            date: Some(format!("2024-06-{day:02}")),
+                            ..EventDateTime::default()
+                        })
+                    };
+                    gcal_event.start = all_day(n + 1);
+                    gcal_event.end = all_day(n + 2);
+                }
+                2 => {
+                    for dt in gcal_event.start.iter_mut().chain(gcal_event.end.iter_mut()) {
+                        dt.time_zone = Some("America/New_York".into());
+                    }
+                }
+                _ => {}
+            }
+            gcal_event.updated = Some(self.tick().into());
+            gcal_event
+        }
+
+        fn write_remote(&mut self, item: &CalendarCache) {
+            let mut gcal_event = to_gcal_event(item);
+            gcal_event.updated = Some(self.tick().into());
+            self.remote.insert(item.event_id.to_string(), gcal_event);
+        }
+
+        fn write_local(&mut self, mut item: CalendarCache) {
+            // `last_modified` is reset on every write to calendar_cache
+            item.last_modified = self.tick().into();
+            self.local.insert(item.event_id.to_string(), item);
+        }
+
+        fn nth<T: Clone>(map: &BTreeMap<String, T>, n: usize) -> Option<T> {
+            if map.is_empty() {
+                None
+            } else {
+                map.values().nth(n % map.len()).cloned()
+            }
+        }
+
+        fn live_remote(&self) -> impl Iterator<Item = &GCalEvent> {
+            self.remote.values().filter(|e| !is_cancelled(e))
+        }
+
+        fn apply(&mut self, step: &Step) -> Option<SyncPlan> {
+            match step {
+                Step::RemoteCreate(n) => {
+                    let gcal_event = self.new_remote_event(*n);
+                    self.remote
+                        .insert(gcal_event.id.clone().unwrap_or_default(), gcal_event);
+                }
+                Step::RemoteEdit(n) => {
+                    let live: BTreeMap<_, _> = self
+                        .live_remote()
+                        .map(|e| (e.id.clone().unwrap_or_default(), e.clone()))
+                        .collect();
+                    if let Some(mut gcal_event) = Self::nth(&live, *n) {
+                        gcal_event.summary = Some(format!("remote edit {}", self.clock));
+                        gcal_event.updated = Some(self.tick().into());
+                        self.remote
+                            .insert(gcal_event.id.clone().unwrap_or_default(), gcal_event);
+                    }
+                }
+                Step::RemoteDelete(n) => {
+                    if let Some(mut gcal_event) = Self::nth(&self.remote, *n) {
+                        gcal_event.status = Some("cancelled".into());
+                        gcal_event.updated = Some(self.tick().into());
+                        self.remote
+                            .insert(gcal_event.id.clone().unwrap_or_default(), gcal_event);
+                    }
+                }
+                Step::LocalCreate(n) => {
+                    let event = self.new_event(*n);
+                    self.write_local(event);
+                }
+                Step::LocalEdit(n) => {
+                    if let Some(mut event) = Self::nth(&self.local, *n) {
+                        event.event_name = format_sstr!("local edit {}", self.clock);
+                        self.write_local(event);
+                    }
+                }
+                Step::Sync(mode, access_role) => {
+                    let calendar_events: Vec<_> = self.remote.values().cloned().collect();
+                    let database_events: Vec<_> = self.local.values().cloned().collect();
+                    let plan = plan_sync(
+                        GCAL_ID,
+                        &calendar_events,
+                        &database_events,
+                        Some(access_role),
+                        true,
+                        *mode,
+                    );
+                    for item in plan.inserts.iter().chain(&plan.updates) {
+                        self.write_remote(item);
+                    }
+                    for item in &plan.imports {
+                        self.write_local(item.clone());
+                    }
+                    for item in &plan.deletes {
+                        self.local.remove(item.event_id.as_str());
+                    }
+                    return Some(plan);
+                }
+            }
+            None
+        }
+    }
+
+    /// `gcal_event` as the local cache would store it
+    fn normalized(gcal_event: &GCalEvent) -> GCalEvent {
+        let event = Event::from_gcal_event(gcal_event, GCAL_ID).unwrap();
+        to_gcal_event(&event.into())
+    }
+
+    proptest! {
+        #[test]
+        fn test_sync_invariants(steps in prop::collection::vec(step(), 1..40)) {
+            let mut calendar = MockCalendar::default();
+            for step in &steps {
+                let remote_before = calendar.remote.clone();
+                let local_before = calendar.local.clone();
+                let Some(plan) = calendar.apply(step) else {
+                    continue;
+                };
+                let Step::Sync(mode, access_role) = step else {
+                    continue;
+                };
+                let writable = access_role_can_edit(Some(*access_role));
+
+                // No event is touched twice
+                let ids: Vec<_> = plan.event_ids().collect();
+                let unique: HashSet<_> = ids.iter().collect();
+                prop_assert_eq!(ids.len(), unique.len());
+
+                for (event_id, before) in &remote_before {
+                    let after = &calendar.remote[event_id];
+                    // Tombstones stay deleted and aren't cached locally
+                    if is_cancelled(before) {
+                        prop_assert!(is_cancelled(after));
+                        prop_assert!(!calendar.local.contains_key(event_id));
+                        continue;
+                    }
+                    // Remote edits newer than the local copy are never overwritten
+                    let remote_newer = local_before.get(event_id).map_or(true, |local| {
+                        before.updated.map_or(false, |u| u >= local.last_modified)
+                    });
+                    if remote_newer {
+                        prop_assert!(compare_gcal_events(before, after));
+                    }
+                    // Events already in sync aren't rewritten (which would
+                    // drop all-day dates and time zones)
+                    let in_sync = local_before.get(event_id).map_or(false, |local| {
+                        compare_gcal_events(&normalized(before), &to_gcal_event(local))
+                    });
+                    if in_sync {
+                        prop_assert!(compare_gcal_events(before, after));
+                    }
+                    // Nothing is written to read-only calendars, and full
+                    // syncs leave events both sides had alone
+                    if !writable || (*mode == SyncMode::Full && local_before.contains_key(event_id)) {
+                        prop_assert!(compare_gcal_events(before, after));
+                    }
+                    if *mode == SyncMode::Full {
+                        if let Some(local) = local_before.get(event_id) {
+                            prop_assert_eq!(&calendar.local[event_id].event_name, &local.event_name);
+                        }
+                    }
+                }
+
+                // Every live event is cached, and with write access created
+                // in gcal
+                for gcal_event in calendar.live_remote() {
+                    let event_id = gcal_event.id.as_deref().unwrap_or_default();
+                    prop_assert!(calendar.local.contains_key(event_id));
+                }
+                if writable {
+                    for event_id in calendar.local.keys() {
+                        prop_assert!(calendar.remote.contains_key(event_id));
+                    }
+                } else {
+                    prop_assert_eq!(calendar.remote.len(), remote_before.len());
+                }
+
+                // After an incremental sync both sides agree
+                if writable && *mode == SyncMode::Future {
+                    for (event_id, local) in &calendar.local {
+                        let remote = normalized(&calendar.remote[event_id]);
+                        prop_assert!(compare_gcal_events(&remote, &to_gcal_event(local)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_sync_tombstone_and_conflict() {
+        let mut calendar = MockCalendar::default();
+        let shared = calendar.new_event(0);
+        calendar.write_remote(&shared);
+        calendar.write_local(shared.clone());
+        let deleted = calendar.new_event(1);
+        calendar.write_remote(&deleted);
+        calendar.write_local(deleted.clone());
+
+        // Edited remotely after the local edit, so gcal wins
+        let mut local_edit = shared.clone();
+        local_edit.event_name = "local edit".into();
+        calendar.write_local(local_edit.clone());
+        let mut remote_edit = to_gcal_event(&shared);
+        remote_edit.summary = Some("remote edit".into());
+        remote_edit.updated = Some(calendar.tick().into());
+        calendar
+            .remote
+            .insert(shared.event_id.to_string(), remote_edit);
+        let mut tombstone = to_gcal_event(&deleted);
+        tombstone.status = Some("cancelled".into());
+        calendar
+            .remote
+            .insert(deleted.event_id.to_string(), tombstone);
+
+        let plan = calendar
+            .apply(&Step::Sync(SyncMode::Future, "owner"))
+            .unwrap();
+        assert!(plan.inserts.is_empty());
+        assert!(plan.updates.is_empty());
+        assert_eq!(plan.deletes.len(), 1);
+        assert_eq!(plan.deletes[0].event_id, deleted.event_id);
+        assert_eq!(plan.imports.len(), 1);
+        assert_eq!(plan.imports[0].event_name, "remote edit");
+        assert!(!calendar.local.contains_key(deleted.event_id.as_str()));
+
+        // A later local edit is written back, but only with write access
+        calendar.write_local(local_edit);
+        let created = calendar.new_event(2);
+        calendar.write_local(created.clone());
+        let calendar_events: Vec<_> = calendar.remote.values().cloned().collect();
+        let database_events: Vec<_> = calendar.local.values().cloned().collect();
+        let plan = |access_role| {
+            plan_sync(
+                GCAL_ID,
+                &calendar_events,
+                &database_events,
+                Some(access_role),
+                true,
+                SyncMode::Future,
+            )
+        };
+
+        let read_only = plan("reader");
+        assert!(read_only.inserts.is_empty());
+        assert!(read_only.updates.is_empty());
+        assert_eq!(read_only.imports.len(), 1);
+
+        let writable = plan("owner");
+        assert_eq!(writable.inserts.len(), 1);
+        assert_eq!(writable.inserts[0].event_id, created.event_id);
+        assert_eq!(writable.updates.len(), 1);
+        assert_eq!(writable.updates[0].event_name, "local edit");
+        assert!(writable.imports.is_empty());
+    }
+}
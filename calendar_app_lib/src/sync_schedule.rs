@@ -0,0 +1,196 @@
+use stack_string::{format_sstr, StackString};
+use std::collections::HashMap;
+use time::{Duration, OffsetDateTime, Time};
+use time_tz::{OffsetDateTimeExt, PrimitiveDateTimeExt};
+
+use gcal_lib::gcal_instance::Event as GCalEvent;
+
+use crate::{calendar::Event, models::CalendarCache, sync_plan::is_cancelled, timezone::TimeZone};
+
+/// Differences between gcal and the local cache seen by an incremental sync.
+/// Incremental syncs only upsert what gcal returns and drop its cancelled
+/// tombstones, so events gcal no longer lists at all (`local_only`) are
+/// never cleaned up without a full sync.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncDrift {
+    pub gcal_only: usize,
+    pub local_only: usize,
+    pub changed: usize,
+    pub total: usize,
+}
+
+impl SyncDrift {
+    #[must_use]
+    pub fn new(calendar_events: &[GCalEvent], database_events: &[CalendarCache]) -> Self {
+        let gcal_map: HashMap<_, _> = calendar_events
+            .iter()
+            .filter(|item| !is_cancelled(item))
+            .filter_map(|item| item.id.as_ref().map(|id| (id.as_str(), item)))
+            .collect();
+        let mut drift = Self::default();
+        for item in database_events {
+            match gcal_map.get(item.event_id.as_str()) {
+                Some(gcal_event) => {
+                    if !same_event(gcal_event, item) {
+                        drift.changed += 1;
+                    }
+                }
+                None => drift.local_only += 1,
+            }
+        }
+        let matched = database_events.len() - drift.local_only;
+        drift.gcal_only = gcal_map.len() - matched;
+        drift.total = gcal_map.len() + drift.local_only;
+        drift
+    }
+
+    /// Share of events only a full sync can fix (`local_only`), in percent.
+    /// `gcal_only` and `changed` events are picked up by the next
+    /// incremental sync.
+    #[must_use]
+    pub fn percent(&self) -> usize {
+        if self.total == 0 {
+            0
+        } else {
+            100 * self.local_only / self.total
+        }
+    }
+}
+
+/// Compares what the user sees rather than the raw gcal representation, so
+/// all day events and explicit time zones don't count as drift.
+fn same_event(gcal_event: &GCalEvent, item: &CalendarCache) -> bool {
+    Event::from_gcal_event(gcal_event, item.gcal_id.as_str()).map_or(false, |event| {
+        event.name == item.event_name
+            && event.start_time == item.event_start_time
+            && event.end_time == item.event_end_time
+            && event.description == item.event_description
+            && event.location.map(|l| l.name) == item.event_location_name
+    })
+}
+
+/// Why a calendar needs a full sync, `None` if incremental syncs are
+/// keeping up.
+#[must_use]
+pub fn full_sync_reason(
+    incremental_syncs: usize,
+    drift: &SyncDrift,
+    max_drift_percent: usize,
+    max_incremental_syncs: usize,
+) -> Option<StackString> {
+    let percent = drift.percent();
+    if percent > max_drift_percent {
+        Some(format_sstr!(
+            "drift {percent}% (gcal only {}, local only {}, changed {} of {})",
+            drift.gcal_only,
+            drift.local_only,
+            drift.changed,
+            drift.total
+        ))
+    } else if incremental_syncs >= max_incremental_syncs {
+        Some(format_sstr!(
+            "{incremental_syncs} incremental syncs since last full sync"
+        ))
+    } else {
+        None
+    }
+}
+
+/// Next time it is `hour` o'clock in `timezone`, full syncs are deferred to
+/// then to keep them clear of peak usage and api quota.
+#[must_use]
+pub fn next_off_peak(now: OffsetDateTime, hour: u8, timezone: TimeZone) -> OffsetDateTime {
+    let local = now.to_timezone(timezone.into());
+    let time = Time::from_hms(hour % 24, 0, 0).unwrap_or(Time::MIDNIGHT);
+    let mut date = local.date();
+    if local.time() >= time {
+        date = date.next_day().unwrap_or(date);
+    }
+    let candidate = date.with_time(time);
+    candidate
+        .assume_timezone(timezone.into())
+        .take()
+        // The hour is skipped on the day clocks spring forward, use the local
+        // time an hour later, just past the gap
+        .or_else(|| {
+            (candidate + Duration::hours(1))
+                .assume_timezone(timezone.into())
+                .take()
+        })
+        .unwrap_or_else(|| candidate.assume_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::{macros::datetime, Duration};
+
+    use crate::{
+        calendar::Event,
+        models::CalendarCache,
+        sync_schedule::{full_sync_reason, next_off_peak, SyncDrift},
+        timezone::TimeZone,
+    };
+
+    #[test]
+    fn test_sync_drift() {
+        let start = datetime!(2024-07-01 12:00:00 UTC);
+        let events: Vec<Event> = (0..4)
+            .map(|i| {
+                let start = start + Duration::days(i);
+                Event::new("cal@example.com", "Run", start, start + Duration::hours(1))
+            })
+            .collect();
+        let gcal_events: Vec<_> = events[..3].iter().map(|e| e.to_gcal_event().1).collect();
+        let mut database_events: Vec<CalendarCache> =
+            events[1..].iter().cloned().map(Into::into).collect();
+        database_events[0].event_name = "Long Run".into();
+
+        let drift = SyncDrift::new(&gcal_events, &database_events);
+        assert_eq!(
+            drift,
+            SyncDrift {
+                gcal_only: 1,
+                local_only: 1,
+                changed: 1,
+                total: 4,
+            }
+        );
+        assert_eq!(drift.percent(), 25);
+        assert_eq!(SyncDrift::default().percent(), 0);
+
+        let reason = full_sync_reason(0, &drift, 5, 48).unwrap();
+        assert!(reason.starts_with("drift 25%"));
+        assert!(full_sync_reason(47, &SyncDrift::default(), 5, 48).is_none());
+        assert_eq!(
+            full_sync_reason(48, &SyncDrift::default(), 5, 48)
+                .unwrap()
+                .as_str(),
+            "48 incremental syncs since last full sync"
+        );
+    }
+
+    #[test]
+    fn test_next_off_peak() -> Result<(), Error> {
+        let utc = TimeZone::utc();
+        let now = datetime!(2024-07-01 12:00:00 UTC);
+        assert_eq!(
+            next_off_peak(now, 3, utc),
+            datetime!(2024-07-02 03:00:00 UTC)
+        );
+        let now = datetime!(2024-07-01 01:30:00 UTC);
+        assert_eq!(
+            next_off_peak(now, 3, utc),
+            datetime!(2024-07-01 03:00:00 UTC)
+        );
+
+        // 2am doesn't exist in New York on 2024-03-10, 3am EDT is used instead
+        let new_york: TimeZone = "America/New_York".parse()?;
+        let now = datetime!(2024-03-09 17:00:00 UTC);
+        assert_eq!(
+            next_off_peak(now, 2, new_york),
+            datetime!(2024-03-10 07:00:00 UTC)
+        );
+        Ok(())
+    }
+}
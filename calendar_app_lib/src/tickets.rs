@@ -0,0 +1,135 @@
+use anyhow::Error;
+use image::{DynamicImage, ImageFormat, Luma};
+use qrcode::QrCode;
+use stack_string::{format_sstr, StackString};
+use std::io::Cursor;
+
+use crate::{attachments::AttachmentStore, models::EventAttachment};
+
+const TICKET_KEYWORDS: [&str; 4] = ["ticket", "boarding", "admission", "pass"];
+
+/// Ticket content ready to be sent to a chat, `is_image` distinguishes a
+/// photo from a document (e.g. a pdf ticket without a readable barcode).
+pub struct TicketContent {
+    pub filename: StackString,
+    pub data: Vec<u8>,
+    pub is_image: bool,
+}
+
+/// Decode the first QR code found in an image attachment.
+#[must_use]
+pub fn extract_barcode(content_type: &str, data: &[u8]) -> Option<StackString> {
+    if !content_type.starts_with("image/") {
+        return None;
+    }
+    let image = image::load_from_memory(data).ok()?.into_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| image.get_pixel(x as u32, y as u32).0[0],
+    );
+    prepared
+        .detect_grids()
+        .into_iter()
+        .find_map(|grid| grid.decode().ok())
+        .map(|(_, payload)| payload.into())
+}
+
+/// Returns `(is_ticket, barcode_payload)`, an attachment is a ticket if it
+/// contains a barcode or its name looks like a ticket.
+#[must_use]
+pub fn detect_ticket(
+    filename: &str,
+    content_type: &str,
+    data: &[u8],
+) -> (bool, Option<StackString>) {
+    let barcode_payload = extract_barcode(content_type, data);
+    let is_ticket = barcode_payload.is_some()
+        || ((content_type.starts_with("image/") || content_type == "application/pdf")
+            && filename_has_ticket_keyword(filename));
+    (is_ticket, barcode_payload)
+}
+
+/// Match keywords as whole words (or their plural), so e.g. `passport.pdf`
+/// or `compass.png` aren't taken for tickets.
+fn filename_has_ticket_keyword(filename: &str) -> bool {
+    let filename = filename.to_lowercase();
+    filename
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .any(|word| {
+            TICKET_KEYWORDS
+                .iter()
+                .any(|k| word == *k || word.strip_suffix('s') == Some(*k))
+        })
+}
+
+/// Render `payload` as a QR code png.
+/// # Errors
+/// Returns error if the payload is too large for a QR code or encoding fails
+pub fn render_barcode(payload: &str) -> Result<Vec<u8>, Error> {
+    let image = QrCode::new(payload.as_bytes())?
+        .render::<Luma<u8>>()
+        .min_dimensions(400, 400)
+        .build();
+    let mut output = Cursor::new(Vec::new());
+    DynamicImage::ImageLuma8(image).write_to(&mut output, ImageFormat::Png)?;
+    Ok(output.into_inner())
+}
+
+/// Prefer a freshly rendered barcode (easy for venue scanners to read),
+/// otherwise send the stored attachment as is.
+/// # Errors
+/// Returns error if the attachment can't be read from the store
+pub async fn ticket_content(
+    attachment: &EventAttachment,
+    store: &AttachmentStore,
+) -> Result<TicketContent, Error> {
+    if let Some(payload) = &attachment.barcode_payload {
+        let data = render_barcode(payload)?;
+        return Ok(TicketContent {
+            filename: format_sstr!("{}.png", attachment.attachment_id),
+            data,
+            is_image: true,
+        });
+    }
+    let data = store.get(&attachment.storage_key).await?;
+    Ok(TicketContent {
+        filename: attachment.filename.clone(),
+        data,
+        is_image: attachment.content_type.starts_with("image/"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::tickets::{detect_ticket, render_barcode};
+
+    #[test]
+    fn test_barcode_roundtrip() -> Result<(), Error> {
+        let payload = "TICKET-1234-ROW-F-SEAT-12";
+        let png = render_barcode(payload)?;
+
+        let (is_ticket, barcode) = detect_ticket("scan.png", "image/png", &png);
+        assert!(is_ticket);
+        assert_eq!(barcode.as_ref().map(|b| b.as_str()), Some(payload));
+
+        let (is_ticket, barcode) = detect_ticket("scan.pdf", "application/pdf", &png);
+        assert!(!is_ticket);
+        assert!(barcode.is_none());
+
+        let (is_ticket, _) = detect_ticket("Concert_Ticket.pdf", "application/pdf", b"%PDF");
+        assert!(is_ticket);
+
+        let (is_ticket, _) = detect_ticket("agenda.pdf", "application/pdf", b"%PDF");
+        assert!(!is_ticket);
+
+        let (is_ticket, _) = detect_ticket("boarding-pass.png", "image/png", b"");
+        assert!(is_ticket);
+
+        let (is_ticket, _) = detect_ticket("passport_scan.pdf", "application/pdf", b"%PDF");
+        assert!(!is_ticket);
+        Ok(())
+    }
+}
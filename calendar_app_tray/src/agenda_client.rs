@@ -0,0 +1,121 @@
+use anyhow::{format_err, Error};
+use log::debug;
+use reqwest::{header::AUTHORIZATION, Client};
+use stack_string::{format_sstr, StackString};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use calendar_app_lib::{calendar::AgendaEvent, config::Config};
+
+/// Fetches `/calendar/agenda.json` with the tray api key and keeps the last
+/// response on disk, so the tray has something to show while offline.
+pub struct AgendaClient {
+    client: Client,
+    url: StackString,
+    api_key: StackString,
+    cache_path: PathBuf,
+}
+
+fn default_cache_path() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("calendar_app_rust")
+        .join("tray_agenda.json")
+}
+
+impl AgendaClient {
+    /// # Errors
+    /// Returns error if `TRAY_API_KEY` isn't set
+    pub fn new(config: &Config) -> Result<Self, Error> {
+        let api_key = config
+            .tray_api_key
+            .clone()
+            .ok_or_else(|| format_err!("TRAY_API_KEY not set"))?;
+        Ok(Self {
+            client: Client::new(),
+            url: format_sstr!(
+                "https://{}/calendar/agenda.json?description=none",
+                config.domain
+            ),
+            api_key,
+            cache_path: default_cache_path(),
+        })
+    }
+
+    /// # Errors
+    /// Returns error if the request fails or the response isn't an agenda
+    pub async fn fetch(&self) -> Result<Vec<AgendaEvent>, Error> {
+        let events = self
+            .client
+            .get(self.url.as_str())
+            .header(
+                AUTHORIZATION,
+                format_sstr!("Bearer {}", self.api_key).as_str(),
+            )
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(events)
+    }
+
+    /// Fetch the agenda, falling back to the cached copy if the server can't
+    /// be reached.
+    /// # Errors
+    /// Returns error if both the fetch and reading the cache fail
+    pub async fn refresh(&self) -> Result<Vec<AgendaEvent>, Error> {
+        match self.fetch().await {
+            Ok(events) => {
+                if let Err(e) = write_cache(&self.cache_path, &events).await {
+                    debug!("failed to write agenda cache {e}");
+                }
+                Ok(events)
+            }
+            Err(e) => {
+                debug!("failed to fetch agenda {e}, using cache");
+                read_cache(&self.cache_path).await
+            }
+        }
+    }
+}
+
+async fn write_cache(path: &Path, events: &[AgendaEvent]) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::write(path, serde_json::to_vec(events)?).await?;
+    Ok(())
+}
+
+async fn read_cache(path: &Path) -> Result<Vec<AgendaEvent>, Error> {
+    let data = fs::read(path).await?;
+    serde_json::from_slice(&data).map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::datetime;
+
+    use calendar_app_lib::calendar::{AgendaEvent, Event};
+
+    use crate::agenda_client::{read_cache, write_cache};
+
+    #[tokio::test]
+    async fn test_agenda_cache() -> Result<(), Error> {
+        let path = std::env::temp_dir()
+            .join(format!("tray_agenda_{}", std::process::id()))
+            .join("tray_agenda.json");
+        assert!(read_cache(&path).await.is_err());
+
+        let start = datetime!(2024-03-01 18:30:00 UTC);
+        let event = Event::new("cal@example.com", "Dinner", start, start);
+        let events = vec![AgendaEvent::new(event, "Personal")];
+        write_cache(&path, &events).await?;
+        assert_eq!(read_cache(&path).await?, events);
+
+        std::fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,5 @@
+#![allow(clippy::module_name_repetitions)]
+
+pub mod agenda_client;
+pub mod tray_app;
+pub mod tray_state;
@@ -0,0 +1,202 @@
+use anyhow::Error;
+use log::{debug, error};
+use notify_rust::Notification;
+use stack_string::format_sstr;
+use std::{thread::JoinHandle, time::Duration as StdDuration};
+use tao::{
+    event::{Event, StartCause},
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy},
+};
+use time::{Duration, OffsetDateTime};
+use tokio::time::{interval, Instant};
+use tray_icon::{
+    menu::{Menu, MenuEvent, MenuItem},
+    Icon, MouseButton, MouseButtonState, TrayIcon, TrayIconBuilder, TrayIconEvent,
+};
+
+use calendar_app_lib::{
+    calendar::AgendaEvent, config::Config, shutdown::Shutdown, timezone::TimeZone,
+};
+
+use crate::{agenda_client::AgendaClient, tray_state::TrayState};
+
+const REFRESH_INTERVAL: StdDuration = StdDuration::from_secs(300);
+const TICK_INTERVAL: StdDuration = StdDuration::from_secs(30);
+
+enum UserEvent {
+    Agenda(Vec<AgendaEvent>),
+    Tick,
+    Tray(TrayIconEvent),
+    Menu(MenuEvent),
+}
+
+/// Plain calendar blue square, avoids shipping an icon file
+fn tray_icon_image() -> Result<Icon, Error> {
+    const SIZE: u32 = 32;
+    let rgba: Vec<u8> = (0..SIZE * SIZE)
+        .flat_map(|_| vec![0x1a, 0x73, 0xe8, 0xff])
+        .collect();
+    Icon::from_rgba(rgba, SIZE, SIZE).map_err(Into::into)
+}
+
+/// Poll the agenda every `REFRESH_INTERVAL` and wake the event loop every
+/// `TICK_INTERVAL` to check for due reminders, until `shutdown` is triggered.
+fn spawn_poller(
+    client: AgendaClient,
+    proxy: EventLoopProxy<UserEvent>,
+    shutdown: Shutdown,
+) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                error!("failed to start runtime {e}");
+                return;
+            }
+        };
+        let poll = async move {
+            let mut ticker = interval(TICK_INTERVAL);
+            let mut last_refresh: Option<Instant> = None;
+            loop {
+                ticker.tick().await;
+                if last_refresh.map_or(true, |t| t.elapsed() >= REFRESH_INTERVAL) {
+                    match client.refresh().await {
+                        Ok(events) => {
+                            if proxy.send_event(UserEvent::Agenda(events)).is_err() {
+                                return;
+                            }
+                        }
+                        Err(e) => error!("failed to load agenda {e}"),
+                    }
+                    last_refresh.replace(Instant::now());
+                }
+                if proxy.send_event(UserEvent::Tick).is_err() {
+                    return;
+                }
+            }
+        };
+        runtime.block_on(shutdown.run_until(poll));
+    })
+}
+
+fn open_web_ui(url: &str) {
+    if let Err(e) = open::that(url) {
+        error!("failed to open {url} {e}");
+    }
+}
+
+fn show_reminders(state: &mut TrayState, now: OffsetDateTime) {
+    for event in state.due_reminders(now) {
+        let body = state.notification_body(&event);
+        if let Err(e) = Notification::new()
+            .appname("calendar-app-rust")
+            .summary(&event.name)
+            .body(&body)
+            .show()
+        {
+            error!("failed to show notification {e}");
+        }
+    }
+}
+
+/// Run the tray companion, this blocks the calling thread (which must be
+/// the main thread on macOS) until Quit is selected.
+/// # Errors
+/// Returns error if `TRAY_API_KEY` isn't set
+pub fn run_tray(config: &Config) -> Result<(), Error> {
+    let client = AgendaClient::new(config)?;
+    let web_ui = format_sstr!("https://{}/calendar/index.html", config.domain);
+    let timezone = config.default_time_zone.unwrap_or_else(TimeZone::local);
+    let mut state = TrayState::new(Duration::minutes(config.tray_reminder_minutes), timezone);
+
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
+
+    let proxy = event_loop.create_proxy();
+    TrayIconEvent::set_event_handler(Some(move |event| {
+        proxy.send_event(UserEvent::Tray(event)).unwrap_or(());
+    }));
+    let proxy = event_loop.create_proxy();
+    MenuEvent::set_event_handler(Some(move |event| {
+        proxy.send_event(UserEvent::Menu(event)).unwrap_or(());
+    }));
+    let shutdown = Shutdown::new();
+    let mut poller = Some(spawn_poller(
+        client,
+        event_loop.create_proxy(),
+        shutdown.clone(),
+    ));
+
+    let menu = Menu::new();
+    let open_item = MenuItem::new("Open Calendar", true, None);
+    let quit_item = MenuItem::new("Quit", true, None);
+    menu.append(&open_item)?;
+    menu.append(&quit_item)?;
+    let mut menu = Some(menu);
+    let mut tray: Option<TrayIcon> = None;
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Wait;
+        match event {
+            // The tray icon has to be created once the event loop runs (gtk
+            // on linux, NSApplication on macOS).
+            Event::NewEvents(StartCause::Init) => {
+                let icon = match tray_icon_image() {
+                    Ok(icon) => icon,
+                    Err(e) => {
+                        error!("failed to build icon {e}");
+                        *control_flow = ControlFlow::Exit;
+                        return;
+                    }
+                };
+                let mut builder = TrayIconBuilder::new()
+                    .with_tooltip(state.tooltip(OffsetDateTime::now_utc()).as_str())
+                    .with_icon(icon);
+                if let Some(menu) = menu.take() {
+                    builder = builder.with_menu(Box::new(menu));
+                }
+                match builder.build() {
+                    Ok(icon) => {
+                        tray.replace(icon);
+                    }
+                    Err(e) => {
+                        error!("failed to create tray icon {e}");
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
+            }
+            Event::UserEvent(UserEvent::Agenda(events)) => {
+                debug!("agenda refreshed {}", events.len());
+                state.update(events);
+            }
+            Event::UserEvent(UserEvent::Tick) => {
+                let now = OffsetDateTime::now_utc();
+                show_reminders(&mut state, now);
+                if let Some(tray) = &tray {
+                    tray.set_tooltip(Some(state.tooltip(now).as_str()))
+                        .unwrap_or(());
+                }
+            }
+            Event::UserEvent(UserEvent::Tray(TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            })) => open_web_ui(&web_ui),
+            Event::UserEvent(UserEvent::Menu(event)) => {
+                if event.id == *open_item.id() {
+                    open_web_ui(&web_ui);
+                } else if event.id == *quit_item.id() {
+                    tray.take();
+                    shutdown.trigger();
+                    if let Some(poller) = poller.take() {
+                        poller.join().unwrap_or(());
+                    }
+                    *control_flow = ControlFlow::Exit;
+                }
+            }
+            _ => {}
+        }
+    })
+}
@@ -0,0 +1,156 @@
+use stack_string::{format_sstr, StackString};
+use std::collections::HashSet;
+use time::{macros::format_description, Duration, OffsetDateTime};
+use time_tz::OffsetDateTimeExt;
+
+use calendar_app_lib::{calendar::AgendaEvent, timezone::TimeZone};
+
+/// Windows truncates tray tooltips at 128 characters
+const MAX_TOOLTIP_LENGTH: usize = 127;
+
+/// Agenda as seen by the tray, tracks which reminders were already shown so
+/// every event pops exactly one notification.
+pub struct TrayState {
+    events: Vec<AgendaEvent>,
+    notified: HashSet<(StackString, i64)>,
+    reminder: Duration,
+    timezone: TimeZone,
+}
+
+impl TrayState {
+    #[must_use]
+    pub fn new(reminder: Duration, timezone: TimeZone) -> Self {
+        Self {
+            events: Vec::new(),
+            notified: HashSet::new(),
+            reminder,
+            timezone,
+        }
+    }
+
+    pub fn update(&mut self, mut events: Vec<AgendaEvent>) {
+        events.sort_by_key(|event| event.start_time);
+        // Keyed on start time as well, a rescheduled event is reminded again
+        let current: HashSet<_> = events
+            .iter()
+            .map(|event| (event.event_id.clone(), event.start_time.unix_timestamp()))
+            .collect();
+        self.notified.retain(|key| current.contains(key));
+        self.events = events;
+    }
+
+    /// Event in progress or the next one to start
+    #[must_use]
+    pub fn next_event(&self, now: OffsetDateTime) -> Option<&AgendaEvent> {
+        self.events.iter().find(|event| *event.end_time > now)
+    }
+
+    fn format_time(&self, dt: OffsetDateTime) -> StackString {
+        dt.to_timezone(self.timezone.into())
+            .format(format_description!("[month]/[day] [hour]:[minute]"))
+            .unwrap_or_default()
+            .into()
+    }
+
+    #[must_use]
+    pub fn tooltip(&self, now: OffsetDateTime) -> StackString {
+        let tooltip = match self.next_event(now) {
+            Some(event) if *event.start_time <= now => format_sstr!(
+                "Now: {} until {}",
+                event.name,
+                self.format_time(*event.end_time)
+            ),
+            Some(event) => format_sstr!(
+                "Next: {} at {}",
+                event.name,
+                self.format_time(*event.start_time)
+            ),
+            None => "No upcoming events".into(),
+        };
+        if tooltip.len() > MAX_TOOLTIP_LENGTH {
+            let mut end = MAX_TOOLTIP_LENGTH;
+            while !tooltip.is_char_boundary(end) {
+                end -= 1;
+            }
+            StackString::from(&tooltip[..end])
+        } else {
+            tooltip
+        }
+    }
+
+    /// Events starting within the reminder window that haven't been reminded
+    pub fn due_reminders(&mut self, now: OffsetDateTime) -> Vec<AgendaEvent> {
+        let reminder = self.reminder;
+        let notified = &mut self.notified;
+        self.events
+            .iter()
+            .filter(|event| {
+                let start_time = *event.start_time;
+                start_time > now
+                    && start_time - reminder <= now
+                    && notified.insert((event.event_id.clone(), start_time.unix_timestamp()))
+            })
+            .cloned()
+            .collect()
+    }
+
+    #[must_use]
+    pub fn notification_body(&self, event: &AgendaEvent) -> StackString {
+        let start_time = self.format_time(*event.start_time);
+        match &event.location {
+            Some(location) => format_sstr!("{start_time} at {location}"),
+            None => start_time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+
+    use calendar_app_lib::{
+        calendar::{AgendaEvent, Event},
+        timezone::TimeZone,
+    };
+
+    use crate::tray_state::TrayState;
+
+    #[test]
+    fn test_tray_state() {
+        let start = datetime!(2024-03-01 18:30:00 UTC);
+        let first = Event::new(
+            "cal@example.com",
+            "Dinner",
+            start,
+            start + Duration::hours(1),
+        );
+        let second = Event::new(
+            "cal@example.com",
+            "Movie",
+            start + Duration::hours(2),
+            start + Duration::hours(4),
+        );
+        let mut state = TrayState::new(Duration::minutes(5), TimeZone::utc());
+        state.update(vec![
+            AgendaEvent::new(second, "Personal"),
+            AgendaEvent::new(first, "Personal"),
+        ]);
+
+        let now = start - Duration::minutes(10);
+        assert_eq!(&state.tooltip(now), "Next: Dinner at 03/01 18:30");
+        assert!(state.due_reminders(now).is_empty());
+
+        let now = start - Duration::minutes(4);
+        let due = state.due_reminders(now);
+        assert_eq!(due.len(), 1);
+        assert_eq!(&due[0].name, "Dinner");
+        assert!(state.due_reminders(now).is_empty());
+
+        let now = start + Duration::minutes(30);
+        assert_eq!(&state.tooltip(now), "Now: Dinner until 03/01 19:30");
+
+        let now = start + Duration::hours(5);
+        assert!(state.next_event(now).is_none());
+        assert_eq!(&state.tooltip(now), "No upcoming events");
+    }
+}
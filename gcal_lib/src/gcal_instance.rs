@@ -10,7 +10,7 @@ use std::{fs::create_dir_all, path::Path, sync::Arc};
 
 use stdout_channel::rate_limiter::RateLimiter;
 
-pub use crate::calendar_v3_types::{CalendarListEntry, Event, EventDateTime};
+pub use crate::calendar_v3_types::{CalendarListEntry, Event, EventDateTime, EventOrganizer};
 use crate::{
     calendar_v3_types::{
         CalendarList, CalendarListListParams, CalendarListService, CalendarScopes, Events,
@@ -130,6 +130,7 @@ impl GCalendarInstance {
             time_min: time_min.map(Into::into),
             time_max: time_max.map(Into::into),
             page_token: next_page_token.map(Into::into),
+            show_deleted: Some(true),
             ..EventsListParams::default()
         };
         exponential_retry(|| async {
@@ -145,7 +146,26 @@ impl GCalendarInstance {
         min_time: Option<OffsetDateTime>,
         max_time: Option<OffsetDateTime>,
     ) -> Result<Vec<Event>, Error> {
+        self.get_gcal_events_access_role(gcal_id, min_time, max_time)
+            .await
+            .map(|(events, _)| {
+                events
+                    .into_iter()
+                    .filter(|event| event.status.as_deref() != Some("cancelled"))
+                    .collect()
+            })
+    }
+
+    /// Events of `gcal_id` (including deleted events, as cancelled
+    /// tombstones) along with the `accessRole` the listing reports for us.
+    pub async fn get_gcal_events_access_role(
+        &self,
+        gcal_id: &str,
+        min_time: Option<OffsetDateTime>,
+        max_time: Option<OffsetDateTime>,
+    ) -> Result<(Vec<Event>, Option<StackString>), Error> {
         let mut output = Vec::new();
+        let mut access_role: Option<StackString> = None;
         let mut next_page_token: Option<StackString> = None;
         loop {
             let cal_list = self
@@ -156,6 +176,9 @@ impl GCalendarInstance {
                     next_page_token.as_ref().map(StackString::as_str),
                 )
                 .await?;
+            if let Some(role) = cal_list.access_role {
+                access_role.get_or_insert_with(|| role.into());
+            }
             if let Some(cal_list) = cal_list.items {
                 output.extend_from_slice(&cal_list);
             }
@@ -165,7 +188,7 @@ impl GCalendarInstance {
                 break;
             }
         }
-        Ok(output)
+        Ok((output, access_role))
     }
 
     pub async fn get_event(&self, gcal_id: &str, gcal_event_id: &str) -> Result<Event, Error> {
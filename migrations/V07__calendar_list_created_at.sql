@@ -0,0 +1,2 @@
+ALTER TABLE calendar_list ADD COLUMN created_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE calendar_list ALTER COLUMN created_at SET DEFAULT now();
@@ -0,0 +1,11 @@
+CREATE TABLE event_attachments (
+    attachment_id UUID NOT NULL PRIMARY KEY,
+    gcal_id TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    storage_key TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS event_attachments_event_idx ON event_attachments (gcal_id, event_id);
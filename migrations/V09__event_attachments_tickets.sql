@@ -0,0 +1,2 @@
+ALTER TABLE event_attachments ADD COLUMN is_ticket BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE event_attachments ADD COLUMN barcode_payload TEXT;
@@ -0,0 +1,11 @@
+CREATE TABLE feed_tokens (
+    token TEXT NOT NULL PRIMARY KEY,
+    email TEXT NOT NULL,
+    gcal_id TEXT NOT NULL,
+    description TEXT,
+    request_count BIGINT NOT NULL DEFAULT 0,
+    rejected_count BIGINT NOT NULL DEFAULT 0,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS feed_tokens_email_idx ON feed_tokens (email);
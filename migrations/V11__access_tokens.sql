@@ -0,0 +1,9 @@
+ALTER TABLE feed_tokens RENAME TO access_tokens;
+ALTER INDEX feed_tokens_email_idx RENAME TO access_tokens_email_idx;
+ALTER TABLE access_tokens ALTER COLUMN gcal_id DROP NOT NULL;
+ALTER TABLE access_tokens ADD COLUMN is_api_key BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE access_tokens ADD COLUMN read_only BOOLEAN NOT NULL DEFAULT true;
+ALTER TABLE access_tokens ADD COLUMN expires_at TIMESTAMP WITH TIME ZONE;
+ALTER TABLE access_tokens ADD COLUMN previous_token TEXT;
+ALTER TABLE access_tokens ADD COLUMN previous_expires_at TIMESTAMP WITH TIME ZONE;
+CREATE INDEX IF NOT EXISTS access_tokens_previous_token_idx ON access_tokens (previous_token);
@@ -0,0 +1,14 @@
+CREATE TABLE inbound_sources (
+    source_token TEXT NOT NULL PRIMARY KEY,
+    source_name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    gcal_id TEXT NOT NULL,
+    required_fields TEXT[] NOT NULL DEFAULT '{}',
+    max_duration_minutes INTEGER,
+    rate_limit_per_minute INTEGER NOT NULL DEFAULT 10,
+    request_count BIGINT NOT NULL DEFAULT 0,
+    rejected_count BIGINT NOT NULL DEFAULT 0,
+    last_used_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS inbound_sources_email_idx ON inbound_sources (email);
@@ -0,0 +1,9 @@
+CREATE TABLE sync_history (
+    sync_id UUID NOT NULL PRIMARY KEY,
+    full_sync BOOLEAN NOT NULL DEFAULT false,
+    success BOOLEAN NOT NULL DEFAULT false,
+    summary TEXT,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    finished_at TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX IF NOT EXISTS sync_history_finished_at_idx ON sync_history (finished_at);
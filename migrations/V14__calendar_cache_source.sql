@@ -0,0 +1,2 @@
+ALTER TABLE calendar_cache ADD COLUMN source_id TEXT;
+ALTER TABLE calendar_cache ADD COLUMN source_modified TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1 @@
+ALTER TABLE calendar_cache ADD COLUMN editable BOOLEAN NOT NULL DEFAULT true;
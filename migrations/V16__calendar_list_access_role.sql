@@ -0,0 +1 @@
+ALTER TABLE calendar_list ADD COLUMN access_role TEXT;
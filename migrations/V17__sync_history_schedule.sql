@@ -0,0 +1,4 @@
+ALTER TABLE sync_history ADD COLUMN gcal_id TEXT;
+ALTER TABLE sync_history ADD COLUMN reason TEXT;
+ALTER TABLE sync_history ADD COLUMN scheduled_for TIMESTAMP WITH TIME ZONE;
+CREATE INDEX IF NOT EXISTS sync_history_gcal_id_idx ON sync_history (gcal_id);
@@ -0,0 +1,12 @@
+CREATE TABLE scrape_runs (
+    run_id UUID NOT NULL PRIMARY KEY,
+    source TEXT NOT NULL,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    events_found INTEGER NOT NULL DEFAULT 0,
+    events_new INTEGER NOT NULL DEFAULT 0,
+    events_updated INTEGER NOT NULL DEFAULT 0,
+    parse_failures INTEGER NOT NULL DEFAULT 0,
+    error TEXT,
+    alert TEXT
+);
+CREATE INDEX IF NOT EXISTS scrape_runs_source_started_at_idx ON scrape_runs (source, started_at);
@@ -0,0 +1,6 @@
+ALTER TABLE access_tokens RENAME COLUMN token TO token_hash;
+ALTER TABLE access_tokens RENAME COLUMN previous_token TO previous_token_hash;
+ALTER INDEX access_tokens_previous_token_idx RENAME TO access_tokens_previous_token_hash_idx;
+UPDATE access_tokens
+SET token_hash=encode(sha256(token_hash::bytea), 'hex'),
+    previous_token_hash=encode(sha256(previous_token_hash::bytea), 'hex');
@@ -1,7 +1,8 @@
 use anyhow::Error;
+use tokio::try_join;
 
 use calendar_app_bot::telegram_bot::TelegramBot;
-use calendar_app_lib::{config::Config, pgpool::PgPool};
+use calendar_app_lib::{config::Config, pgpool::PgPool, shutdown::Shutdown};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -11,7 +12,8 @@ async fn main() -> Result<(), Error> {
         let pool = PgPool::new(&config.database_url)?;
         if let Some(telegram_bot_token) = config.telegram_bot_token.as_ref() {
             let bot = TelegramBot::new(telegram_bot_token, &pool, &config).await;
-            Box::pin(bot.run()).await?;
+            let shutdown = Shutdown::new();
+            try_join!(Box::pin(bot.run(&shutdown)), shutdown.trigger_on_signal())?;
         }
         Ok(())
     })
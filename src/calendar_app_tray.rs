@@ -0,0 +1,10 @@
+use anyhow::Error;
+
+use calendar_app_lib::config::Config;
+use calendar_app_tray::tray_app::run_tray;
+
+fn main() -> Result<(), Error> {
+    env_logger::init();
+    let config = Config::init_config()?;
+    run_tray(&config)
+}
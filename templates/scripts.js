@@ -1,18 +1,80 @@
 !function() {
     displayAgenda();
 }()
-function displayAgenda() {
-    let url = "/calendar/agenda";
+let agendaRefreshTimer = null;
+let nowLineTimer = null;
+function displayAgenda(week=false, refresh=false) {
+    let url = week ? "/calendar/agenda?week=true" : "/calendar/agenda";
     let xmlhttp = new XMLHttpRequest();
     xmlhttp.onload = function f() {
-        document.getElementById("sub_article").innerHTML = "&nbsp;";
+        if (!refresh) {
+            document.getElementById("sub_article").innerHTML = "&nbsp;";
+        }
         document.getElementById("main_article").innerHTML = xmlhttp.responseText;
         document.getElementById("garminconnectoutput").innerHTML = "done";
+        startAgendaRefresh();
     }
     xmlhttp.open("GET", url, true);
     xmlhttp.send(null);
     document.getElementById("garminconnectoutput").innerHTML = "running";
 }
+function agendaTable() {
+    return document.getElementById("agenda_table");
+}
+function refreshAgenda() {
+    let table = agendaTable();
+    if (!table) {
+        stopAgendaRefresh();
+    } else if (!document.hidden) {
+        displayAgenda(table.dataset.week == "true", true);
+    }
+}
+function updateNowLine() {
+    let table = agendaTable();
+    let marker = document.getElementById("now_line");
+    if (!table || !marker) {
+        return;
+    }
+    let now = Date.now() / 1000;
+    let next = null;
+    for (const row of table.querySelectorAll("tr[data-start]")) {
+        if (parseInt(row.dataset.start) > now) {
+            next = row;
+            break;
+        }
+    }
+    if (next) {
+        next.parentNode.insertBefore(marker, next);
+    } else {
+        marker.parentNode.appendChild(marker);
+    }
+    marker.cells[0].textContent = "now " + new Date().toLocaleTimeString();
+}
+function stopAgendaRefresh() {
+    clearInterval(agendaRefreshTimer);
+    clearInterval(nowLineTimer);
+    agendaRefreshTimer = null;
+    nowLineTimer = null;
+}
+function startAgendaRefresh() {
+    stopAgendaRefresh();
+    let table = agendaTable();
+    if (!table) {
+        return;
+    }
+    updateNowLine();
+    nowLineTimer = setInterval(updateNowLine, 60 * 1000);
+    let seconds = parseInt(table.dataset.refresh);
+    if (seconds > 0) {
+        agendaRefreshTimer = setInterval(refreshAgenda, seconds * 1000);
+    }
+}
+document.addEventListener("visibilitychange", function() {
+    // Refresh is paused while the tab is hidden, catch up when it's shown
+    if (!document.hidden && agendaTable()) {
+        refreshAgenda();
+    }
+});
 function syncCalendars() {
     let url = "/calendar/sync_calendars";
     let xmlhttp = new XMLHttpRequest();
@@ -83,6 +145,18 @@ function listCalendars() {
     xmlhttp.send(null);
     document.getElementById("garminconnectoutput").innerHTML = "running";
 }
+function scrapeHealth() {
+    let url = "/calendar/scrape_health";
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.onload = function f() {
+        document.getElementById("main_article").innerHTML = xmlhttp.responseText;
+        document.getElementById("sub_article").innerHTML = "&nbsp;";
+        document.getElementById("garminconnectoutput").innerHTML = "done";
+    }
+    xmlhttp.open("GET", url, true);
+    xmlhttp.send(null);
+    document.getElementById("garminconnectoutput").innerHTML = "running";
+}
 function listEvents(calendar_name) {
     let url = `/calendar/list_events?calendar_name=${calendar_name}`;
     let xmlhttp = new XMLHttpRequest();
@@ -155,3 +229,57 @@ function calendarDisplay(gcal_id, display) {
     xmlhttp.send(data);
     document.getElementById("garminconnectoutput").innerHTML = "running";
 }
+function uploadAttachment(gcal_id, event_id) {
+    let file = document.getElementById("attachment_file").files[0];
+    if (!file) {
+        return;
+    }
+    let reader = new FileReader();
+    reader.onload = function f() {
+        let content = reader.result.split(',')[1];
+        let url = "/calendar/attachments";
+        let data = JSON.stringify({
+            'gcal_id': gcal_id,
+            'event_id': event_id,
+            'filename': file.name,
+            'content_type': file.type,
+            'content': content,
+        });
+        let xmlhttp = new XMLHttpRequest();
+        xmlhttp.onload = function f() {
+            document.getElementById("garminconnectoutput").innerHTML = "done";
+            eventDetail(gcal_id, event_id);
+        }
+        xmlhttp.open("POST", url, true);
+        xmlhttp.setRequestHeader('Content-Type', 'application/json');
+        xmlhttp.send(data);
+    }
+    reader.readAsDataURL(file);
+    document.getElementById("garminconnectoutput").innerHTML = "running";
+}
+function deleteAttachment(attachment_id, gcal_id, event_id) {
+    let url = "/calendar/attachments";
+    let data = JSON.stringify({'attachment_id': attachment_id});
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.onload = function f() {
+        document.getElementById("garminconnectoutput").innerHTML = "done";
+        eventDetail(gcal_id, event_id);
+    }
+    xmlhttp.open("DELETE", url, true);
+    xmlhttp.setRequestHeader('Content-Type', 'application/json');
+    xmlhttp.send(data);
+    document.getElementById("garminconnectoutput").innerHTML = "running";
+}
+function shareLink(path) {
+    let url = "/calendar/share_link";
+    let data = JSON.stringify({'path': path});
+    let xmlhttp = new XMLHttpRequest();
+    xmlhttp.onload = function f() {
+        document.getElementById("sub_article").innerHTML = xmlhttp.responseText;
+        document.getElementById("garminconnectoutput").innerHTML = "done";
+    }
+    xmlhttp.open("POST", url, true);
+    xmlhttp.setRequestHeader('Content-Type', 'application/json');
+    xmlhttp.send(data);
+    document.getElementById("garminconnectoutput").innerHTML = "running";
+}